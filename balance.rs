@@ -0,0 +1,163 @@
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use crate::scoring::ScoringStrategy;
+use crate::server::VpnServer;
+use crate::{Result, VpnError};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct LoadBalancingConfig {
+    pub enabled: bool,
+    pub score_band: f64,  // Points below the best score still worth picking
+}
+
+impl Default for LoadBalancingConfig {
+    fn default() -> Self {
+        Self { enabled: true, score_band: 5.0 }
+    }
+}
+
+impl LoadBalancingConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.score_band.is_finite() || !(0.0..=100.0).contains(&self.score_band) {
+            return Err(VpnError::ConfigError("Load balancing score band must be 0-100".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Spreads clients over servers that score about as well as the best one,
+/// so a burst of quick connects does not pile onto a single server.
+///
+/// Two candidates within the score band are drawn at random, weighted by
+/// how close they are to the best score, and the less loaded one wins
+/// (power of two choices).
+#[derive(Debug)]
+pub struct LoadBalancer {
+    score_band: f64,  // 0 = always the best server
+    rng: Mutex<StdRng>,
+}
+
+impl Default for LoadBalancer {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+impl LoadBalancer {
+    pub fn new(score_band: f64) -> Self {
+        Self {
+            score_band: score_band.max(0.0),
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+
+    pub fn from_config(config: &LoadBalancingConfig) -> Self {
+        Self::new(if config.enabled { config.score_band } else { 0.0 })
+    }
+
+    /// Same picks for the same seed and inputs
+    pub fn with_seed(self, seed: u64) -> Self {
+        Self { rng: Mutex::new(StdRng::seed_from_u64(seed)), ..self }
+    }
+
+    pub fn score_band(&self) -> f64 {
+        self.score_band
+    }
+
+    /// Picks from `ranked`, which must be best first
+    pub fn choose<'a>(&self, ranked: &[&'a VpnServer], scoring: &dyn ScoringStrategy) -> Option<&'a VpnServer> {
+        let best = *ranked.first()?;
+        if self.score_band <= 0.0 {
+            return Some(best);
+        }
+
+        // Servers on hold only compete with each other, and only when
+        // nothing else matched
+        let floor = scoring.score(best) - self.score_band;
+        let candidates: Vec<(&VpnServer, f64)> = ranked.iter()
+            .take_while(|s| s.is_backing_off() == best.is_backing_off())
+            .map(|s| (*s, scoring.score(s) - floor))
+            .filter(|(_, weight)| *weight > 0.0)
+            .collect();
+        if candidates.len() < 2 {
+            return Some(best);
+        }
+
+        let mut weights: Vec<f64> = candidates.iter().map(|(_, weight)| *weight).collect();
+        let mut rng = self.rng.lock().unwrap_or_else(|e| e.into_inner());
+        let first = WeightedIndex::new(&weights).ok()?.sample(&mut *rng);
+        weights[first] = 0.0;
+        let second = WeightedIndex::new(&weights).ok()?.sample(&mut *rng);
+
+        // Less loaded wins; on a tie the better ranked one
+        let (a, b) = (first.min(second), first.max(second));
+        Some(if candidates[b].0.load < candidates[a].0.load { candidates[b].0 } else { candidates[a].0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::ServerQuery;
+    use crate::scoring::ScoringProfile;
+    use crate::server::ServerManager;
+    use std::collections::HashMap;
+
+    fn picks(manager: &ServerManager, seed: u64) -> Vec<String> {
+        let balancer = LoadBalancer::new(5.0).with_seed(seed);
+        let query = ServerQuery::new();
+        let ranked = manager.query(&query).servers;
+        (0..200)
+            .map(|_| balancer.choose(&ranked, &ScoringProfile::Balanced).unwrap().id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_spreads_within_band() {
+        let manager = ServerManager::new();
+        let best = manager.get_fastest_server(ScoringProfile::Balanced).unwrap();
+        let best_score = best.score();
+
+        let picked = picks(&manager, 7);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for id in &picked {
+            *counts.entry(id).or_default() += 1;
+        }
+        assert!(counts.len() > 1);
+        for server in manager.get_all_servers() {
+            if counts.contains_key(server.id.as_str()) {
+                assert!(server.score() > best_score - 5.0);
+            }
+        }
+
+        assert_eq!(picked, picks(&manager, 7));
+    }
+
+    #[test]
+    fn test_less_loaded_of_two_wins() {
+        let manager = ServerManager::new();
+        let mut busy = manager.get_all_servers()[0].clone();
+        busy.id = "busy".to_string();
+        busy.load = 60;
+        busy.latency = 10;
+        let mut quiet = busy.clone();
+        quiet.id = "quiet".to_string();
+        quiet.load = 50;
+        quiet.latency = 50;
+
+        // Busy scores higher, but two candidates always means both are drawn
+        let scoring = ScoringProfile::Balanced;
+        assert!(scoring.score(&busy) > scoring.score(&quiet));
+        let balancer = LoadBalancer::new(20.0).with_seed(1);
+        for _ in 0..20 {
+            assert_eq!(balancer.choose(&[&busy, &quiet], &scoring).unwrap().id, "quiet");
+        }
+
+        assert_eq!(LoadBalancer::new(0.0).choose(&[&busy, &quiet], &scoring).unwrap().id, "busy");
+        assert!(LoadBalancer::new(5.0).choose(&[], &scoring).is_none());
+        assert!(LoadBalancingConfig { enabled: true, score_band: -1.0 }.validate().is_err());
+    }
+}
@@ -86,6 +86,7 @@ cp Cargo.toml "${PACKAGE_DIR}/docs/"
 echo "📋 Creating default configuration..."
 cat > "${PACKAGE_DIR}/config/default-config.json" << 'EOF'
 {
+  "schema_version": 2,
   "auto_connect": false,
   "auto_connect_server": null,
   "start_on_boot": false,
@@ -95,7 +96,12 @@ cat > "${PACKAGE_DIR}/config/default-config.json" << 'EOF'
     "port": 51820,
     "use_tcp": false,
     "obfuscation": false,
-    "mtu": 1420
+    "mtu": 1420,
+    "port_hopping": {
+      "enabled": false,
+      "seed": "",
+      "interval_secs": 30
+    }
   },
   "kill_switch": {
     "mode": "Automatic",
@@ -106,16 +112,34 @@ cat > "${PACKAGE_DIR}/config/default-config.json" << 'EOF'
   "dns_mode": "Auto",
   "ipv6_leak_protection": true,
   "webrtc_leak_protection": true,
+  "security_policy": {
+    "min_security_level": 7,
+    "allowed_protocols": [],
+    "banned_protocols": []
+  },
   "split_tunnel": {
     "mode": "Disabled",
     "apps": [],
     "domains": [],
     "ip_ranges": []
   },
+  "multi_hop": {
+    "enabled": false,
+    "entry_country": null,
+    "exit_country": null
+  },
+  "scoring": "Balanced",
+  "load_balancing": {
+    "enabled": true,
+    "score_band": 5.0
+  },
   "mtu": 1420,
   "connect_timeout": 30,
   "reconnect_on_disconnect": true,
   "reconnect_attempts": 3,
+  "server_manifest_url": null,
+  "location": null,
+  "geoip_database": null,
   "block_trackers": false,
   "block_ads": false,
   "block_malware": true,
@@ -1,13 +1,33 @@
 use serde::{Deserialize, Serialize};
-use crate::protocol::{VpnProtocol, ProtocolConfig};
-use crate::killswitch::KillSwitchConfig;
-use crate::split_tunnel::SplitTunnelConfig;
+use crate::protocol::{VpnProtocol, ProtocolConfig, SecurityPolicy};
+use crate::killswitch::{KillSwitchConfig, KillSwitchMode};
+use crate::split_tunnel::{SplitTunnelConfig, SplitTunnelMode};
+use crate::datacap::DataCapConfig;
 use crate::dns::DnsMode;
+use crate::entitlements::Feature;
+use crate::net::IpNetwork;
+use crate::multihop::MultiHopConfig;
+use crate::geo::{GeoIpDatabase, GeoPoint};
+use crate::balance::LoadBalancingConfig;
+use crate::format::ConfigFormat;
+use crate::migration::{self, CURRENT_SCHEMA_VERSION};
+use crate::scoring::ScoringProfile;
+use crate::storage::{load_newest_backup, write_atomic, write_with_backups};
+use crate::validation::ValidationReport;
+use std::net::IpAddr;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+/// Earlier versions of a settings file kept next to it
+pub const CONFIG_BACKUPS: usize = 5;
+
+/// Fields missing from the file take their default value; renamed or
+/// reshaped fields are upgraded by `migration` on load.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct VpnConfig {
+    pub schema_version: u32,
+    
     // General settings
     pub auto_connect: bool,
     pub auto_connect_server: Option<String>,
@@ -22,13 +42,31 @@ pub struct VpnConfig {
     pub dns_mode: DnsMode,
     pub ipv6_leak_protection: bool,
     pub webrtc_leak_protection: bool,
+    #[serde(default)]
+    pub security_policy: SecurityPolicy,
     
     // Advanced settings
     pub split_tunnel: SplitTunnelConfig,
+    #[serde(default)]
+    pub multi_hop: MultiHopConfig,
+    #[serde(default)]
+    pub scoring: ScoringProfile,  // What "best server" means
+    #[serde(default)]
+    pub load_balancing: LoadBalancingConfig,
+    #[serde(default)]
+    pub data_cap: DataCapConfig,  // Only applies to plans with a data limit
     pub mtu: u16,
     pub connect_timeout: u32,  // seconds
     pub reconnect_on_disconnect: bool,
     pub reconnect_attempts: u32,
+    #[serde(default)]
+    pub server_manifest_url: Option<String>,  // None = bundled catalog only
+    #[serde(default)]
+    pub location: Option<GeoPoint>,           // User-supplied, wins over GeoIP
+    #[serde(default)]
+    pub geoip_database: Option<String>,       // Path to an offline GeoIP table
+    #[serde(default)]
+    pub public_ip_lookup: bool,               // Opt-in: ask a web service for the IP to look up
     
     // Privacy settings
     pub block_trackers: bool,
@@ -46,6 +84,8 @@ pub struct VpnConfig {
 impl Default for VpnConfig {
     fn default() -> Self {
         Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            
             // General
             auto_connect: false,
             auto_connect_server: None,
@@ -60,13 +100,22 @@ impl Default for VpnConfig {
             dns_mode: DnsMode::Auto,
             ipv6_leak_protection: true,
             webrtc_leak_protection: true,
+            security_policy: SecurityPolicy::default(),
             
             // Advanced
             split_tunnel: SplitTunnelConfig::default(),
+            multi_hop: MultiHopConfig::default(),
+            scoring: ScoringProfile::Balanced,
+            load_balancing: LoadBalancingConfig::default(),
+            data_cap: DataCapConfig::default(),
             mtu: 1420,
             connect_timeout: 30,
             reconnect_on_disconnect: true,
             reconnect_attempts: 3,
+            server_manifest_url: None,
+            location: None,
+            geoip_database: None,
+            public_ip_lookup: false,
             
             // Privacy
             block_trackers: false,
@@ -84,29 +133,108 @@ impl Default for VpnConfig {
 }
 
 impl VpnConfig {
+    /// Loads and validates the config, upgrading files from older versions.
+    /// An upgraded file is rewritten in the current format and the original
+    /// is kept next to it as `<name>.v<version>.bak`.
+    ///
+    /// JSON, TOML and YAML are read alike; see `ConfigFormat::detect`.
+    ///
+    /// If the file exists but cannot be read or parsed, the newest backup
+    /// that can is loaded instead and the broken file is left for
+    /// inspection. A file from a newer version or with invalid settings is
+    /// reported as it is.
     pub fn load_from_file(path: &PathBuf) -> crate::Result<Self> {
+        let (value, contents) = match Self::parse_file(path) {
+            Ok(parsed) => parsed,
+            Err(e) if path.exists() => return Self::load_backup(path, &e).ok_or(e),
+            Err(e) => return Err(e),
+        };
+        let (config, from_version) = Self::from_value(value)?;
+        if from_version < CURRENT_SCHEMA_VERSION {
+            write_atomic(&Self::backup_path(path, from_version), contents.as_bytes())
+                .map_err(|e| crate::VpnError::ConfigError(format!("Failed to back up config: {}", e)))?;
+            config.save_to_file(path)?;
+        }
+        
+        Ok(config)
+    }
+
+    fn load_backup(path: &Path, error: &crate::VpnError) -> Option<Self> {
+        load_newest_backup(path, CONFIG_BACKUPS, error, |backup, contents| {
+            let value = ConfigFormat::detect(backup, contents).parse(contents)?;
+            Self::from_value(value).map(|(config, _)| config)
+        })
+    }
+
+    // The parsed, upgraded and validated config, the file as read and the
+    // schema version it was written with
+    fn read_file(path: &Path) -> crate::Result<(Self, String, u32)> {
+        let (value, contents) = Self::parse_file(path)?;
+        let (config, from_version) = Self::from_value(value)?;
+        Ok((config, contents, from_version))
+    }
+
+    // The file's contents and the settings in it, not yet upgraded
+    fn parse_file(path: &Path) -> crate::Result<(serde_json::Value, String)> {
         let contents = fs::read_to_string(path)
             .map_err(|e| crate::VpnError::ConfigError(format!("Failed to read config: {}", e)))?;
+        let value = ConfigFormat::detect(path, &contents).parse(&contents)?;
+        Ok((value, contents))
+    }
+
+    // Upgrades and validates parsed settings, returning the schema version
+    // they were written with
+    fn from_value(mut value: serde_json::Value) -> crate::Result<(Self, u32)> {
+        let from_version = migration::migrate(&mut value)?;
         
-        let config: VpnConfig = serde_json::from_str(&contents)
+        let config: VpnConfig = serde_json::from_value(value)
             .map_err(|e| crate::VpnError::ConfigError(format!("Failed to parse config: {}", e)))?;
         
-        Ok(config)
+        let report = config.check();
+        if !report.is_valid() {
+            return Err(crate::VpnError::ConfigError(format!("Invalid config:\n{}", report)));
+        }
+        for warning in report.warnings() {
+            log::warn!("Config {}", warning);
+        }
+        Ok((config, from_version))
     }
 
+    /// Where the pre-migration copy of a version `version` config is kept
+    pub fn backup_path(path: &Path, version: u32) -> PathBuf {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("config.json");
+        path.with_file_name(format!("{}.v{}.bak", name, version))
+    }
+
+    /// Writes in the format of the extension, else that of the file being
+    /// replaced, else JSON. Comments in a TOML file are kept.
+    ///
+    /// The file is replaced atomically, readable by its owner only, and the
+    /// last `CONFIG_BACKUPS` versions are kept as `<name>.bak.<n>`.
     pub fn save_to_file(&self, path: &PathBuf) -> crate::Result<()> {
-        let json = serde_json::to_string_pretty(self)
+        let previous = fs::read_to_string(path).ok();
+        let format = Self::save_format(path, previous.as_deref());
+        let value = serde_json::to_value(self)
             .map_err(|e| crate::VpnError::ConfigError(format!("Failed to serialize config: {}", e)))?;
+        let rendered = format.render(&value, previous.as_deref())?;
         
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)
-                .map_err(|e| crate::VpnError::ConfigError(format!("Failed to create config directory: {}", e)))?;
-        }
-        
-        fs::write(path, json)
-            .map_err(|e| crate::VpnError::ConfigError(format!("Failed to write config: {}", e)))?;
-        
-        Ok(())
+        write_with_backups(path, rendered.as_bytes(), CONFIG_BACKUPS)
+            .map_err(|e| crate::VpnError::ConfigError(format!("Failed to write config: {}", e)))
+    }
+
+    /// Rewrites the config at `from` as `to`, in the format `to` asks for.
+    /// `from` is left as it is, even if it was written by an older version.
+    pub fn convert(from: &Path, to: &Path) -> crate::Result<ConfigFormat> {
+        let (config, _, _) = Self::read_file(from)?;
+        let format = Self::save_format(to, fs::read_to_string(to).ok().as_deref());
+        config.save_to_file(&to.to_path_buf())?;
+        Ok(format)
+    }
+
+    fn save_format(path: &Path, previous: Option<&str>) -> ConfigFormat {
+        ConfigFormat::from_extension(path)
+            .or_else(|| previous.map(ConfigFormat::sniff))
+            .unwrap_or(ConfigFormat::Json)
     }
 
     pub fn get_config_path() -> PathBuf {
@@ -145,6 +273,74 @@ impl VpnConfig {
         }
     }
 
+    /// Machine-wide settings an administrator can push to every user. Sits
+    /// below the user's config.json; see `layers::ConfigLoader`.
+    pub fn get_system_config_path() -> Option<PathBuf> {
+        #[cfg(target_os = "linux")]
+        {
+            Some(PathBuf::from("/etc/vpn-mobile/config.json"))
+        }
+        
+        #[cfg(target_os = "macos")]
+        {
+            Some(PathBuf::from("/Library/Application Support/VPN Mobile/config.json"))
+        }
+        
+        #[cfg(target_os = "windows")]
+        {
+            let data = std::env::var("PROGRAMDATA").unwrap_or_else(|_| "C:\\ProgramData".to_string());
+            Some(PathBuf::from(data).join("VPN Mobile\\config.json"))
+        }
+        
+        // Mobile apps are sandboxed and have no machine-wide settings
+        #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+        {
+            None
+        }
+    }
+
+    /// Managed policy, next to the system-wide config
+    pub fn get_policy_path() -> Option<PathBuf> {
+        Self::get_system_config_path().map(|path| path.with_file_name("policy.json"))
+    }
+
+    /// Last-known-good server manifest, next to the config file
+    pub fn get_manifest_cache_path() -> PathBuf {
+        Self::get_config_path().with_file_name("servers.manifest.json")
+    }
+
+    /// The user's location: the configured one, else a lookup of `ip` in
+    /// the offline GeoIP database
+    pub fn locate(&self, ip: Option<IpAddr>) -> crate::Result<Option<GeoPoint>> {
+        if self.location.is_some() {
+            return Ok(self.location);
+        }
+        match (ip, &self.geoip_database) {
+            (Some(ip), Some(path)) => Ok(GeoIpDatabase::load(path.as_ref())?.lookup(ip)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Favorites and recent servers, next to the config file
+    pub fn get_saved_servers_path() -> PathBuf {
+        Self::get_config_path().with_file_name("saved-servers.json")
+    }
+
+    /// The signed-in account and its subscription
+    pub fn get_account_path() -> PathBuf {
+        Self::get_config_path().with_file_name("account.json")
+    }
+
+    /// Data used in the current billing period
+    pub fn get_data_usage_path() -> PathBuf {
+        Self::get_config_path().with_file_name("data-usage.json")
+    }
+
+    /// Named connection profiles, next to the config file
+    pub fn get_profiles_path() -> PathBuf {
+        Self::get_config_path().with_file_name("profiles.json")
+    }
+
     pub fn preset_maximum_security() -> Self {
         Self {
             protocol_config: ProtocolConfig::new(VpnProtocol::WireGuard)
@@ -160,6 +356,10 @@ impl VpnConfig {
             block_trackers: true,
             block_ads: true,
             block_malware: true,
+            multi_hop: MultiHopConfig {
+                enabled: true,
+                ..Default::default()
+            },
             ..Default::default()
         }
     }
@@ -173,6 +373,7 @@ impl VpnConfig {
             },
             reconnect_on_disconnect: true,
             reconnect_attempts: 5,
+            scoring: ScoringProfile::Streaming,
             ..Default::default()
         }
     }
@@ -189,24 +390,156 @@ impl VpnConfig {
                 mode: crate::split_tunnel::SplitTunnelMode::IncludeOnly,
                 ..Default::default()
             },
+            scoring: ScoringProfile::P2P,
             ..Default::default()
         }
     }
 
+    /// Errors joined into one message; see `check` for the full report
     pub fn validate(&self) -> Result<(), String> {
-        if self.connect_timeout < 5 {
-            return Err("Connect timeout must be at least 5 seconds".to_string());
+        let report = self.check();
+        if report.is_valid() {
+            return Ok(());
         }
+        let errors: Vec<String> = report.errors().map(|e| e.to_string()).collect();
+        Err(errors.join("; "))
+    }
+
+    /// Every error and warning in the config, with the JSON path of the
+    /// offending field
+    pub fn check(&self) -> ValidationReport {
+        let mut report = ValidationReport::new();
         
+        if self.connect_timeout < 5 {
+            report.error("connect_timeout", "out_of_range", "Connect timeout must be at least 5 seconds");
+        }
         if self.reconnect_attempts > 10 {
-            return Err("Reconnect attempts cannot exceed 10".to_string());
+            report.error("reconnect_attempts", "out_of_range", "Reconnect attempts cannot exceed 10");
         }
-        
         if self.mtu < 1280 || self.mtu > 1500 {
-            return Err("MTU must be between 1280 and 1500".to_string());
+            report.error("mtu", "out_of_range", "MTU must be between 1280 and 1500");
         }
         
-        Ok(())
+        self.check_protocol(&mut report);
+        self.check_dns(&mut report);
+        self.check_kill_switch(&mut report);
+        self.check_split_tunnel(&mut report);
+        
+        if let ScoringProfile::Custom(weights) = &self.scoring {
+            report.check("scoring.Custom", "invalid_weights", weights.validate());
+        }
+        report.check("load_balancing.score_band", "out_of_range", self.load_balancing.validate());
+        report.check("data_cap", "out_of_range", self.data_cap.validate());
+        if let Some(location) = &self.location {
+            report.check("location", "out_of_range", location.validate());
+        }
+        
+        report
+    }
+    
+    fn check_protocol(&self, report: &mut ValidationReport) {
+        let config = &self.protocol_config;
+        let protocol = config.protocol;
+        
+        report.check("protocol_config.protocol", "policy_violation", self.security_policy.check(protocol));
+        
+        if config.port == 0 {
+            report.error("protocol_config.port", "invalid_port", "Port 0 cannot be dialled");
+        } else if !protocol.default_port_ranges().iter().any(|r| r.contains(config.port)) {
+            report.warning(
+                "protocol_config.port",
+                "nonstandard_port",
+                format!("Port {} is not a standard {} port; servers that do not listen on it use their own", config.port, protocol.name()),
+            );
+        }
+        
+        if config.mtu < 1280 || config.mtu > 1500 {
+            report.error("protocol_config.mtu", "out_of_range", "MTU must be between 1280 and 1500");
+        } else if config.mtu != self.mtu {
+            report.warning("protocol_config.mtu", "mtu_mismatch", format!("Differs from the interface MTU ({})", self.mtu));
+        }
+        
+        // Only OpenVPN has a TCP mode; the others are UDP or IPsec only
+        if config.use_tcp && !matches!(protocol, VpnProtocol::OpenVPN) {
+            report.error("protocol_config.use_tcp", "unsupported_transport", format!("{} cannot run over TCP", protocol.name()));
+        }
+        // Obfuscation wraps the tunnel in TLS or random-looking UDP, which
+        // IPsec and PPTP's GRE cannot carry
+        if config.obfuscation && !matches!(protocol, VpnProtocol::OpenVPN | VpnProtocol::WireGuard) {
+            report.error("protocol_config.obfuscation", "unsupported_obfuscation", format!("{} does not support obfuscation", protocol.name()));
+        }
+        
+        report.check(
+            "protocol_config.port_hopping",
+            "invalid_port_hopping",
+            config.port_hopping.validate(protocol, config.use_tcp),
+        );
+        if config.port_hopping.enabled && config.obfuscation {
+            report.warning("protocol_config.port_hopping", "redundant_obfuscation", "Port hopping and obfuscation both change how traffic looks; one is usually enough");
+        }
+    }
+    
+    fn check_dns(&self, report: &mut ValidationReport) {
+        match &self.dns_mode {
+            DnsMode::Custom(server) => {
+                if server.primary.parse::<IpAddr>().is_err() {
+                    report.error("dns_mode.Custom.primary", "invalid_ip", format!("Not an IP address: {}", server.primary));
+                }
+                if let Some(secondary) = &server.secondary {
+                    if secondary.parse::<IpAddr>().is_err() {
+                        report.error("dns_mode.Custom.secondary", "invalid_ip", format!("Not an IP address: {}", secondary));
+                    }
+                }
+            }
+            DnsMode::System => {
+                report.warning("dns_mode", "dns_leak_risk", "System DNS sends queries outside the tunnel");
+            }
+            DnsMode::Auto => {}
+        }
+    }
+    
+    fn check_kill_switch(&self, report: &mut ValidationReport) {
+        for (idx, ip) in self.kill_switch.allowed_ips.iter().enumerate() {
+            if ip.parse::<IpNetwork>().is_err() {
+                report.error(format!("kill_switch.allowed_ips[{}]", idx), "invalid_cidr", format!("Not an IP or CIDR range: {}", ip));
+            }
+        }
+    }
+    
+    fn check_split_tunnel(&self, report: &mut ValidationReport) {
+        let split = &self.split_tunnel;
+        // Sets have no stable order, so entries are named rather than indexed
+        let mut ranges: Vec<&String> = split.ip_ranges.iter().collect();
+        ranges.sort();
+        for range in ranges {
+            if range.parse::<IpNetwork>().is_err() {
+                report.error("split_tunnel.ip_ranges", "invalid_cidr", format!("Not an IP or CIDR range: {}", range));
+            }
+        }
+        
+        let empty = split.apps.is_empty() && split.domains.is_empty() && split.ip_ranges.is_empty();
+        match split.mode {
+            SplitTunnelMode::Disabled => return,
+            _ if empty => report.warning("split_tunnel", "empty_split_tunnel", "Split tunneling is on but lists no apps, domains or ranges"),
+            _ => {}
+        }
+        
+        if self.kill_switch.mode != KillSwitchMode::Always {
+            return;
+        }
+        match split.mode {
+            SplitTunnelMode::IncludeOnly => report.warning(
+                "split_tunnel.mode",
+                "kill_switch_conflict",
+                "With the kill switch always on, apps outside the include list have no network at all",
+            ),
+            SplitTunnelMode::ExcludeOnly if split.apps.iter().any(|app| !self.kill_switch.allowed_apps.contains(app)) => report.warning(
+                "split_tunnel.apps",
+                "kill_switch_conflict",
+                "Excluded apps are blocked by the always-on kill switch unless also in kill_switch.allowed_apps",
+            ),
+            _ => {}
+        }
     }
 }
 
@@ -218,9 +551,12 @@ pub struct UserProfile {
     pub subscription_expires: Option<chrono::DateTime<chrono::Utc>>,
     pub max_devices: u32,
     pub data_limit: Option<u64>,  // bytes per month, None = unlimited
+    #[serde(default)]
+    pub active_sessions: u32,     // Connected on other devices when last synced
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+/// Ordered from least to most included
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub enum SubscriptionTier {
     Free,
     Premium,
@@ -244,13 +580,38 @@ impl SubscriptionTier {
         }
     }
 
-    pub fn has_feature(&self, feature: &str) -> bool {
+    /// Bytes per billing period, None = unlimited
+    pub fn data_limit(&self) -> Option<u64> {
         match self {
-            SubscriptionTier::Free => matches!(feature, "basic_vpn"),
-            SubscriptionTier::Premium => true,
-            SubscriptionTier::Business => true,
+            SubscriptionTier::Free => Some(10 * 1024 * 1024 * 1024), // 10 GB
+            SubscriptionTier::Premium | SubscriptionTier::Business => None,
         }
     }
+
+    pub fn has_feature(&self, feature: Feature) -> bool {
+        *self >= feature.required_tier()
+    }
+}
+
+impl UserProfile {
+    /// `None` when no account has been saved, i.e. the user is on Free
+    pub fn load(path: &Path) -> crate::Result<Option<Self>> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(crate::VpnError::ConfigError(format!("Failed to read account: {}", e))),
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| crate::VpnError::ConfigError(format!("Failed to parse account: {}", e)))
+    }
+
+    /// Day of the month a billing period starts: the renewal day, or the
+    /// 1st without a subscription. Capped at 28 so every month has one.
+    pub fn billing_day(&self) -> u32 {
+        use chrono::Datelike;
+        self.subscription_expires.map_or(1, |expires| expires.day().min(28))
+    }
 }
 
 impl Default for UserProfile {
@@ -261,7 +622,8 @@ impl Default for UserProfile {
             subscription_tier: SubscriptionTier::Free,
             subscription_expires: None,
             max_devices: 1,
-            data_limit: Some(10 * 1024 * 1024 * 1024), // 10 GB
+            data_limit: SubscriptionTier::Free.data_limit(),
+            active_sessions: 0,
         }
     }
 }
@@ -269,6 +631,7 @@ impl Default for UserProfile {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::backup_paths;
 
     #[test]
     fn test_default_config() {
@@ -299,4 +662,238 @@ mod tests {
         config.mtu = 1000;
         assert!(config.validate().is_err());
     }
+
+    #[test]
+    fn test_security_policy_validation() {
+        let mut config = VpnConfig::default();
+        config.protocol_config = ProtocolConfig::new(VpnProtocol::PPTP);
+        assert!(config.validate().is_err());
+        
+        config.security_policy.allow(VpnProtocol::PPTP);
+        assert!(config.validate().is_ok());
+        
+        config.security_policy = SecurityPolicy::enterprise();
+        assert!(config.validate().is_err());
+        
+        config.protocol_config = ProtocolConfig::new(VpnProtocol::L2TP);
+        config.security_policy.allow(VpnProtocol::L2TP);
+        assert!(config.validate().is_err());
+        
+        config.protocol_config = ProtocolConfig::new(VpnProtocol::IKEv2);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_load_rejects_policy_violation() {
+        let mut config = VpnConfig::default();
+        config.protocol_config = ProtocolConfig::new(VpnProtocol::PPTP);
+        
+        let path = std::env::temp_dir().join(format!("vpn-mobile-policy-{}.json", std::process::id()));
+        fs::write(&path, serde_json::to_string(&config).unwrap()).unwrap();
+        let result = VpnConfig::load_from_file(&path);
+        let _ = fs::remove_file(&path);
+        
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_check_reports_every_issue() {
+        let mut config = VpnConfig::default();
+        config.kill_switch.allowed_ips = vec!["10.0.0.0/8".into(), "192.168.1.1".into(), "10.0.0.300/8".into()];
+        config.split_tunnel.ip_ranges.insert("not-a-range".into());
+        config.dns_mode = DnsMode::Custom(crate::dns::DnsServer {
+            primary: "1.1.1".into(),
+            ..crate::dns::DnsServer::cloudflare()
+        });
+        config.protocol_config = ProtocolConfig::new(VpnProtocol::IKEv2).with_obfuscation(true);
+        config.protocol_config.port = 0;
+        config.mtu = 1000;
+        
+        let report = config.check();
+        let code = |path: &str| report.find(path).map(|issue| issue.code);
+        assert_eq!(code("kill_switch.allowed_ips[2]"), Some("invalid_cidr"));
+        assert_eq!(code("kill_switch.allowed_ips[1]"), None);
+        assert_eq!(code("split_tunnel.ip_ranges"), Some("invalid_cidr"));
+        assert_eq!(code("dns_mode.Custom.primary"), Some("invalid_ip"));
+        assert_eq!(code("protocol_config.obfuscation"), Some("unsupported_obfuscation"));
+        assert_eq!(code("protocol_config.port"), Some("invalid_port"));
+        assert_eq!(code("mtu"), Some("out_of_range"));
+        assert_eq!(report.errors().count(), 6);
+        
+        let message = config.validate().unwrap_err();
+        assert!(message.contains("kill_switch.allowed_ips[2]") && message.contains("mtu"));
+    }
+    
+    #[test]
+    fn test_contradictions_and_warnings() {
+        let mut config = VpnConfig::default();
+        config.kill_switch.mode = KillSwitchMode::Always;
+        config.split_tunnel.mode = SplitTunnelMode::IncludeOnly;
+        config.split_tunnel.apps.insert("qbittorrent".into());
+        let report = config.check();
+        assert_eq!(report.find("split_tunnel.mode").unwrap().code, "kill_switch_conflict");
+        assert_eq!(report.warnings().count(), 1);
+        assert!(report.is_valid(), "existing configs with this combination still load");
+        
+        // Excluding an app the kill switch blocks only warns
+        config.split_tunnel.mode = SplitTunnelMode::ExcludeOnly;
+        assert!(config.check().is_valid());
+        assert_eq!(config.check().warnings().count(), 1);
+        config.kill_switch.allowed_apps.push("qbittorrent".into());
+        assert!(config.check().issues().is_empty());
+        
+        config.dns_mode = DnsMode::System;
+        config.protocol_config.port = 8080;
+        let report = config.check();
+        assert!(report.is_valid());
+        assert_eq!(report.find("dns_mode").unwrap().code, "dns_leak_risk");
+        assert_eq!(report.find("protocol_config.port").unwrap().code, "nonstandard_port");
+    }
+    
+    // Frozen copies of configs written by earlier versions, oldest first
+    const FIXTURES: &[(&str, &str)] = &[
+        ("v1-initial", include_str!("fixtures/config/v1-initial.json")),
+        ("v1-default", include_str!("fixtures/config/v1-default.json")),
+        ("v1-country-names", include_str!("fixtures/config/v1-country-names.json")),
+        ("current", include_str!("default-config.json")),
+    ];
+    
+    #[test]
+    fn test_fixtures_from_every_version_load() {
+        let dir = std::env::temp_dir().join(format!("vpn-mobile-migrate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        
+        for (name, contents) in FIXTURES {
+            let path = dir.join(format!("{}.json", name));
+            fs::write(&path, contents).unwrap();
+            let version = migration::schema_version(&serde_json::from_str(contents).unwrap()).unwrap();
+            
+            let config = VpnConfig::load_from_file(&path).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(config.schema_version, CURRENT_SCHEMA_VERSION, "{}", name);
+            
+            let backup = VpnConfig::backup_path(&path, version);
+            if version < CURRENT_SCHEMA_VERSION {
+                // Original kept, file rewritten in the current format
+                assert_eq!(fs::read_to_string(&backup).unwrap(), *contents, "{}", name);
+                let rewritten = fs::read_to_string(&path).unwrap();
+                assert!(rewritten.contains("\"schema_version\": 2"), "{}", name);
+                fs::remove_file(&backup).unwrap();
+                
+                // Loading the upgraded file is a no-op
+                VpnConfig::load_from_file(&path).unwrap();
+                assert!(!backup.exists(), "{}", name);
+            } else {
+                assert!(!backup.exists(), "{}", name);
+            }
+        }
+        
+        let upgraded = VpnConfig::load_from_file(&dir.join("v1-country-names.json")).unwrap();
+        assert!(upgraded.auto_connect);
+        assert_eq!(upgraded.multi_hop.entry_country.unwrap().code(), "CH");
+        assert_eq!(upgraded.multi_hop.exit_country.unwrap().code(), "US");
+        assert!(!upgraded.notify_on_ip_change);
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn test_newer_schema_is_rejected() {
+        let mut config = serde_json::to_value(VpnConfig::default()).unwrap();
+        config["schema_version"] = (CURRENT_SCHEMA_VERSION + 1).into();
+        
+        let path = std::env::temp_dir().join(format!("vpn-mobile-future-{}.json", std::process::id()));
+        fs::write(&path, config.to_string()).unwrap();
+        let result = VpnConfig::load_from_file(&path);
+        let _ = fs::remove_file(&path);
+        
+        assert!(result.is_err());
+    }
+    
+    #[test]
+    fn test_formats_round_trip() {
+        let dir = std::env::temp_dir().join(format!("vpn-mobile-formats-{}", std::process::id()));
+        let mut config = VpnConfig::preset_maximum_security();
+        config.location = Some(GeoPoint::new(47.37, 8.54).unwrap());
+        config.kill_switch.allowed_ips = vec!["10.0.0.0/8".to_string()];
+        let json = dir.join("config.json");
+        config.save_to_file(&json).unwrap();
+        
+        let toml = dir.join("config.toml");
+        let yaml = dir.join("config.yaml");
+        let sniffed = dir.join("config");
+        assert_eq!(VpnConfig::convert(&json, &toml).unwrap(), ConfigFormat::Toml);
+        assert_eq!(VpnConfig::convert(&toml, &yaml).unwrap(), ConfigFormat::Yaml);
+        fs::copy(&toml, &sniffed).unwrap();
+        for path in [&toml, &yaml, &sniffed] {
+            let loaded = VpnConfig::load_from_file(path).unwrap();
+            assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&config).unwrap(), "{}", path.display());
+        }
+        
+        // Ops comments survive a load, change and save
+        let commented = format!("# Fleet defaults, see the runbook\n{}", fs::read_to_string(&toml).unwrap())
+            .replace("mtu = 1420", "mtu = 1420 # matches the tunnel");
+        fs::write(&toml, commented).unwrap();
+        let mut loaded = VpnConfig::load_from_file(&toml).unwrap();
+        loaded.mtu = 1380;
+        loaded.save_to_file(&toml).unwrap();
+        let saved = fs::read_to_string(&toml).unwrap();
+        assert!(saved.starts_with("# Fleet defaults, see the runbook\n"), "{}", saved);
+        assert!(saved.contains("mtu = 1380 # matches the tunnel"), "{}", saved);
+        
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn test_corrupt_config_falls_back_to_backup() {
+        let dir = std::env::temp_dir().join(format!("vpn-mobile-backups-{}", std::process::id()));
+        let path = dir.join("config.json");
+        let mut config = VpnConfig::default();
+        for mtu in [1400, 1380, 1360] {
+            config.mtu = mtu;
+            config.save_to_file(&path).unwrap();
+        }
+        assert_eq!(backup_paths(&path, CONFIG_BACKUPS).iter().filter(|b| b.exists()).count(), 2);
+        
+        // A write cut short
+        let saved = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+        assert_eq!(VpnConfig::load_from_file(&path).unwrap().mtu, 1380);
+        // The broken file is kept, not overwritten
+        assert_eq!(fs::read_to_string(&path).unwrap().len(), saved.len() / 2);
+        
+        // Invalid settings and newer versions are reported, not hidden
+        fs::write(&path, saved.replace("\"mtu\": 1360", "\"mtu\": 100")).unwrap();
+        assert!(VpnConfig::load_from_file(&path).unwrap_err().to_string().contains("mtu"));
+        fs::write(&path, saved.replace(&format!("\"schema_version\": {}", CURRENT_SCHEMA_VERSION), "\"schema_version\": 999")).unwrap();
+        assert!(VpnConfig::load_from_file(&path).is_err());
+        fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+        
+        // No usable backup: the original error
+        for backup in backup_paths(&path, CONFIG_BACKUPS) {
+            let _ = fs::remove_file(backup);
+        }
+        assert!(VpnConfig::load_from_file(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn test_locate_prefers_configured_location() {
+        let path = std::env::temp_dir().join(format!("vpn-mobile-geoip-{}.csv", std::process::id()));
+        fs::write(&path, "203.0.113.0/24,35.68,139.65\n").unwrap();
+        
+        let mut config = VpnConfig::default();
+        let ip = Some("203.0.113.7".parse().unwrap());
+        assert_eq!(config.locate(ip).unwrap(), None);
+        
+        config.geoip_database = Some(path.to_string_lossy().into_owned());
+        assert_eq!(config.locate(ip).unwrap().unwrap().latitude, 35.68);
+        assert_eq!(config.locate(None).unwrap(), None);
+        
+        config.location = Some(GeoPoint::new(47.37, 8.54).unwrap());
+        assert_eq!(config.locate(ip).unwrap(), config.location);
+        let _ = fs::remove_file(&path);
+        
+        config.location = Some(GeoPoint { latitude: 120.0, longitude: 0.0 });
+        assert!(config.validate().is_err());
+    }
 }
@@ -1,14 +1,55 @@
 use crate::{ConnectionInfo, ConnectionStatus, Result, VpnError, VpnServer, VpnStats};
-use crate::protocol::ProtocolConfig;
+use crate::datacap::DataCapTracker;
+use crate::entitlements::Entitlements;
+use crate::ikev2::{Ikev2Config, Ikev2Session};
+use crate::multihop::{OnionLayers, ServerChain};
+use crate::net::Ipv6Mode;
+use crate::ports::PortHopper;
+use crate::protocol::{ProtocolConfig, SecurityPolicy, VpnProtocol};
 use chrono::Utc;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 use std::time::Duration;
 
+enum Transport {
+    Simulated,
+    Ikev2(Box<Ikev2Session>),
+    MultiHop(OnionLayers),
+}
+
+impl Transport {
+    fn tunnel_address(&self) -> Option<String> {
+        match self {
+            Transport::Simulated | Transport::MultiHop(_) => None,
+            Transport::Ikev2(session) => session.tunnel_address().map(|ip| ip.to_string()),
+        }
+    }
+
+    fn tunnel_address_v6(&self, exit: &VpnServer) -> Option<String> {
+        match self {
+            // The responder decides whether we get an IPv6 address
+            Transport::Ikev2(session) => session.tunnel_address_v6().map(|ip| ip.to_string()),
+            Transport::Simulated | Transport::MultiHop(_) if exit.supports_ipv6 => Some(format!(
+                "fd00:8::{:x}:{:x}", rand::random::<u16>(), rand::random::<u16>()
+            )),
+            _ => None,
+        }
+    }
+}
+
 pub struct VpnConnection {
     info: Arc<RwLock<ConnectionInfo>>,
     stats: Arc<RwLock<VpnStats>>,
     protocol_config: ProtocolConfig,
+    security_policy: SecurityPolicy,
+    entitlements: Entitlements,
+    other_sessions: u32,  // The account's sessions on other devices
+    data_cap: Option<Arc<Mutex<DataCapTracker>>>,
+    ipv6_leak_protection: bool,
+    ikev2_config: Option<Ikev2Config>,
+    transport: Arc<Mutex<Option<Transport>>>,
+    port_hopper: Arc<RwLock<Option<PortHopper>>>,
 }
 
 impl VpnConnection {
@@ -22,6 +63,11 @@ impl VpnConnection {
                 bytes_received: 0,
                 duration: Duration::from_secs(0),
                 ip_address: None,
+                port: None,
+                ipv6_address: None,
+                ipv6_mode: Ipv6Mode::default(),
+                allowed_ips: Vec::new(),
+                chain: Vec::new(),
             })),
             stats: Arc::new(RwLock::new(VpnStats {
                 current_speed_up: 0.0,
@@ -32,40 +78,175 @@ impl VpnConnection {
                 packet_loss: 0.0,
             })),
             protocol_config,
+            security_policy: SecurityPolicy::default(),
+            entitlements: Entitlements::default(),
+            other_sessions: 0,
+            data_cap: None,
+            ipv6_leak_protection: true,
+            ikev2_config: None,
+            transport: Arc::new(Mutex::new(None)),
+            port_hopper: Arc::new(RwLock::new(None)),
         }
     }
 
     pub async fn connect(&self, server: VpnServer) -> Result<()> {
+        // Refuse protocols the security policy does not allow
+        self.security_policy.check(self.protocol_config.protocol)?;
+        self.entitlements.check_server(&server)?;
+        self.entitlements.check_devices(self.other_sessions)?;
+        self.check_data_cap().await?;
+        let (port, hopper) = self.select_ports(&server)?;
+
         // Update status to connecting
         {
             let mut info = self.info.write().await;
             info.status = ConnectionStatus::Connecting;
             info.server = Some(server.clone());
+            info.chain = vec![server.clone()];
+            info.port = Some(port);
         }
 
-        // Simulate connection process
-        log::info!("Connecting to {} using {:?}", server.name, self.protocol_config.protocol);
+        log::info!("Connecting to {}:{} using {:?}", server.name, port, self.protocol_config.protocol);
         
-        // In a real implementation, this would:
-        // 1. Establish network connection
-        // 2. Perform handshake
-        // 3. Set up encryption
-        // 4. Configure routing
-        
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        let transport = match self.establish_transport(&server, port).await {
+            Ok(transport) => transport,
+            Err(e) => {
+                let mut info = self.info.write().await;
+                info.status = ConnectionStatus::Error(e.to_string());
+                info.server = None;
+                info.port = None;
+                info.chain.clear();
+                return Err(e);
+            }
+        };
+        let tunnel_address = transport.tunnel_address();
+        let tunnel_address_v6 = transport.tunnel_address_v6(&server);
+        *self.transport.lock().await = Some(transport);
+        *self.port_hopper.write().await = hopper;
 
         // Update status to connected
         {
             let mut info = self.info.write().await;
             info.status = ConnectionStatus::Connected;
             info.connected_at = Some(Utc::now());
-            info.ip_address = Some(format!("10.8.{}.{}", rand::random::<u8>(), rand::random::<u8>()));
+            self.assign_addresses(&mut info, tunnel_address, tunnel_address_v6);
         }
 
         log::info!("Successfully connected to {}", server.name);
         Ok(())
     }
 
+    /// Connects through every server in the chain, each hop tunnelled
+    /// inside the previous one. The exit hop is reported as the server.
+    pub async fn connect_chain(&self, chain: ServerChain) -> Result<()> {
+        self.security_policy.check(self.protocol_config.protocol)?;
+        self.entitlements.check_chain(&chain)?;
+        self.entitlements.check_devices(self.other_sessions)?;
+        self.check_data_cap().await?;
+        if self.protocol_config.protocol == VpnProtocol::IKEv2 {
+            return Err(VpnError::ConnectionFailed(
+                "Multi-hop is not available over IKEv2".to_string()
+            ));
+        }
+        // Only the entry hop is reachable from outside, so it sets the ports
+        let (port, hopper) = self.select_ports(chain.entry())?;
+
+        {
+            let mut info = self.info.write().await;
+            info.status = ConnectionStatus::Connecting;
+            info.server = Some(chain.exit().clone());
+            info.chain = chain.hops().to_vec();
+            info.port = Some(port);
+        }
+
+        log::info!("Connecting via {} hops: {}", chain.len(), chain.describe());
+        for (idx, hop) in chain.hops().iter().enumerate() {
+            log::info!("Establishing hop {}/{} to {} using {:?}",
+                idx + 1, chain.len(), hop.name, self.protocol_config.protocol);
+            
+            // Simulate the handshake with this hop through the hops before it
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+        let transport = Transport::MultiHop(OnionLayers::new(chain.len()));
+        let tunnel_address_v6 = transport.tunnel_address_v6(chain.exit());
+        *self.transport.lock().await = Some(transport);
+        *self.port_hopper.write().await = hopper;
+
+        {
+            let mut info = self.info.write().await;
+            info.status = ConnectionStatus::Connected;
+            info.connected_at = Some(Utc::now());
+            self.assign_addresses(&mut info, None, tunnel_address_v6);
+        }
+
+        log::info!("Successfully connected to {} via {}", chain.exit().name, chain.entry().name);
+        Ok(())
+    }
+
+    async fn check_data_cap(&self) -> Result<()> {
+        match &self.data_cap {
+            Some(data_cap) => data_cap.lock().await.check(),
+            None => Ok(()),
+        }
+    }
+
+    // Dual-stack addressing. Without an IPv6 address in the tunnel, IPv6 is
+    // blackholed unless leak protection has been turned off.
+    fn assign_addresses(&self, info: &mut ConnectionInfo, ipv4: Option<String>, ipv6: Option<String>) {
+        info.ip_address = Some(ipv4.unwrap_or_else(|| {
+            format!("10.8.{}.{}", rand::random::<u8>(), rand::random::<u8>())
+        }));
+        info.ipv6_mode = Ipv6Mode::select(ipv6.is_some(), self.ipv6_leak_protection);
+        info.ipv6_address = ipv6;
+        info.allowed_ips = info.ipv6_mode.allowed_ips();
+
+        if info.ipv6_mode == Ipv6Mode::Blackhole {
+            log::info!("Server has no IPv6, blackholing IPv6 traffic");
+        }
+    }
+
+    fn select_ports(&self, server: &VpnServer) -> Result<(u16, Option<PortHopper>)> {
+        let config = &self.protocol_config;
+        config.port_hopping.validate(config.protocol, config.use_tcp)?;
+
+        let port = server.resolve_port(config);
+        if port != config.port {
+            log::info!("{} does not accept port {}, using {}", server.name, config.port, port);
+        }
+
+        let hopper = if config.port_hopping.enabled {
+            let ranges = server.hop_ranges(config);
+            log::info!("Port hopping every {}s across {} port range(s)",
+                config.port_hopping.interval_secs, ranges.len());
+            Some(PortHopper::new(&config.port_hopping, ranges)?)
+        } else {
+            None
+        };
+        Ok((port, hopper))
+    }
+
+    async fn establish_transport(&self, server: &VpnServer, port: u16) -> Result<Transport> {
+        match self.protocol_config.protocol {
+            VpnProtocol::IKEv2 => {
+                let config = self.ikev2_config.clone().ok_or_else(|| {
+                    VpnError::AuthenticationFailed("IKEv2 requires certificate or EAP credentials".to_string())
+                })?;
+                let remote = resolve(&server.host, port).await?;
+                Ok(Transport::Ikev2(Box::new(Ikev2Session::connect(remote, config).await?)))
+            }
+            _ => {
+                // Simulate connection process
+                // In a real implementation, this would:
+                // 1. Establish network connection
+                // 2. Perform handshake
+                // 3. Set up encryption
+                // 4. Configure routing
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                Ok(Transport::Simulated)
+            }
+        }
+    }
+
     pub async fn disconnect(&self) -> Result<()> {
         {
             let mut info = self.info.write().await;
@@ -77,8 +258,17 @@ impl VpnConnection {
 
         log::info!("Disconnecting from VPN");
         
-        // Simulate disconnection
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        match self.transport.lock().await.take() {
+            Some(Transport::Ikev2(session)) => {
+                if let Err(e) = session.close().await {
+                    log::warn!("Failed to delete IKEv2 SA: {}", e);
+                }
+            }
+            _ => {
+                // Simulate disconnection
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
 
         {
             let mut info = self.info.write().await;
@@ -86,16 +276,21 @@ impl VpnConnection {
             info.server = None;
             info.connected_at = None;
             info.ip_address = None;
+            info.port = None;
+            info.ipv6_address = None;
+            info.allowed_ips.clear();
+            info.chain.clear();
         }
+        *self.port_hopper.write().await = None;
 
         log::info!("Disconnected successfully");
         Ok(())
     }
 
     pub async fn reconnect(&self) -> Result<()> {
-        let server = {
+        let (server, chain) = {
             let info = self.info.read().await;
-            info.server.clone()
+            (info.server.clone(), info.chain.clone())
         };
 
         if let Some(server) = server {
@@ -107,13 +302,54 @@ impl VpnConnection {
             log::info!("Reconnecting to VPN");
             self.disconnect().await?;
             tokio::time::sleep(Duration::from_millis(500)).await;
-            self.connect(server).await?;
+            if chain.len() > 1 {
+                self.connect_chain(ServerChain::new(chain)?).await?;
+            } else {
+                self.connect(server).await?;
+            }
             Ok(())
         } else {
             Err(VpnError::ConnectionFailed("No server to reconnect to".to_string()))
         }
     }
 
+    /// Moves the tunnel to a new local address after a network change.
+    /// IKEv2 keeps its SAs through MOBIKE; other transports need a reconnect.
+    pub async fn roam(&self, local: SocketAddr) -> Result<()> {
+        let mut transport = self.transport.lock().await;
+        match transport.as_mut() {
+            Some(Transport::Ikev2(session)) => session.update_addresses(local).await,
+            Some(Transport::Simulated) | Some(Transport::MultiHop(_)) => Ok(()),
+            None => Err(VpnError::ConnectionFailed("Not connected".to_string())),
+        }
+    }
+
+    pub async fn send_packet(&self, packet: &[u8]) -> Result<()> {
+        let mut transport = self.transport.lock().await;
+        match transport.as_mut() {
+            Some(Transport::Ikev2(session)) => session.send_packet(packet).await,
+            // The hops are simulated; there is nowhere to send the onion
+            Some(Transport::MultiHop(layers)) => Err(VpnError::NetworkError(format!(
+                "Simulated {}-hop transport does not carry packets", layers.len()
+            ))),
+            Some(Transport::Simulated) => Ok(()),
+            None => Err(VpnError::ConnectionFailed("Not connected".to_string())),
+        }
+    }
+
+    /// The transport is only locked long enough to take its receiving
+    /// half, so sending carries on while this waits
+    pub async fn recv_packet(&self) -> Result<Vec<u8>> {
+        let receiver = match self.transport.lock().await.as_ref() {
+            Some(Transport::Ikev2(session)) => session.receiver(),
+            Some(Transport::Simulated) | Some(Transport::MultiHop(_)) => return Err(VpnError::NetworkError(
+                "Simulated transport does not carry packets".to_string()
+            )),
+            None => return Err(VpnError::ConnectionFailed("Not connected".to_string())),
+        };
+        receiver.recv().await
+    }
+
     pub async fn get_info(&self) -> ConnectionInfo {
         let info = self.info.read().await;
         let mut info_clone = info.clone();
@@ -139,8 +375,23 @@ impl VpnConnection {
         // Simulate traffic
         stats.current_speed_up = rand::random::<f64>() * 10.0;
         stats.current_speed_down = rand::random::<f64>() * 50.0;
-        stats.total_upload += (stats.current_speed_up * 1024.0 * 1024.0) as u64;
-        stats.total_download += (stats.current_speed_down * 1024.0 * 1024.0) as u64;
+
+        // Counted against the data limit, and slowed once it is used up
+        let mut data_cap = match &self.data_cap {
+            Some(data_cap) => Some(data_cap.lock().await),
+            None => None,
+        };
+        if let Some(limit) = data_cap.as_ref().and_then(|d| d.speed_limit()) {
+            stats.current_speed_up = stats.current_speed_up.min(limit);
+            stats.current_speed_down = stats.current_speed_down.min(limit);
+        }
+        let sent = (stats.current_speed_up * 1024.0 * 1024.0) as u64;
+        let received = (stats.current_speed_down * 1024.0 * 1024.0) as u64;
+        stats.total_upload += sent;
+        stats.total_download += received;
+        if let Some(data_cap) = data_cap.as_mut() {
+            data_cap.record(sent + received);
+        }
         stats.latency = 20 + rand::random::<u32>() % 50;
         stats.packet_loss = rand::random::<f32>() * 0.5;
 
@@ -162,37 +413,107 @@ impl VpnConnection {
     pub fn set_protocol_config(&mut self, config: ProtocolConfig) {
         self.protocol_config = config;
     }
+
+    pub fn set_ikev2_config(&mut self, config: Ikev2Config) {
+        self.ikev2_config = Some(config);
+    }
+
+    pub fn get_security_policy(&self) -> &SecurityPolicy {
+        &self.security_policy
+    }
+
+    pub fn set_security_policy(&mut self, policy: SecurityPolicy) {
+        self.security_policy = policy;
+    }
+
+    pub fn get_entitlements(&self) -> &Entitlements {
+        &self.entitlements
+    }
+
+    /// What the subscription allows; connecting to anything else fails
+    pub fn set_entitlements(&mut self, entitlements: Entitlements) {
+        self.entitlements = entitlements;
+    }
+
+    /// Sessions the account has open elsewhere. Connecting fails once they
+    /// use up the plan's devices.
+    pub fn set_other_sessions(&mut self, sessions: u32) {
+        self.other_sessions = sessions;
+    }
+
+    pub fn get_data_cap(&self) -> Option<&Arc<Mutex<DataCapTracker>>> {
+        self.data_cap.as_ref()
+    }
+
+    /// Counts traffic against the tracker's limit, which may then refuse
+    /// to connect or throttle the tunnel
+    pub fn set_data_cap(&mut self, data_cap: Arc<Mutex<DataCapTracker>>) {
+        self.data_cap = Some(data_cap);
+    }
+
+    /// (source, destination) ports in use right now when port hopping
+    pub async fn current_ports(&self) -> Option<(u16, u16)> {
+        self.port_hopper.read().await.as_ref().map(|hopper| hopper.current_ports())
+    }
+
+    pub fn set_ipv6_leak_protection(&mut self, enabled: bool) {
+        self.ipv6_leak_protection = enabled;
+    }
+}
+
+pub(crate) async fn resolve(host: &str, port: u16) -> Result<SocketAddr> {
+    tokio::net::lookup_host((host, port)).await
+        .map_err(|e| VpnError::ServerUnavailable(format!("Failed to resolve {}: {}", host, e)))?
+        .next()
+        .ok_or_else(|| VpnError::ServerUnavailable(format!("No addresses found for {}", host)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::SubscriptionTier;
+    use crate::datacap::{DataCapAction, DataCapConfig};
+    use crate::ikev2::stub::{self, StubAuth};
+    use crate::ikev2::IkeAuth;
+    use crate::ports::{PortHoppingConfig, PortRange};
     use crate::server::{Country, ServerLocation};
+    use std::collections::HashMap;
+    use ed25519_dalek::SigningKey;
 
-    #[tokio::test]
-    async fn test_connection_lifecycle() {
-        let config = ProtocolConfig::default();
-        let connection = VpnConnection::new(config);
-
-        let server = VpnServer {
+    fn test_server() -> VpnServer {
+        VpnServer {
             id: "test-1".to_string(),
             name: "Test Server".to_string(),
             location: ServerLocation {
                 city: "Test City".to_string(),
-                country: Country::UnitedStates,
+                country: Country::from_alpha2("US").unwrap(),
                 latitude: 0.0,
                 longitude: 0.0,
             },
             host: "test.vpn.com".to_string(),
             port: 443,
+            port_ranges: HashMap::new(),
+            public_keys: HashMap::new(),
             load: 50,
             latency: 30,
             bandwidth: 1000,
             is_premium: false,
             supports_p2p: true,
             supports_streaming: true,
+            supports_ipv6: false,
             online: true,
-        };
+            former_ids: Vec::new(),
+            distance_km: None,
+            reliability: None,
+            backoff_until: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_lifecycle() {
+        let config = ProtocolConfig::default();
+        let connection = VpnConnection::new(config);
+        let server = test_server();
 
         // Test connection
         assert!(connection.connect(server).await.is_ok());
@@ -202,4 +523,166 @@ mod tests {
         assert!(connection.disconnect().await.is_ok());
         assert!(!connection.is_connected().await);
     }
+
+    #[tokio::test]
+    async fn test_data_cap_refuses_or_throttles() {
+        let mut connection = VpnConnection::new(ProtocolConfig::default());
+        let mut tracker = DataCapTracker::new(Some(1024), 1, DataCapConfig::default());
+        tracker.record(1024);
+        let data_cap = Arc::new(Mutex::new(tracker));
+        connection.set_data_cap(data_cap.clone());
+
+        let refused = connection.connect(test_server()).await;
+        assert!(matches!(refused, Err(VpnError::DataLimitReached(_))));
+        assert!(!connection.is_connected().await);
+
+        data_cap.lock().await.set_config(DataCapConfig { on_limit: DataCapAction::Throttle, ..DataCapConfig::default() });
+        connection.connect(test_server()).await.unwrap();
+        connection.update_stats().await;
+        let stats = connection.get_stats().await;
+        assert!(stats.current_speed_up <= 0.125 && stats.current_speed_down <= 0.125);
+        assert!(data_cap.lock().await.used() > 1024);
+    }
+
+    #[tokio::test]
+    async fn test_multi_hop_chain() {
+        let mut connection = VpnConnection::new(ProtocolConfig::default());
+
+        let mut exit = test_server();
+        exit.id = "test-2".to_string();
+        exit.location.country = Country::from_alpha2("SE").unwrap();
+        let chain = ServerChain::new(vec![test_server(), exit]).unwrap();
+
+        // Multi-hop is not part of the Free plan
+        let refused = connection.connect_chain(chain.clone()).await;
+        assert!(matches!(refused, Err(VpnError::UpgradeRequired(_))));
+        assert!(!connection.is_connected().await);
+
+        connection.set_entitlements(Entitlements::for_tier(SubscriptionTier::Premium));
+        connection.connect_chain(chain.clone()).await.unwrap();
+        let info = connection.get_info().await;
+        assert_eq!(info.chain.len(), 2);
+        assert_eq!(info.chain[0].id, "test-1");
+        assert_eq!(info.server.unwrap().id, "test-2");
+
+        connection.disconnect().await.unwrap();
+        assert!(connection.get_info().await.chain.is_empty());
+
+        // Every device the plan allows is already in use
+        connection.set_other_sessions(SubscriptionTier::Premium.max_devices());
+        let refused = connection.connect_chain(chain).await;
+        assert!(matches!(refused, Err(VpnError::UpgradeRequired(_))));
+        assert!(matches!(connection.connect(test_server()).await, Err(VpnError::UpgradeRequired(_))));
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_addressing() {
+        let mut connection = VpnConnection::new(ProtocolConfig::default());
+
+        let mut server = test_server();
+        server.supports_ipv6 = true;
+        connection.connect(server).await.unwrap();
+        let info = connection.get_info().await;
+        assert_eq!(info.ipv6_mode, Ipv6Mode::Tunnel);
+        assert!(info.ipv6_address.unwrap().starts_with("fd00:8::"));
+        assert!(info.allowed_ips.iter().any(|n| n.is_ipv6()));
+        connection.disconnect().await.unwrap();
+
+        // No IPv6 on the server: blackhole by default, leak only on request
+        connection.connect(test_server()).await.unwrap();
+        let info = connection.get_info().await;
+        assert_eq!(info.ipv6_mode, Ipv6Mode::Blackhole);
+        assert!(info.ipv6_address.is_none());
+        assert!(info.allowed_ips.iter().any(|n| n.is_ipv6()));
+        connection.disconnect().await.unwrap();
+
+        connection.set_ipv6_leak_protection(false);
+        connection.connect(test_server()).await.unwrap();
+        let info = connection.get_info().await;
+        assert_eq!(info.ipv6_mode, Ipv6Mode::Unprotected);
+        assert!(!info.allowed_ips.iter().any(|n| n.is_ipv6()));
+    }
+
+    #[tokio::test]
+    async fn test_port_selection_and_hopping() {
+        let mut server = test_server();
+        server.port_ranges.insert(VpnProtocol::WireGuard, vec![PortRange::single(53), PortRange::new(2000, 2999)]);
+
+        // The configured port is not accepted and neither is the default
+        let connection = VpnConnection::new(ProtocolConfig::default());
+        connection.connect(server.clone()).await.unwrap();
+        assert_eq!(connection.get_info().await.port, Some(53));
+        assert!(connection.current_ports().await.is_none());
+        connection.disconnect().await.unwrap();
+
+        let mut protocol_config = ProtocolConfig::default()
+            .with_port_hopping(PortHoppingConfig::generate(30));
+        protocol_config.port = 2500;
+        let connection = VpnConnection::new(protocol_config);
+        connection.connect(server).await.unwrap();
+        assert_eq!(connection.get_info().await.port, Some(2500));
+        let (_, dst) = connection.current_ports().await.unwrap();
+        assert!(dst == 53 || (2000..=2999).contains(&dst));
+        connection.disconnect().await.unwrap();
+
+        let connection = VpnConnection::new(
+            ProtocolConfig::new(VpnProtocol::OpenVPN).with_tcp(true).with_port_hopping(PortHoppingConfig::generate(30))
+        );
+        let result = connection.connect(test_server()).await;
+        assert!(matches!(result, Err(VpnError::ConfigError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_connect_refuses_insecure_protocol() {
+        let mut connection = VpnConnection::new(ProtocolConfig::new(VpnProtocol::PPTP));
+
+        let result = connection.connect(test_server()).await;
+        assert!(matches!(result, Err(VpnError::PolicyViolation(_))));
+        assert!(!connection.is_connected().await);
+
+        let mut policy = SecurityPolicy::enterprise();
+        policy.allow(VpnProtocol::L2TP);
+        connection.set_security_policy(policy);
+        connection.set_protocol_config(ProtocolConfig::new(VpnProtocol::L2TP));
+
+        let result = connection.connect(test_server()).await;
+        assert!(matches!(result, Err(VpnError::PolicyViolation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_ikev2_transport() {
+        let client_key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let stub = stub::spawn(StubAuth::Certificate { trusted: client_key.verifying_key() }).await;
+
+        let mut protocol_config = ProtocolConfig::new(VpnProtocol::IKEv2);
+        protocol_config.port = stub.ike_addr.port();
+        let mut connection = VpnConnection::new(protocol_config);
+
+        let mut server = test_server();
+        server.host = "127.0.0.1".to_string();
+
+        // Credentials are required before an IKEv2 connection can start
+        let result = connection.connect(server.clone()).await;
+        assert!(matches!(result, Err(VpnError::AuthenticationFailed(_))));
+
+        let auth = IkeAuth::Certificate { signing_key: client_key, responder_key: stub.key };
+        connection.set_ikev2_config(
+            Ikev2Config::new(auth, "client.vpn.test").with_natt_port(stub.natt_addr.port())
+        );
+        connection.connect(server).await.unwrap();
+        assert!(connection.is_connected().await);
+        let info = connection.get_info().await;
+        assert_eq!(info.ip_address.as_deref(), Some("10.10.0.2"));
+        assert_eq!(info.ipv6_address.as_deref(), Some("fd00:10::2"));
+        assert_eq!(info.ipv6_mode, Ipv6Mode::Tunnel);
+
+        connection.roam("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let packet = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 10, 10, 0, 2, 9, 9, 9, 9];
+        connection.send_packet(&packet).await.unwrap();
+        assert_eq!(connection.recv_packet().await.unwrap(), packet.to_vec());
+
+        connection.disconnect().await.unwrap();
+        assert!(!connection.is_connected().await);
+        assert!(stub.state.lock().await.deleted);
+    }
 }
@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use crate::VpnError;
+
+// One row per ISO 3166-1 country; adding a location only needs a new row
+const COUNTRY_DATA: &str = include_str!("countries.tsv");
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Continent {
+    Europe,
+    NorthAmerica,
+    SouthAmerica,
+    Asia,
+    Oceania,
+    Africa,
+    Antarctica,
+}
+
+impl Continent {
+    pub fn name(&self) -> &str {
+        match self {
+            Continent::Europe => "Europe",
+            Continent::NorthAmerica => "North America",
+            Continent::SouthAmerica => "South America",
+            Continent::Asia => "Asia",
+            Continent::Oceania => "Oceania",
+            Continent::Africa => "Africa",
+            Continent::Antarctica => "Antarctica",
+        }
+    }
+
+    pub fn all() -> Vec<Continent> {
+        vec![
+            Continent::Europe,
+            Continent::NorthAmerica,
+            Continent::SouthAmerica,
+            Continent::Asia,
+            Continent::Oceania,
+            Continent::Africa,
+            Continent::Antarctica,
+        ]
+    }
+}
+
+#[derive(Debug)]
+struct CountryInfo {
+    alpha2: String,
+    alpha3: String,
+    name: String,
+    continent: Continent,
+    aliases: Vec<String>,  // Other names people or older configs use
+}
+
+fn countries() -> &'static [CountryInfo] {
+    static TABLE: OnceLock<Vec<CountryInfo>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table: Vec<CountryInfo> = COUNTRY_DATA.lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                let continent = Continent::all()
+                    .into_iter()
+                    .find(|c| c.name().replace(' ', "") == fields[3])
+                    .unwrap_or_else(|| panic!("unknown continent in country table: {}", line));
+                CountryInfo {
+                    alpha2: fields[0].to_string(),
+                    alpha3: fields[1].to_string(),
+                    name: fields[2].to_string(),
+                    continent,
+                    aliases: fields.get(4)
+                        .map(|a| a.split(',').filter(|a| !a.is_empty()).map(str::to_string).collect())
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+        table.sort_by(|a, b| a.alpha2.cmp(&b.alpha2));
+        table
+    })
+}
+
+// Case, spaces and punctuation are ignored, so the enum names older
+// configs stored ("UnitedStates") match the display name ("United States")
+fn normalize(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+/// An ISO 3166-1 country. Serialized as its alpha-2 code; parses from
+/// alpha-2 or alpha-3 codes, names and the enum names of earlier versions.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Country(u16);  // Index into the country table
+
+impl Country {
+    pub fn from_alpha2(code: &str) -> Option<Self> {
+        countries()
+            .binary_search_by(|c| c.alpha2.as_str().cmp(&code.to_ascii_uppercase()))
+            .ok()
+            .map(|idx| Country(idx as u16))
+    }
+
+    pub fn from_alpha3(code: &str) -> Option<Self> {
+        countries()
+            .iter()
+            .position(|c| c.alpha3.eq_ignore_ascii_case(code))
+            .map(|idx| Country(idx as u16))
+    }
+
+    fn info(&self) -> &'static CountryInfo {
+        &countries()[self.0 as usize]
+    }
+
+    /// ISO 3166-1 alpha-2, e.g. "CH"
+    pub fn code(&self) -> &'static str {
+        &self.info().alpha2
+    }
+
+    pub fn alpha3(&self) -> &'static str {
+        &self.info().alpha3
+    }
+
+    pub fn name(&self) -> &'static str {
+        &self.info().name
+    }
+
+    pub fn continent(&self) -> Continent {
+        self.info().continent
+    }
+
+    /// The pair of regional indicator symbols for the alpha-2 code
+    pub fn flag_emoji(&self) -> String {
+        self.code()
+            .chars()
+            .filter_map(|c| char::from_u32(0x1F1E6 + (c as u32 - 'A' as u32)))
+            .collect()
+    }
+
+    /// Every ISO 3166-1 country, by alpha-2 code
+    pub fn all() -> Vec<Country> {
+        (0..countries().len() as u16).map(Country).collect()
+    }
+}
+
+impl FromStr for Country {
+    type Err = VpnError;
+
+    fn from_str(s: &str) -> crate::Result<Self> {
+        let s = s.trim();
+        let by_code = match s.len() {
+            2 => Self::from_alpha2(s),
+            3 => Self::from_alpha3(s),
+            _ => None,
+        };
+        if let Some(country) = by_code {
+            return Ok(country);
+        }
+
+        let wanted = normalize(s);
+        countries()
+            .iter()
+            .position(|c| {
+                normalize(&c.name) == wanted || c.aliases.iter().any(|a| normalize(a) == wanted)
+            })
+            .map(|idx| Country(idx as u16))
+            .ok_or_else(|| VpnError::ConfigError(format!("Unknown country: {}", s)))
+    }
+}
+
+impl TryFrom<String> for Country {
+    type Error = VpnError;
+
+    fn try_from(s: String) -> crate::Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Country> for String {
+    fn from(country: Country) -> Self {
+        country.code().to_string()
+    }
+}
+
+impl fmt::Debug for Country {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Country({})", self.code())
+    }
+}
+
+impl fmt::Display for Country {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_country_table() {
+        let all = Country::all();
+        assert_eq!(all.len(), 249);
+        for country in &all {
+            assert_eq!(country.code().len(), 2);
+            assert_eq!(country.alpha3().len(), 3);
+            assert_eq!(Country::from_alpha2(country.code()), Some(*country));
+        }
+
+        let swiss = Country::from_alpha2("ch").unwrap();
+        assert_eq!(swiss.name(), "Switzerland");
+        assert_eq!(swiss.alpha3(), "CHE");
+        assert_eq!(swiss.continent(), Continent::Europe);
+        assert_eq!(swiss.flag_emoji(), "🇨🇭");
+        assert_eq!(Country::from_alpha2("AR").unwrap().continent(), Continent::SouthAmerica);
+        assert!(Country::from_alpha2("XX").is_none());
+    }
+
+    #[test]
+    fn test_parse_codes_names_and_legacy() {
+        let us = Country::from_alpha2("US").unwrap();
+        for input in ["US", "usa", "United States", "united states", "UnitedStates", "America"] {
+            assert_eq!(input.parse::<Country>().unwrap(), us, "{}", input);
+        }
+        assert_eq!("SouthKorea".parse::<Country>().unwrap().code(), "KR");
+        assert_eq!("UnitedArabEmirates".parse::<Country>().unwrap().code(), "AE");
+        assert_eq!("Türkiye".parse::<Country>().unwrap().code(), "TR");
+        assert!("Atlantis".parse::<Country>().is_err());
+    }
+
+    #[test]
+    fn test_serde_compat() {
+        let japan: Country = serde_json::from_str("\"Japan\"").unwrap();
+        assert_eq!(serde_json::to_string(&japan).unwrap(), "\"JP\"");
+        assert_eq!(serde_json::from_str::<Country>("\"JP\"").unwrap(), japan);
+        assert!(serde_json::from_str::<Country>("\"Narnia\"").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+use chrono::{DateTime, Datelike, Months, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::sync::broadcast;
+use crate::analytics::Analytics;
+use crate::storage::write_atomic;
+use crate::{Result, VpnError};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum DataCapAction {
+    #[default]
+    Disconnect,  // And refuse to connect until the next period
+    Throttle,    // Stay connected at `throttle_speed`
+}
+
+/// What happens as the plan's monthly data runs out
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct DataCapConfig {
+    pub warn_at: Vec<u8>,      // Percentages of the limit that raise a warning
+    pub on_limit: DataCapAction,
+    pub throttle_speed: f64,   // MB/s once the limit is hit
+}
+
+impl Default for DataCapConfig {
+    fn default() -> Self {
+        Self {
+            warn_at: vec![80, 95],
+            on_limit: DataCapAction::Disconnect,
+            throttle_speed: 0.125,
+        }
+    }
+}
+
+impl DataCapConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.warn_at.iter().any(|percent| !(1..100).contains(percent)) {
+            return Err(VpnError::ConfigError("Data cap warnings must be at 1-99%".to_string()));
+        }
+        if !self.throttle_speed.is_finite() || self.throttle_speed <= 0.0 {
+            return Err(VpnError::ConfigError("Throttle speed must be above 0 MB/s".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Counters for one billing period
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DataUsage {
+    pub period_start: DateTime<Utc>,
+    pub used: u64,          // bytes, both directions
+    #[serde(default)]
+    pub warned: Vec<u8>,    // Thresholds already announced, 100 = the limit
+}
+
+impl DataUsage {
+    fn new(period_start: DateTime<Utc>) -> Self {
+        Self { period_start, used: 0, warned: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataCapEvent {
+    Warning { percent: u8, used: u64, limit: u64, resets: DateTime<Utc> },
+    LimitReached { action: DataCapAction, used: u64, limit: u64, resets: DateTime<Utc> },
+}
+
+/// Counts tunnel traffic against the plan's monthly data limit. Periods
+/// start on the billing day; the counters survive restarts once
+/// `persist_to` is set.
+pub struct DataCapTracker {
+    limit: Option<u64>,  // bytes per period, None = unlimited
+    billing_day: u32,    // 1-28, so every month has one
+    config: DataCapConfig,
+    usage: DataUsage,
+    path: Option<PathBuf>,
+    events: broadcast::Sender<DataCapEvent>,
+}
+
+impl DataCapTracker {
+    pub fn new(limit: Option<u64>, billing_day: u32, config: DataCapConfig) -> Self {
+        let billing_day = billing_day.clamp(1, 28);
+        Self {
+            limit,
+            billing_day,
+            config,
+            usage: DataUsage::new(period_start(Utc::now(), billing_day)),
+            path: None,
+            events: broadcast::channel(16).0,
+        }
+    }
+
+    /// Keeps the counters in `path` from now on, continuing from what is
+    /// saved there if it is for the current period
+    pub fn persist_to(&mut self, path: PathBuf) -> Result<()> {
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                self.usage = serde_json::from_str(&contents)
+                    .map_err(|e| VpnError::ConfigError(format!("Failed to parse data usage: {}", e)))?;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(VpnError::ConfigError(format!("Failed to read data usage: {}", e))),
+        }
+        self.path = Some(path);
+        self.roll_over(Utc::now());
+        self.save()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DataCapEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn set_config(&mut self, config: DataCapConfig) {
+        self.config = config;
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    pub fn used(&self) -> u64 {
+        self.usage.used
+    }
+
+    pub fn resets_at(&self) -> DateTime<Utc> {
+        next_period(self.usage.period_start)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.limit.is_some_and(|limit| self.usage.used >= limit)
+    }
+
+    /// Refuses to connect once the data is used up, unless the policy is
+    /// to throttle
+    pub fn check(&self) -> Result<()> {
+        if self.is_exhausted() && self.config.on_limit == DataCapAction::Disconnect {
+            return Err(VpnError::DataLimitReached(format!(
+                "{} used this month; it resets on {}, or upgrade for unlimited data",
+                self.describe(), self.resets_at().format("%Y-%m-%d")
+            )));
+        }
+        Ok(())
+    }
+
+    /// MB/s the tunnel may use right now, if limited
+    pub fn speed_limit(&self) -> Option<f64> {
+        (self.is_exhausted() && self.config.on_limit == DataCapAction::Throttle)
+            .then_some(self.config.throttle_speed)
+    }
+
+    /// e.g. "8.00 GB of 10.00 GB (80%)"
+    pub fn describe(&self) -> String {
+        match self.limit {
+            Some(limit) => format!("{} of {} ({}%)",
+                Analytics::format_bytes(self.usage.used), Analytics::format_bytes(limit), self.percent_used(limit)),
+            None => format!("{} (unlimited)", Analytics::format_bytes(self.usage.used)),
+        }
+    }
+
+    pub fn record(&mut self, bytes: u64) {
+        self.record_at(bytes, Utc::now());
+    }
+
+    fn record_at(&mut self, bytes: u64, now: DateTime<Utc>) {
+        let rolled = self.roll_over(now);
+        if bytes == 0 && !rolled {
+            return;
+        }
+        self.usage.used = self.usage.used.saturating_add(bytes);
+        self.announce();
+        if let Err(e) = self.save() {
+            log::warn!("{}", e);
+        }
+    }
+
+    // Starts a new period once the current one has ended
+    fn roll_over(&mut self, now: DateTime<Utc>) -> bool {
+        let start = period_start(now, self.billing_day);
+        if self.usage.period_start == start {
+            return false;
+        }
+        log::info!("New billing period from {}, {} used in the last", start, Analytics::format_bytes(self.usage.used));
+        self.usage = DataUsage::new(start);
+        true
+    }
+
+    // Each threshold once per period; crossing several at once only
+    // reports the highest
+    fn announce(&mut self) {
+        let Some(limit) = self.limit else { return };
+        let resets = self.resets_at();
+        let used = self.usage.used;
+
+        if used >= limit {
+            if !self.usage.warned.contains(&100) {
+                self.usage.warned.extend(self.config.warn_at.iter().copied().chain([100]));
+                let _ = self.events.send(DataCapEvent::LimitReached { action: self.config.on_limit, used, limit, resets });
+            }
+            return;
+        }
+
+        let percent = self.percent_used(limit);
+        let crossed: Vec<u8> = self.config.warn_at.iter()
+            .copied()
+            .filter(|threshold| percent >= *threshold && !self.usage.warned.contains(threshold))
+            .collect();
+        if let Some(highest) = crossed.iter().max().copied() {
+            self.usage.warned.extend(crossed);
+            let _ = self.events.send(DataCapEvent::Warning { percent: highest, used, limit, resets });
+        }
+    }
+
+    fn percent_used(&self, limit: u64) -> u8 {
+        (self.usage.used as f64 / limit.max(1) as f64 * 100.0).floor().min(100.0) as u8
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let json = serde_json::to_string_pretty(&self.usage)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to serialize data usage: {}", e)))?;
+        write_atomic(path, json.as_bytes())
+            .map_err(|e| VpnError::ConfigError(format!("Failed to save data usage: {}", e)))
+    }
+}
+
+// Midnight UTC on the most recent billing day
+fn period_start(now: DateTime<Utc>, billing_day: u32) -> DateTime<Utc> {
+    let this_month = Utc.with_ymd_and_hms(now.year(), now.month(), billing_day, 0, 0, 0).unwrap();
+    if now >= this_month {
+        this_month
+    } else {
+        this_month - Months::new(1)
+    }
+}
+
+fn next_period(start: DateTime<Utc>) -> DateTime<Utc> {
+    start + Months::new(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GB: u64 = 1024 * 1024 * 1024;
+
+    fn midnight(date: &str) -> DateTime<Utc> {
+        format!("{}T00:00:00Z", date).parse().unwrap()
+    }
+
+    #[test]
+    fn test_warns_then_enforces() {
+        let mut tracker = DataCapTracker::new(Some(10 * GB), 1, DataCapConfig::default());
+        let mut events = tracker.subscribe();
+        let now = Utc::now();
+
+        tracker.record_at(7 * GB, now);
+        assert!(events.try_recv().is_err());
+        tracker.record_at(GB + GB / 2, now);
+        assert!(matches!(events.try_recv(), Ok(DataCapEvent::Warning { percent: 80, .. })));
+        tracker.record_at(GB / 10, now);
+        assert!(events.try_recv().is_err(), "80% is only announced once");
+        tracker.record_at(GB, now);
+        assert!(matches!(events.try_recv(), Ok(DataCapEvent::Warning { percent: 95, .. })));
+        assert!(tracker.check().is_ok());
+        tracker.record_at(GB, now);
+        assert!(matches!(events.try_recv(), Ok(DataCapEvent::LimitReached { action: DataCapAction::Disconnect, .. })));
+
+        let error = tracker.check().unwrap_err();
+        assert!(matches!(error, VpnError::DataLimitReached(_)));
+        assert!(error.to_string().contains("resets on"), "{}", error);
+        assert_eq!(tracker.speed_limit(), None);
+
+        tracker.set_config(DataCapConfig { on_limit: DataCapAction::Throttle, ..DataCapConfig::default() });
+        assert!(tracker.check().is_ok());
+        assert_eq!(tracker.speed_limit(), Some(0.125));
+    }
+
+    #[test]
+    fn test_periods_and_persistence() {
+        let noon = midnight("2026-10-18") + chrono::Duration::hours(12);
+        assert_eq!(period_start(noon, 20), midnight("2026-09-20"));
+        assert_eq!(period_start(noon, 18), midnight("2026-10-18"));
+        assert_eq!(next_period(midnight("2026-01-28")), midnight("2026-02-28"));
+
+        let path = std::env::temp_dir().join(format!("vpn-data-usage-{}.json", rand::random::<u32>()));
+        let mut tracker = DataCapTracker::new(Some(10 * GB), 1, DataCapConfig::default());
+        tracker.persist_to(path.clone()).unwrap();
+        tracker.record(3 * GB);
+
+        let mut restarted = DataCapTracker::new(Some(10 * GB), 1, DataCapConfig::default());
+        restarted.persist_to(path.clone()).unwrap();
+        assert_eq!(restarted.used(), 3 * GB);
+
+        // A new month starts from zero
+        let next_month = restarted.resets_at() + chrono::Duration::hours(1);
+        restarted.record_at(GB, next_month);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(restarted.used(), GB);
+    }
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use crate::{Result, VpnError};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct DnsServer {
     pub name: String,
     pub primary: String,
@@ -68,7 +68,7 @@ impl DnsServer {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum DnsMode {
     Auto,              // Use VPN server's DNS
     Custom(DnsServer), // Use custom DNS servers
@@ -81,6 +81,7 @@ pub struct DnsManager {
     dns_filtering: bool,
     block_malware: bool,
     block_ads: bool,
+    locked_mode: Option<DnsMode>,  // Fixed by a managed policy
 }
 
 impl DnsManager {
@@ -91,19 +92,42 @@ impl DnsManager {
             dns_filtering: false,
             block_malware: true,
             block_ads: false,
+            locked_mode: None,
         }
     }
 
-    pub fn set_mode(&mut self, mode: DnsMode) {
+    /// Refused while a managed policy fixes another mode
+    pub fn set_mode(&mut self, mode: DnsMode) -> Result<()> {
+        if self.locked_mode.as_ref().is_some_and(|locked| *locked != mode) {
+            return Err(VpnError::PolicyViolation(
+                "The DNS mode is set by your organization's policy".to_string()
+            ));
+        }
         self.mode = mode;
+        Ok(())
+    }
+
+    /// Pins the mode, as required by a managed policy; None lifts the lock
+    pub fn set_locked_mode(&mut self, mode: Option<DnsMode>) {
+        if let Some(mode) = &mode {
+            self.mode = mode.clone();
+        }
+        self.locked_mode = mode;
     }
 
     pub fn get_mode(&self) -> &DnsMode {
         &self.mode
     }
 
-    pub fn enable_leak_protection(&mut self, enabled: bool) {
+    /// Cannot be turned off while a managed policy fixes the DNS mode
+    pub fn enable_leak_protection(&mut self, enabled: bool) -> Result<()> {
+        if !enabled && self.locked_mode.is_some() {
+            return Err(VpnError::PolicyViolation(
+                "DNS leak protection is required by your organization's policy".to_string()
+            ));
+        }
         self.leak_protection = enabled;
+        Ok(())
     }
 
     pub fn enable_dns_filtering(&mut self, enabled: bool) {
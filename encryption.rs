@@ -1,11 +1,13 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm, Nonce,
 };
 use sha2::{Sha256, Digest};
 use base64::{Engine as _, engine::general_purpose};
 use crate::{Result, VpnError};
 
+const NONCE_SIZE: usize = 12;
+
 pub struct EncryptionManager {
     cipher: Aes256Gcm,
 }
@@ -31,18 +33,26 @@ impl EncryptionManager {
     }
 
     pub fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
-        let nonce = Nonce::from_slice(b"unique nonce"); // In production, use random nonce
+        // Fresh random nonce per message, sent in front of the ciphertext
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
         
-        self.cipher
-            .encrypt(nonce, data)
-            .map_err(|e| VpnError::EncryptionError(format!("Encryption failed: {}", e)))
+        let ciphertext = self.cipher
+            .encrypt(&nonce, data)
+            .map_err(|e| VpnError::EncryptionError(format!("Encryption failed: {}", e)))?;
+        
+        let mut output = nonce.to_vec();
+        output.extend_from_slice(&ciphertext);
+        Ok(output)
     }
 
     pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<Vec<u8>> {
-        let nonce = Nonce::from_slice(b"unique nonce");
+        if encrypted_data.len() < NONCE_SIZE {
+            return Err(VpnError::EncryptionError("Ciphertext is too short".to_string()));
+        }
+        let (nonce, ciphertext) = encrypted_data.split_at(NONCE_SIZE);
         
         self.cipher
-            .decrypt(nonce, encrypted_data)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
             .map_err(|e| VpnError::EncryptionError(format!("Decryption failed: {}", e)))
     }
 
@@ -88,7 +98,15 @@ impl EncryptionLevel {
             EncryptionLevel::None => "No encryption (not recommended)",
             EncryptionLevel::Standard => "AES-128 encryption",
             EncryptionLevel::Strong => "AES-256 encryption (recommended)",
-            EncryptionLevel::Maximum => "AES-256 with double encryption",
+            EncryptionLevel::Maximum => "AES-256 with double encryption (multi-hop)",
+        }
+    }
+
+    /// Number of servers traffic must pass through, each adding a layer
+    pub fn min_hops(&self) -> usize {
+        match self {
+            EncryptionLevel::Maximum => 2,
+            _ => 1,
         }
     }
 
@@ -116,6 +134,9 @@ mod tests {
         let decrypted = manager.decrypt(&encrypted).unwrap();
         
         assert_eq!(data.to_vec(), decrypted);
+        
+        // Nonces are never reused, so equal plaintexts encrypt differently
+        assert_ne!(encrypted, manager.encrypt(data).unwrap());
     }
 
     #[test]
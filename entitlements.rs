@@ -0,0 +1,205 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::config::{SubscriptionTier, UserProfile};
+use crate::multihop::ServerChain;
+use crate::query::ServerQuery;
+use crate::server::VpnServer;
+use crate::{Result, VpnError};
+
+/// Parts of the service that depend on the subscription
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Feature {
+    PremiumServers,
+    P2P,
+    MultiHop,
+    SplitTunneling,
+}
+
+impl Feature {
+    pub fn all() -> Vec<Feature> {
+        vec![Feature::PremiumServers, Feature::P2P, Feature::MultiHop, Feature::SplitTunneling]
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Feature::PremiumServers => "Premium servers",
+            Feature::P2P => "P2P",
+            Feature::MultiHop => "Multi-hop",
+            Feature::SplitTunneling => "Split tunneling",
+        }
+    }
+
+    /// The lowest tier that includes the feature
+    pub fn required_tier(&self) -> SubscriptionTier {
+        match self {
+            Feature::PremiumServers
+            | Feature::P2P
+            | Feature::MultiHop
+            | Feature::SplitTunneling => SubscriptionTier::Premium,
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// What the user's subscription lets them do right now. An expired
+/// subscription counts as Free.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entitlements {
+    tier: SubscriptionTier,
+    max_devices: u32,
+    data_limit: Option<u64>,  // bytes per billing period, None = unlimited
+}
+
+impl Default for Entitlements {
+    fn default() -> Self {
+        Self::for_tier(SubscriptionTier::Free)
+    }
+}
+
+impl Entitlements {
+    pub fn for_tier(tier: SubscriptionTier) -> Self {
+        Self { tier, max_devices: tier.max_devices(), data_limit: tier.data_limit() }
+    }
+
+    pub fn for_profile(profile: &UserProfile) -> Self {
+        let expired = profile.subscription_expires.is_some_and(|expires| expires <= chrono::Utc::now());
+        if expired {
+            return Self::default();
+        }
+        // A profile cannot claim more devices or data than its plan includes
+        let mut entitlements = Self::for_tier(profile.subscription_tier);
+        entitlements.max_devices = profile.max_devices.min(entitlements.max_devices);
+        entitlements.data_limit = match (entitlements.data_limit, profile.data_limit) {
+            (Some(tier), Some(profile)) => Some(tier.min(profile)),
+            (limit, _) => limit,
+        };
+        entitlements
+    }
+
+    pub fn tier(&self) -> SubscriptionTier {
+        self.tier
+    }
+
+    pub fn max_devices(&self) -> u32 {
+        self.max_devices
+    }
+
+    pub fn data_limit(&self) -> Option<u64> {
+        self.data_limit
+    }
+
+    pub fn allows(&self, feature: Feature) -> bool {
+        self.tier.has_feature(feature)
+    }
+
+    pub fn check(&self, feature: Feature) -> Result<()> {
+        if self.allows(feature) {
+            return Ok(());
+        }
+        Err(VpnError::UpgradeRequired(format!(
+            "{} requires {} (current plan: {})",
+            feature, feature.required_tier().name(), self.tier.name()
+        )))
+    }
+
+    pub fn permits_server(&self, server: &VpnServer) -> bool {
+        !server.is_premium || self.allows(Feature::PremiumServers)
+    }
+
+    pub fn check_server(&self, server: &VpnServer) -> Result<()> {
+        if self.permits_server(server) {
+            return Ok(());
+        }
+        Err(VpnError::UpgradeRequired(format!(
+            "{} is a premium server and requires {} (current plan: {})",
+            server.name, Feature::PremiumServers.required_tier().name(), self.tier.name()
+        )))
+    }
+
+    /// Multi-hop itself, and every server along the way
+    pub fn check_chain(&self, chain: &ServerChain) -> Result<()> {
+        self.check(Feature::MultiHop)?;
+        chain.hops().iter().try_for_each(|hop| self.check_server(hop))
+    }
+
+    /// Refuses a search for features the plan lacks; premium servers are
+    /// skipped during selection rather than refused
+    pub fn check_query(&self, query: &ServerQuery) -> Result<()> {
+        if query.wants_p2p() {
+            self.check(Feature::P2P)?;
+        }
+        Ok(())
+    }
+
+    /// Whether one more device may connect while `connected` already are
+    pub fn check_devices(&self, connected: u32) -> Result<()> {
+        if connected < self.max_devices {
+            return Ok(());
+        }
+        Err(VpnError::UpgradeRequired(format!(
+            "The {} plan allows {} connected device(s); disconnect one or upgrade",
+            self.tier.name(), self.max_devices
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ServerManager;
+
+    fn servers() -> (VpnServer, VpnServer) {
+        let manager = ServerManager::new();
+        let servers = manager.get_all_servers();
+        let premium = servers.iter().find(|s| s.is_premium).unwrap();
+        let standard = servers.iter().find(|s| !s.is_premium).unwrap();
+        ((*premium).clone(), (*standard).clone())
+    }
+
+    #[test]
+    fn test_free_tier_is_limited() {
+        let free = Entitlements::default();
+        let (premium, standard) = servers();
+
+        assert!(Feature::all().iter().all(|f| !free.allows(*f)));
+        assert!(free.check_server(&standard).is_ok());
+        let error = free.check_server(&premium).unwrap_err();
+        assert!(matches!(error, VpnError::UpgradeRequired(_)));
+        assert!(error.to_string().contains("requires Premium"), "{}", error);
+
+        assert!(free.check_query(&ServerQuery::new().with_p2p(true)).is_err());
+        assert!(free.check_query(&ServerQuery::new().with_p2p(false)).is_ok());
+        let chain = ServerChain::new(vec![standard.clone(), premium.clone()]).unwrap();
+        assert!(free.check_chain(&chain).is_err());
+        assert!(Entitlements::for_tier(SubscriptionTier::Premium).check_chain(&chain).is_ok());
+
+        assert!(free.check_devices(0).is_ok());
+        assert!(free.check_devices(1).is_err());
+    }
+
+    #[test]
+    fn test_profile_decides_entitlements() {
+        let mut profile = UserProfile {
+            subscription_tier: SubscriptionTier::Premium,
+            max_devices: 50,
+            ..UserProfile::default()
+        };
+        let entitlements = Entitlements::for_profile(&profile);
+        assert!(entitlements.allows(Feature::MultiHop));
+        assert_eq!(entitlements.max_devices(), SubscriptionTier::Premium.max_devices());
+
+        // Nor can it lift the Free plan's data limit
+        let free = UserProfile { data_limit: None, ..UserProfile::default() };
+        assert_eq!(Entitlements::for_profile(&free).data_limit(), SubscriptionTier::Free.data_limit());
+        let smaller = UserProfile { data_limit: Some(1024), ..UserProfile::default() };
+        assert_eq!(Entitlements::for_profile(&smaller).data_limit(), Some(1024));
+
+        profile.subscription_expires = Some(chrono::Utc::now() - chrono::Duration::days(1));
+        assert_eq!(Entitlements::for_profile(&profile), Entitlements::default());
+    }
+}
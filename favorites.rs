@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use crate::server::VpnServer;
+use crate::storage::write_atomic;
+use crate::{Result, VpnError};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Favorite {
+    pub server_id: String,
+    #[serde(default)]
+    pub label: Option<String>,  // User's own name for the server
+}
+
+impl Favorite {
+    pub fn new(server_id: String) -> Self {
+        Self { server_id, label: None }
+    }
+}
+
+/// Favorites and recents as stored next to config.json
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SavedServers {
+    #[serde(default)]
+    pub favorites: Vec<Favorite>,
+    #[serde(default)]
+    pub recent: Vec<String>,     // Most recent first
+    #[serde(default)]
+    pub removed: Vec<VpnServer>, // Last known details of saved servers that left the catalog
+}
+
+impl SavedServers {
+    /// `None` when nothing has been saved yet
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(VpnError::ConfigError(format!("Failed to read saved servers: {}", e))),
+        };
+        serde_json::from_str(&contents)
+            .map(Some)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to parse saved servers: {}", e)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to serialize saved servers: {}", e)))?;
+        write_atomic(path, json.as_bytes())
+            .map_err(|e| VpnError::ConfigError(format!("Failed to write saved servers: {}", e)))
+    }
+
+    /// Points entries at servers' new ids, given old id -> new id. Returns
+    /// whether anything changed.
+    pub fn migrate(&mut self, renames: &HashMap<String, String>) -> bool {
+        let mut changed = false;
+        for id in self.favorites.iter_mut().map(|f| &mut f.server_id).chain(self.recent.iter_mut()) {
+            if let Some(new_id) = renames.get(id.as_str()) {
+                log::info!("Saved server {} is now {}", id, new_id);
+                *id = new_id.clone();
+                changed = true;
+            }
+        }
+
+        // Two old ids may have merged into one server
+        let mut seen = Vec::new();
+        self.favorites.retain(|f| {
+            let first = !seen.contains(&f.server_id);
+            seen.push(f.server_id.clone());
+            first
+        });
+        let mut seen = Vec::new();
+        self.recent.retain(|id| {
+            let first = !seen.contains(id);
+            seen.push(id.clone());
+            first
+        });
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_renamed_ids() {
+        let mut saved = SavedServers {
+            favorites: vec![Favorite::new("ch-zrh-1".to_string()), Favorite::new("CH-1".to_string())],
+            recent: vec!["SE-1".to_string(), "ch-zrh-1".to_string()],
+            removed: Vec::new(),
+        };
+        let renames = HashMap::from([("ch-zrh-1".to_string(), "CH-1".to_string())]);
+
+        assert!(saved.migrate(&renames));
+        assert_eq!(saved.favorites, vec![Favorite::new("CH-1".to_string())]);
+        assert_eq!(saved.recent, vec!["SE-1".to_string(), "CH-1".to_string()]);
+        assert!(!saved.migrate(&renames));
+    }
+
+    #[test]
+    fn test_load_missing_and_round_trip() {
+        let path = std::env::temp_dir().join(format!("vpn-saved-{}.json", rand::random::<u32>()));
+        assert_eq!(SavedServers::load(&path).unwrap(), None);
+
+        let mut saved = SavedServers::default();
+        saved.favorites.push(Favorite { server_id: "JP-1".to_string(), label: Some("Anime".to_string()) });
+        saved.save(&path).unwrap();
+        assert_eq!(SavedServers::load(&path).unwrap(), Some(saved));
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(SavedServers::load(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
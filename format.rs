@@ -0,0 +1,180 @@
+use serde_json::Value;
+use std::fmt;
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Table};
+use crate::{Result, VpnError};
+
+/// File formats a config can be written in. All three map onto the same
+/// JSON value, so a setting means the same thing whichever is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    pub fn all() -> Vec<ConfigFormat> {
+        vec![ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml]
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Toml => "TOML",
+            ConfigFormat::Yaml => "YAML",
+        }
+    }
+
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "json" => Some(ConfigFormat::Json),
+            "toml" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Best guess from the text itself
+    pub fn sniff(contents: &str) -> Self {
+        let trimmed = contents.trim_start();
+        if trimmed.starts_with('{') {
+            ConfigFormat::Json
+        } else if !trimmed.is_empty() && trimmed.parse::<DocumentMut>().is_ok() {
+            ConfigFormat::Toml
+        } else {
+            ConfigFormat::Yaml
+        }
+    }
+
+    /// The extension decides; files without a known one are sniffed
+    pub fn detect(path: &Path, contents: &str) -> Self {
+        Self::from_extension(path).unwrap_or_else(|| Self::sniff(contents))
+    }
+
+    pub fn parse(&self, contents: &str) -> Result<Value> {
+        let parsed = match self {
+            ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml_edit::de::from_str(contents).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        };
+        parsed.map_err(|e| VpnError::ConfigError(format!("Failed to parse {} config: {}", self.name(), e)))
+    }
+
+    /// Renders `value`. For TOML, `previous` is the file being replaced: its
+    /// comments and key order are kept for settings that still exist.
+    pub fn render(&self, value: &Value, previous: Option<&str>) -> Result<String> {
+        let rendered = match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string()),
+            ConfigFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => render_toml(value, previous),
+        };
+        rendered.map_err(|e| VpnError::ConfigError(format!("Failed to serialize {} config: {}", self.name(), e)))
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn render_toml(value: &Value, previous: Option<&str>) -> std::result::Result<String, String> {
+    // TOML has no null; an unset option is simply left out
+    let mut fresh = toml_edit::ser::to_document(&without_nulls(value)).map_err(|e| e.to_string())?;
+    expand_tables(fresh.as_table_mut());
+
+    match previous.and_then(|p| p.parse::<DocumentMut>().ok()) {
+        Some(mut document) => {
+            update_table(document.as_table_mut(), fresh.as_table());
+            Ok(document.to_string())
+        }
+        None => Ok(fresh.to_string()),
+    }
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => Value::Object(fields.iter()
+            .filter(|(_, field)| !field.is_null())
+            .map(|(name, field)| (name.clone(), without_nulls(field)))
+            .collect()),
+        Value::Array(items) => Value::Array(items.iter().map(without_nulls).collect()),
+        other => other.clone(),
+    }
+}
+
+// Nested objects as [section] headers rather than inline tables
+fn expand_tables(table: &mut Table) {
+    for (_, item) in table.iter_mut() {
+        if item.is_inline_table() {
+            if let Ok(mut expanded) = std::mem::take(item).into_table() {
+                expand_tables(&mut expanded);
+                *item = Item::Table(expanded);
+            }
+        }
+    }
+}
+
+// Writes `fresh` into `existing`, keeping the comments and layout of
+// anything that is still there
+fn update_table(existing: &mut Table, fresh: &Table) {
+    existing.retain(|key, _| fresh.contains_key(key));
+    for (key, item) in fresh.iter() {
+        match (existing.get_mut(key), item) {
+            (Some(Item::Table(old)), Item::Table(new)) => update_table(old, new),
+            (Some(Item::Value(old)), Item::Value(new)) => {
+                let decor = old.decor().clone();
+                *old = new.clone();
+                *old.decor_mut() = decor;
+            }
+            _ => {
+                existing.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_detects_format() {
+        assert_eq!(ConfigFormat::from_extension(&PathBuf::from("a/config.YML")), Some(ConfigFormat::Yaml));
+        assert_eq!(ConfigFormat::from_extension(&PathBuf::from("config")), None);
+        assert_eq!(ConfigFormat::sniff("  { \"mtu\": 1400 }"), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::sniff("# ops\nmtu = 1400\n[kill_switch]\nmode = \"Always\""), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::sniff("mtu: 1400\nkill_switch:\n  mode: Always"), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::detect(&PathBuf::from("config.conf"), "mtu = 1400"), ConfigFormat::Toml);
+    }
+
+    #[test]
+    fn test_same_value_in_every_format() {
+        let value = json!({
+            "mtu": 1400,
+            "server_manifest_url": null,
+            "kill_switch": { "mode": "Always", "allowed_ips": ["10.0.0.0/8"] },
+        });
+        for format in ConfigFormat::all() {
+            let parsed = format.parse(&format.render(&value, None).unwrap()).unwrap();
+            // Nulls only survive where the format has them
+            let expected = if format == ConfigFormat::Toml { without_nulls(&value) } else { value.clone() };
+            assert_eq!(parsed, expected, "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_toml_keeps_comments() {
+        let previous = "# Managed by ops\nmtu = 1400 # tunnel overhead\nblock_ads = true\n\n# Never leak\n[kill_switch]\nmode = \"Always\"\n";
+        let value = json!({ "mtu": 1380, "kill_switch": { "mode": "Always", "allow_lan": false } });
+        let rendered = ConfigFormat::Toml.render(&value, Some(previous)).unwrap();
+
+        assert!(rendered.starts_with("# Managed by ops\nmtu = 1380 # tunnel overhead\n"), "{}", rendered);
+        assert!(rendered.contains("# Never leak\n[kill_switch]\n"), "{}", rendered);
+        assert!(!rendered.contains("block_ads"));
+        assert_eq!(ConfigFormat::Toml.parse(&rendered).unwrap(), value);
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use crate::net::IpNetwork;
+use crate::{Result, VpnError};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+// Answers with the caller's public address as plain text
+const PUBLIC_IP_URL: &str = "https://api.ipify.org";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GeoPoint {
+    pub fn new(latitude: f64, longitude: f64) -> Result<Self> {
+        let point = Self { latitude, longitude };
+        point.validate()?;
+        Ok(point)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if !(-90.0..=90.0).contains(&self.latitude) || !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(VpnError::ConfigError(format!("Coordinates out of range: {}", self)));
+        }
+        Ok(())
+    }
+
+    /// Great-circle distance using the haversine formula
+    pub fn distance_km(&self, other: &GeoPoint) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+}
+
+/// "lat,lon", e.g. "47.37,8.54"
+impl FromStr for GeoPoint {
+    type Err = VpnError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || VpnError::ConfigError(format!("Invalid coordinates: {}", s));
+        let (lat, lon) = s.split_once(',').ok_or_else(invalid)?;
+        Self::new(
+            lat.trim().parse().map_err(|_| invalid())?,
+            lon.trim().parse().map_err(|_| invalid())?,
+        )
+    }
+}
+
+impl fmt::Display for GeoPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.4},{:.4}", self.latitude, self.longitude)
+    }
+}
+
+/// Offline IP-to-location table. Each line is `network,latitude,longitude`;
+/// blank lines and lines starting with `#` are skipped. The most specific
+/// matching network wins.
+#[derive(Debug, Clone, Default)]
+pub struct GeoIpDatabase {
+    entries: Vec<(IpNetwork, GeoPoint)>,
+}
+
+impl GeoIpDatabase {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to read GeoIP database: {}", e)))?;
+        contents.parse()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> Option<GeoPoint> {
+        self.entries.iter()
+            .filter(|(network, _)| network.contains(ip))
+            .max_by_key(|(network, _)| network.prefix())
+            .map(|(_, point)| *point)
+    }
+}
+
+/// The address the internet sees, to look up in a `GeoIpDatabase`. Only
+/// meaningful before the tunnel is up, which means the request reveals
+/// the user's real IP to `PUBLIC_IP_URL`; callers must have the user's
+/// consent (`VpnConfig::public_ip_lookup`).
+pub async fn public_ip() -> Result<IpAddr> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .map_err(|e| VpnError::NetworkError(e.to_string()))?;
+    let body = client.get(PUBLIC_IP_URL).send().await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| VpnError::NetworkError(format!("Failed to look up public IP: {}", e)))?
+        .text().await
+        .map_err(|e| VpnError::NetworkError(format!("Failed to look up public IP: {}", e)))?;
+    body.trim().parse()
+        .map_err(|_| VpnError::NetworkError(format!("Unexpected public IP response: {}", body.trim())))
+}
+
+impl FromStr for GeoIpDatabase {
+    type Err = VpnError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (idx, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || VpnError::ConfigError(format!("GeoIP database line {}: {}", idx + 1, line));
+            let (network, point) = line.split_once(',').ok_or_else(invalid)?;
+            entries.push((
+                network.parse().map_err(|_| invalid())?,
+                point.parse().map_err(|_| invalid())?,
+            ));
+        }
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_haversine_distance() {
+        let london = GeoPoint::new(51.5074, -0.1278).unwrap();
+        let paris = GeoPoint::new(48.8566, 2.3522).unwrap();
+        let sydney = GeoPoint::new(-33.8688, 151.2093).unwrap();
+
+        assert!((london.distance_km(&paris) - 344.0).abs() < 5.0);
+        assert!((london.distance_km(&sydney) - 16994.0).abs() < 50.0);
+        assert_eq!(paris.distance_km(&paris), 0.0);
+        assert!(GeoPoint::new(91.0, 0.0).is_err());
+        assert_eq!("47.37, 8.54".parse::<GeoPoint>().unwrap(), GeoPoint::new(47.37, 8.54).unwrap());
+    }
+
+    #[test]
+    fn test_geoip_longest_prefix_wins() {
+        let db: GeoIpDatabase = "
+            # network,latitude,longitude
+            81.0.0.0/8,51.5,-0.1
+            81.2.69.0/24,53.48,-2.24
+            2a02:1810::/32,50.85,4.35
+        ".parse().unwrap();
+
+        assert_eq!(db.len(), 3);
+        assert_eq!(db.lookup("81.2.69.160".parse().unwrap()).unwrap().latitude, 53.48);
+        assert_eq!(db.lookup("81.9.9.9".parse().unwrap()).unwrap().latitude, 51.5);
+        assert!(db.lookup("2a02:1810::1".parse().unwrap()).is_some());
+        assert!(db.lookup("8.8.8.8".parse().unwrap()).is_none());
+
+        assert!("10.0.0.0/8,north".parse::<GeoIpDatabase>().is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Events kept per server; older ones fall off the front
+const MAX_EVENTS: usize = 50;
+// An event counts half as much after this long
+const HALF_LIFE_HOURS: f64 = 24.0;
+// Failures in a row before a server is put on hold
+const FAILURES_BEFORE_BACKOFF: usize = 3;
+const BASE_BACKOFF_SECS: i64 = 300;
+const MAX_BACKOFF_SECS: i64 = 3600;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum HealthEventKind {
+    Connected { handshake: Duration },
+    ConnectFailed,
+    Dropped { after: Duration },  // Tunnel lost mid-session
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct HealthEvent {
+    pub at: DateTime<Utc>,
+    pub kind: HealthEventKind,
+}
+
+/// Recent connection outcomes for one server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerHealth {
+    events: VecDeque<HealthEvent>,  // Oldest first
+}
+
+impl ServerHealth {
+    pub fn record(&mut self, kind: HealthEventKind, at: DateTime<Utc>) {
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(HealthEvent { at, kind });
+    }
+
+    pub fn events(&self) -> impl Iterator<Item = &HealthEvent> {
+        self.events.iter()
+    }
+
+    /// 0.0-1.0, with each event weighted down by its age. One imaginary
+    /// success is added so a single early failure does not sink a server.
+    pub fn reliability(&self, now: DateTime<Utc>) -> f64 {
+        let (mut good, mut total) = (1.0, 1.0);
+        for event in &self.events {
+            let age_hours = (now - event.at).num_seconds().max(0) as f64 / 3600.0;
+            let weight = 0.5f64.powf(age_hours / HALF_LIFE_HOURS);
+            total += weight;
+            if matches!(event.kind, HealthEventKind::Connected { .. }) {
+                good += weight;
+            }
+        }
+        good / total
+    }
+
+    pub fn successes(&self) -> usize {
+        self.events.iter().filter(|e| matches!(e.kind, HealthEventKind::Connected { .. })).count()
+    }
+
+    pub fn failures(&self) -> usize {
+        self.events.iter().filter(|e| e.kind == HealthEventKind::ConnectFailed).count()
+    }
+
+    pub fn drops(&self) -> usize {
+        self.events.iter().filter(|e| matches!(e.kind, HealthEventKind::Dropped { .. })).count()
+    }
+
+    pub fn average_handshake(&self) -> Option<Duration> {
+        let handshakes: Vec<Duration> = self.events.iter()
+            .filter_map(|e| match e.kind {
+                HealthEventKind::Connected { handshake } => Some(handshake),
+                _ => None,
+            })
+            .collect();
+        if handshakes.is_empty() {
+            return None;
+        }
+        Some(handshakes.iter().sum::<Duration>() / handshakes.len() as u32)
+    }
+
+    /// Failed connects and drops since the last successful connect
+    pub fn consecutive_failures(&self) -> usize {
+        self.events.iter()
+            .rev()
+            .take_while(|e| !matches!(e.kind, HealthEventKind::Connected { .. }))
+            .count()
+    }
+
+    /// A server that keeps failing is skipped until this time. The wait
+    /// doubles with each further failure, up to an hour.
+    pub fn backoff_until(&self) -> Option<DateTime<Utc>> {
+        let failures = self.consecutive_failures();
+        if failures < FAILURES_BEFORE_BACKOFF {
+            return None;
+        }
+        let doublings = (failures - FAILURES_BEFORE_BACKOFF).min(4) as u32;
+        let secs = (BASE_BACKOFF_SECS << doublings).min(MAX_BACKOFF_SECS);
+        self.events.back().map(|last| last.at + chrono::Duration::seconds(secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connected() -> HealthEventKind {
+        HealthEventKind::Connected { handshake: Duration::from_millis(200) }
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let mut health = ServerHealth::default();
+        let now = Utc::now();
+        for _ in 0..MAX_EVENTS + 10 {
+            health.record(connected(), now);
+        }
+        health.record(HealthEventKind::Dropped { after: Duration::from_secs(60) }, now);
+
+        assert_eq!(health.events().count(), MAX_EVENTS);
+        assert_eq!(health.successes(), MAX_EVENTS - 1);
+        assert_eq!(health.drops(), 1);
+        assert_eq!(health.average_handshake(), Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn test_old_failures_decay() {
+        let now = Utc::now();
+        let mut old = ServerHealth::default();
+        let mut fresh = ServerHealth::default();
+        for _ in 0..4 {
+            old.record(HealthEventKind::ConnectFailed, now - chrono::Duration::days(7));
+            fresh.record(HealthEventKind::ConnectFailed, now);
+        }
+        old.record(connected(), now);
+        fresh.record(connected(), now);
+
+        assert!(old.reliability(now) > 0.9);
+        assert!(fresh.reliability(now) < 0.5);
+        assert_eq!(ServerHealth::default().reliability(now), 1.0);
+    }
+
+    #[test]
+    fn test_backoff_grows_and_resets() {
+        let now = Utc::now();
+        let mut health = ServerHealth::default();
+        health.record(HealthEventKind::ConnectFailed, now);
+        health.record(HealthEventKind::ConnectFailed, now);
+        assert_eq!(health.backoff_until(), None);
+
+        health.record(HealthEventKind::ConnectFailed, now);
+        assert_eq!(health.backoff_until(), Some(now + chrono::Duration::minutes(5)));
+        health.record(HealthEventKind::ConnectFailed, now);
+        assert_eq!(health.backoff_until(), Some(now + chrono::Duration::minutes(10)));
+        for _ in 0..10 {
+            health.record(HealthEventKind::ConnectFailed, now);
+        }
+        assert_eq!(health.backoff_until(), Some(now + chrono::Duration::hours(1)));
+
+        health.record(connected(), now);
+        assert_eq!(health.consecutive_failures(), 0);
+        assert_eq!(health.backoff_until(), None);
+    }
+}
@@ -0,0 +1,1985 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload as AeadPayload},
+    Aes256Gcm, Nonce,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey};
+use crate::{Result, VpnError};
+
+// Exchange types (RFC 7296 3.1)
+const IKE_SA_INIT: u8 = 34;
+const IKE_AUTH: u8 = 35;
+const INFORMATIONAL: u8 = 37;
+
+// Header flags
+const FLAG_INITIATOR: u8 = 0x08;
+const FLAG_RESPONSE: u8 = 0x20;
+
+// Payload types
+const PAYLOAD_SA: u8 = 33;
+const PAYLOAD_KE: u8 = 34;
+const PAYLOAD_IDI: u8 = 35;
+const PAYLOAD_IDR: u8 = 36;
+const PAYLOAD_CERT: u8 = 37;
+const PAYLOAD_AUTH: u8 = 39;
+const PAYLOAD_NONCE: u8 = 40;
+const PAYLOAD_NOTIFY: u8 = 41;
+const PAYLOAD_DELETE: u8 = 42;
+const PAYLOAD_TSI: u8 = 44;
+const PAYLOAD_TSR: u8 = 45;
+const PAYLOAD_SK: u8 = 46;
+const PAYLOAD_CP: u8 = 47;
+const PAYLOAD_EAP: u8 = 48;
+
+// Notify message types
+const NOTIFY_INVALID_KE_PAYLOAD: u16 = 17;
+const NOTIFY_NO_PROPOSAL_CHOSEN: u16 = 14;
+const NOTIFY_AUTHENTICATION_FAILED: u16 = 24;
+const NOTIFY_TS_UNACCEPTABLE: u16 = 38;
+const NOTIFY_NAT_DETECTION_SOURCE_IP: u16 = 16388;
+const NOTIFY_NAT_DETECTION_DESTINATION_IP: u16 = 16389;
+const NOTIFY_MOBIKE_SUPPORTED: u16 = 16396;
+const NOTIFY_UPDATE_SA_ADDRESSES: u16 = 16400;
+const NOTIFY_SIGNATURE_HASH_ALGORITHMS: u16 = 16431;
+
+// Transform types and IDs for the single suite we offer:
+// AES-256-GCM-16, PRF_HMAC_SHA2_256, Curve25519 and no ESN
+const TRANSFORM_ENCR: u8 = 1;
+const TRANSFORM_PRF: u8 = 2;
+const TRANSFORM_DH: u8 = 4;
+const TRANSFORM_ESN: u8 = 5;
+const ENCR_AES_GCM_16: u16 = 20;
+const PRF_HMAC_SHA2_256: u16 = 5;
+const DH_CURVE25519: u16 = 31;
+const ATTR_KEY_LENGTH: u16 = 0x800e;
+const PROTOCOL_IKE: u8 = 1;
+const PROTOCOL_ESP: u8 = 3;
+
+// Identification, certificate and authentication encodings
+const ID_FQDN: u8 = 2;
+const ID_RFC822_ADDR: u8 = 3;
+const CERT_RAW_PUBLIC_KEY: u8 = 15;
+const AUTH_SHARED_KEY_MIC: u8 = 2;
+const AUTH_DIGITAL_SIGNATURE: u8 = 14;
+
+// Configuration payload
+const CFG_REQUEST: u8 = 1;
+const CFG_REPLY: u8 = 2;
+const INTERNAL_IP4_ADDRESS: u16 = 1;
+const INTERNAL_IP6_ADDRESS: u16 = 8;
+
+// Traffic selector types
+const TS_IPV4_ADDR_RANGE: u8 = 7;
+const TS_IPV6_ADDR_RANGE: u8 = 8;
+
+// EAP (RFC 3748) and EAP-MSCHAPv2 (draft-kamath-pppext-eap-mschapv2)
+const EAP_REQUEST: u8 = 1;
+const EAP_RESPONSE: u8 = 2;
+const EAP_SUCCESS: u8 = 3;
+const EAP_FAILURE: u8 = 4;
+const EAP_TYPE_MSCHAPV2: u8 = 26;
+const MSCHAPV2_CHALLENGE: u8 = 1;
+const MSCHAPV2_RESPONSE: u8 = 2;
+const MSCHAPV2_SUCCESS: u8 = 3;
+
+const IKE_HEADER_LEN: usize = 28;
+const GCM_KEY_LEN: usize = 36; // 32 byte key + 4 byte salt (RFC 5282)
+const GCM_IV_LEN: usize = 8;
+const GCM_ICV_LEN: usize = 16;
+const NON_ESP_MARKER: [u8; 4] = [0; 4];
+const NATT_KEEPALIVE: u8 = 0xff;
+
+// DER prefixes for Ed25519 (RFC 8410, RFC 8420)
+const ED25519_SPKI_PREFIX: [u8; 12] = [0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+const ED25519_ALGORITHM_ID: [u8; 7] = [0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How the initiator proves its identity in IKE_AUTH. The responder always
+/// authenticates with a raw Ed25519 public key that must match `responder_key`.
+#[derive(Clone)]
+pub enum IkeAuth {
+    Certificate {
+        signing_key: SigningKey,
+        responder_key: VerifyingKey,
+    },
+    EapMschapv2 {
+        username: String,
+        password: String,
+        responder_key: VerifyingKey,
+    },
+}
+
+impl IkeAuth {
+    fn responder_key(&self) -> &VerifyingKey {
+        match self {
+            IkeAuth::Certificate { responder_key, .. } => responder_key,
+            IkeAuth::EapMschapv2 { responder_key, .. } => responder_key,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Ikev2Config {
+    pub auth: IkeAuth,
+    pub identity: String,
+    pub natt_port: u16,
+    pub timeout: Duration,
+    pub retransmits: u32,
+    pub force_udp_encapsulation: bool,  // We cannot send raw ESP, so always float to NAT-T
+}
+
+impl Ikev2Config {
+    pub fn new(auth: IkeAuth, identity: &str) -> Self {
+        Self {
+            auth,
+            identity: identity.to_string(),
+            natt_port: 4500,
+            timeout: Duration::from_secs(2),
+            retransmits: 3,
+            force_udp_encapsulation: true,
+        }
+    }
+
+    pub fn with_natt_port(mut self, port: u16) -> Self {
+        self.natt_port = port;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NatStatus {
+    pub local_behind_nat: bool,
+    pub remote_behind_nat: bool,
+    pub encapsulated: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Payload {
+    kind: u8,
+    body: Vec<u8>,
+}
+
+impl Payload {
+    fn new(kind: u8, body: Vec<u8>) -> Self {
+        Self { kind, body }
+    }
+
+    fn notify(notify_type: u16, data: &[u8]) -> Self {
+        let mut body = vec![0, 0];
+        body.extend_from_slice(&notify_type.to_be_bytes());
+        body.extend_from_slice(data);
+        Self::new(PAYLOAD_NOTIFY, body)
+    }
+
+    fn nonce(nonce: &[u8]) -> Self {
+        Self::new(PAYLOAD_NONCE, nonce.to_vec())
+    }
+
+    fn key_exchange(public: &[u8]) -> Self {
+        let mut body = DH_CURVE25519.to_be_bytes().to_vec();
+        body.extend_from_slice(&[0, 0]);
+        body.extend_from_slice(public);
+        Self::new(PAYLOAD_KE, body)
+    }
+
+    fn identification(kind: u8, id_type: u8, data: &[u8]) -> Self {
+        let mut body = vec![id_type, 0, 0, 0];
+        body.extend_from_slice(data);
+        Self::new(kind, body)
+    }
+
+    fn auth(method: u8, data: &[u8]) -> Self {
+        let mut body = vec![method, 0, 0, 0];
+        body.extend_from_slice(data);
+        Self::new(PAYLOAD_AUTH, body)
+    }
+
+    fn certificate(public_key: &VerifyingKey) -> Self {
+        let mut body = vec![CERT_RAW_PUBLIC_KEY];
+        body.extend_from_slice(&ED25519_SPKI_PREFIX);
+        body.extend_from_slice(public_key.as_bytes());
+        Self::new(PAYLOAD_CERT, body)
+    }
+
+    fn traffic_selectors(kind: u8) -> Self {
+        // Two selectors covering all IPv4 and all IPv6 traffic
+        let mut body = vec![2, 0, 0, 0];
+        body.extend_from_slice(&[TS_IPV4_ADDR_RANGE, 0, 0, 16, 0, 0, 0xff, 0xff]);
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(&[0xff; 4]);
+        body.extend_from_slice(&[TS_IPV6_ADDR_RANGE, 0, 0, 40, 0, 0, 0xff, 0xff]);
+        body.extend_from_slice(&[0; 16]);
+        body.extend_from_slice(&[0xff; 16]);
+        Self::new(kind, body)
+    }
+
+    fn delete_ike() -> Self {
+        Self::new(PAYLOAD_DELETE, vec![PROTOCOL_IKE, 0, 0, 0])
+    }
+
+    fn notify_type(&self) -> Option<u16> {
+        if self.kind == PAYLOAD_NOTIFY && self.body.len() >= 4 {
+            Some(u16::from_be_bytes([self.body[2], self.body[3]]))
+        } else {
+            None
+        }
+    }
+
+    fn notify_data(&self) -> &[u8] {
+        let spi_size = self.body.get(1).copied().unwrap_or(0) as usize;
+        self.body.get(4 + spi_size..).unwrap_or(&[])
+    }
+
+    /// Body of ID, AUTH and KE payloads without the 4 byte fixed prefix
+    fn data(&self) -> &[u8] {
+        self.body.get(4..).unwrap_or(&[])
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IkeMessage {
+    spi_i: u64,
+    spi_r: u64,
+    exchange: u8,
+    flags: u8,
+    message_id: u32,
+    payloads: Vec<Payload>,
+}
+
+impl IkeMessage {
+    fn find(&self, kind: u8) -> Option<&Payload> {
+        self.payloads.iter().find(|p| p.kind == kind)
+    }
+
+    fn find_notify(&self, notify_type: u16) -> Option<&Payload> {
+        self.payloads.iter().find(|p| p.notify_type() == Some(notify_type))
+    }
+
+    fn is_response(&self) -> bool {
+        self.flags & FLAG_RESPONSE != 0
+    }
+
+    /// Maps error notifications from the peer onto our error type
+    fn check_errors(&self) -> Result<()> {
+        for payload in &self.payloads {
+            match payload.notify_type() {
+                Some(NOTIFY_AUTHENTICATION_FAILED) => {
+                    return Err(VpnError::AuthenticationFailed(
+                        "IKEv2 responder rejected our credentials".to_string()
+                    ));
+                }
+                Some(NOTIFY_NO_PROPOSAL_CHOSEN) => {
+                    return Err(VpnError::ConnectionFailed(
+                        "IKEv2 responder accepted none of our proposals".to_string()
+                    ));
+                }
+                Some(NOTIFY_INVALID_KE_PAYLOAD) => {
+                    return Err(VpnError::ConnectionFailed(
+                        "IKEv2 responder does not support Curve25519".to_string()
+                    ));
+                }
+                Some(NOTIFY_TS_UNACCEPTABLE) => {
+                    return Err(VpnError::ConnectionFailed(
+                        "IKEv2 responder rejected our traffic selectors".to_string()
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_header(&self, next_payload: u8, length: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(length);
+        out.extend_from_slice(&self.spi_i.to_be_bytes());
+        out.extend_from_slice(&self.spi_r.to_be_bytes());
+        out.push(next_payload);
+        out.push(0x20); // IKE version 2.0
+        out.push(self.exchange);
+        out.push(self.flags);
+        out.extend_from_slice(&self.message_id.to_be_bytes());
+        out.extend_from_slice(&(length as u32).to_be_bytes());
+        out
+    }
+
+    /// Serializes the message, wrapping all payloads in an SK payload when a
+    /// send key is given.
+    fn encode(&self, send_key: Option<&[u8]>) -> Result<Vec<u8>> {
+        let (first, chain) = encode_chain(&self.payloads);
+
+        let key = match send_key {
+            Some(key) => key,
+            None => {
+                let mut out = self.encode_header(first, IKE_HEADER_LEN + chain.len());
+                out.extend_from_slice(&chain);
+                return Ok(out);
+            }
+        };
+
+        let mut plaintext = chain;
+        plaintext.push(0); // Pad Length, AES-GCM needs no padding
+        let sk_len = 4 + GCM_IV_LEN + plaintext.len() + GCM_ICV_LEN;
+        let total = IKE_HEADER_LEN + sk_len;
+
+        let mut out = self.encode_header(PAYLOAD_SK, total);
+        out.push(first);
+        out.push(0);
+        out.extend_from_slice(&(sk_len as u16).to_be_bytes());
+
+        let mut iv = [0u8; GCM_IV_LEN];
+        OsRng.fill_bytes(&mut iv);
+        let ciphertext = gcm_seal(key, &iv, &out, &plaintext)?;
+        out.extend_from_slice(&iv);
+        out.extend_from_slice(&ciphertext);
+        Ok(out)
+    }
+
+    fn decode(bytes: &[u8], recv_key: Option<&[u8]>) -> Result<Self> {
+        if bytes.len() < IKE_HEADER_LEN {
+            return Err(malformed("message shorter than the IKE header"));
+        }
+        if bytes[17] >> 4 != 2 {
+            return Err(malformed("unsupported IKE major version"));
+        }
+        let length = read_u32(bytes, 24) as usize;
+        if length != bytes.len() {
+            return Err(malformed("length field does not match datagram size"));
+        }
+
+        let mut message = IkeMessage {
+            spi_i: read_u64(bytes, 0),
+            spi_r: read_u64(bytes, 8),
+            exchange: bytes[18],
+            flags: bytes[19],
+            message_id: read_u32(bytes, 20),
+            payloads: Vec::new(),
+        };
+
+        let first = bytes[16];
+        if first != PAYLOAD_SK {
+            message.payloads = decode_chain(first, &bytes[IKE_HEADER_LEN..])?;
+            return Ok(message);
+        }
+
+        let key = recv_key.ok_or_else(|| malformed("encrypted payload before keys were established"))?;
+        let sk = &bytes[IKE_HEADER_LEN..];
+        if sk.len() < 4 + GCM_IV_LEN + GCM_ICV_LEN + 1 {
+            return Err(malformed("encrypted payload too short"));
+        }
+        let inner_first = sk[0];
+        let aad = &bytes[..IKE_HEADER_LEN + 4];
+        let iv = &sk[4..4 + GCM_IV_LEN];
+        let mut plaintext = gcm_open(key, iv, aad, &sk[4 + GCM_IV_LEN..])?;
+
+        let pad_len = *plaintext.last().unwrap_or(&0) as usize;
+        if pad_len + 1 > plaintext.len() {
+            return Err(malformed("invalid padding in encrypted payload"));
+        }
+        plaintext.truncate(plaintext.len() - pad_len - 1);
+        message.payloads = decode_chain(inner_first, &plaintext)?;
+        Ok(message)
+    }
+}
+
+fn encode_chain(payloads: &[Payload]) -> (u8, Vec<u8>) {
+    let mut out = Vec::new();
+    for (idx, payload) in payloads.iter().enumerate() {
+        let next = payloads.get(idx + 1).map(|p| p.kind).unwrap_or(0);
+        out.push(next);
+        out.push(0);
+        out.extend_from_slice(&((payload.body.len() + 4) as u16).to_be_bytes());
+        out.extend_from_slice(&payload.body);
+    }
+    (payloads.first().map(|p| p.kind).unwrap_or(0), out)
+}
+
+fn decode_chain(first: u8, mut bytes: &[u8]) -> Result<Vec<Payload>> {
+    let mut payloads = Vec::new();
+    let mut kind = first;
+    while kind != 0 {
+        if bytes.len() < 4 {
+            return Err(malformed("truncated payload header"));
+        }
+        let next = bytes[0];
+        let len = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        if len < 4 || len > bytes.len() {
+            return Err(malformed("payload length out of bounds"));
+        }
+        payloads.push(Payload::new(kind, bytes[4..len].to_vec()));
+        bytes = &bytes[len..];
+        kind = next;
+    }
+    Ok(payloads)
+}
+
+fn malformed(reason: &str) -> VpnError {
+    VpnError::NetworkError(format!("Malformed IKEv2 message: {}", reason))
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+// ----------------------------------------------------------------------------
+// Security association proposals
+// ----------------------------------------------------------------------------
+
+fn encode_transform(out: &mut Vec<u8>, last: bool, transform_type: u8, id: u16, key_len: Option<u16>) {
+    let len: u16 = if key_len.is_some() { 12 } else { 8 };
+    out.push(if last { 0 } else { 3 });
+    out.push(0);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.push(transform_type);
+    out.push(0);
+    out.extend_from_slice(&id.to_be_bytes());
+    if let Some(bits) = key_len {
+        out.extend_from_slice(&ATTR_KEY_LENGTH.to_be_bytes());
+        out.extend_from_slice(&bits.to_be_bytes());
+    }
+}
+
+fn encode_proposal(protocol: u8, spi: &[u8]) -> Payload {
+    let mut transforms = Vec::new();
+    let count = if protocol == PROTOCOL_IKE {
+        encode_transform(&mut transforms, false, TRANSFORM_ENCR, ENCR_AES_GCM_16, Some(256));
+        encode_transform(&mut transforms, false, TRANSFORM_PRF, PRF_HMAC_SHA2_256, None);
+        encode_transform(&mut transforms, true, TRANSFORM_DH, DH_CURVE25519, None);
+        3
+    } else {
+        encode_transform(&mut transforms, false, TRANSFORM_ENCR, ENCR_AES_GCM_16, Some(256));
+        encode_transform(&mut transforms, true, TRANSFORM_ESN, 0, None);
+        2
+    };
+
+    let len = 8 + spi.len() + transforms.len();
+    let mut body = vec![0, 0];
+    body.extend_from_slice(&(len as u16).to_be_bytes());
+    body.extend_from_slice(&[1, protocol, spi.len() as u8, count]);
+    body.extend_from_slice(spi);
+    body.extend_from_slice(&transforms);
+    Payload::new(PAYLOAD_SA, body)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Proposal {
+    protocol: u8,
+    spi: Vec<u8>,
+    transforms: Vec<(u8, u16, Option<u16>)>,
+}
+
+impl Proposal {
+    /// Whether the proposal is exactly the suite we offer
+    fn is_supported(&self) -> bool {
+        let has = |t: u8, id: u16, key: Option<u16>| self.transforms.contains(&(t, id, key));
+        let encr = has(TRANSFORM_ENCR, ENCR_AES_GCM_16, Some(256));
+        match self.protocol {
+            PROTOCOL_IKE => encr
+                && has(TRANSFORM_PRF, PRF_HMAC_SHA2_256, None)
+                && has(TRANSFORM_DH, DH_CURVE25519, None),
+            PROTOCOL_ESP => encr && self.spi.len() == 4,
+            _ => false,
+        }
+    }
+}
+
+fn decode_proposals(body: &[u8]) -> Result<Vec<Proposal>> {
+    let mut proposals = Vec::new();
+    let mut rest = body;
+    while rest.len() >= 8 {
+        let last = rest[0] == 0;
+        let len = read_u16(rest, 2) as usize;
+        if len < 8 || len > rest.len() {
+            return Err(malformed("proposal length out of bounds"));
+        }
+        let protocol = rest[5];
+        let spi_size = rest[6] as usize;
+        let count = rest[7] as usize;
+        if 8 + spi_size > len {
+            return Err(malformed("proposal SPI out of bounds"));
+        }
+        let spi = rest[8..8 + spi_size].to_vec();
+
+        let mut transforms = Vec::new();
+        let mut t = &rest[8 + spi_size..len];
+        for _ in 0..count {
+            if t.len() < 8 {
+                return Err(malformed("truncated transform"));
+            }
+            let t_len = read_u16(t, 2) as usize;
+            if t_len < 8 || t_len > t.len() {
+                return Err(malformed("transform length out of bounds"));
+            }
+            let key_len = if t_len >= 12 && read_u16(t, 8) == ATTR_KEY_LENGTH {
+                Some(read_u16(t, 10))
+            } else {
+                None
+            };
+            transforms.push((t[4], read_u16(t, 6), key_len));
+            t = &t[t_len..];
+        }
+
+        proposals.push(Proposal { protocol, spi, transforms });
+        rest = &rest[len..];
+        if last {
+            break;
+        }
+    }
+    Ok(proposals)
+}
+
+fn chosen_proposal(message: &IkeMessage, protocol: u8) -> Result<Proposal> {
+    let sa = message.find(PAYLOAD_SA)
+        .ok_or_else(|| malformed("missing SA payload"))?;
+    decode_proposals(&sa.body)?
+        .into_iter()
+        .find(|p| p.protocol == protocol && p.is_supported())
+        .ok_or_else(|| VpnError::ConnectionFailed(
+            "IKEv2 responder chose a proposal we did not offer".to_string()
+        ))
+}
+
+// ----------------------------------------------------------------------------
+// Key schedule (RFC 7296 2.13, 2.14, 2.17) and AES-GCM (RFC 5282, RFC 4106)
+// ----------------------------------------------------------------------------
+
+fn prf(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn prf_plus(key: &[u8], seed: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut previous = Vec::new();
+    let mut counter = 1u8;
+    while out.len() < len {
+        let mut input = previous.clone();
+        input.extend_from_slice(seed);
+        input.push(counter);
+        previous = prf(key, &input);
+        out.extend_from_slice(&previous);
+        counter += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+#[derive(Clone)]
+struct IkeKeys {
+    sk_d: Vec<u8>,
+    sk_ei: Vec<u8>,
+    sk_er: Vec<u8>,
+    sk_pi: Vec<u8>,
+    sk_pr: Vec<u8>,
+}
+
+impl IkeKeys {
+    fn derive(nonce_i: &[u8], nonce_r: &[u8], shared: &[u8], spi_i: u64, spi_r: u64) -> Self {
+        let mut nonces = nonce_i.to_vec();
+        nonces.extend_from_slice(nonce_r);
+        let skeyseed = prf(&nonces, shared);
+
+        let mut seed = nonces;
+        seed.extend_from_slice(&spi_i.to_be_bytes());
+        seed.extend_from_slice(&spi_r.to_be_bytes());
+
+        // AEAD suites have no separate integrity keys, so SK_ai/SK_ar are empty
+        let material = prf_plus(&skeyseed, &seed, 32 + GCM_KEY_LEN * 2 + 32 * 2);
+        let (sk_d, rest) = material.split_at(32);
+        let (sk_ei, rest) = rest.split_at(GCM_KEY_LEN);
+        let (sk_er, rest) = rest.split_at(GCM_KEY_LEN);
+        let (sk_pi, sk_pr) = rest.split_at(32);
+
+        Self {
+            sk_d: sk_d.to_vec(),
+            sk_ei: sk_ei.to_vec(),
+            sk_er: sk_er.to_vec(),
+            sk_pi: sk_pi.to_vec(),
+            sk_pr: sk_pr.to_vec(),
+        }
+    }
+
+    /// KEYMAT for the first CHILD_SA: initiator-to-responder key first
+    fn child_keys(&self, nonce_i: &[u8], nonce_r: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut seed = nonce_i.to_vec();
+        seed.extend_from_slice(nonce_r);
+        let keymat = prf_plus(&self.sk_d, &seed, GCM_KEY_LEN * 2);
+        (keymat[..GCM_KEY_LEN].to_vec(), keymat[GCM_KEY_LEN..].to_vec())
+    }
+}
+
+fn gcm_nonce(key: &[u8], iv: &[u8]) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[..4].copy_from_slice(&key[32..GCM_KEY_LEN]);
+    nonce[4..].copy_from_slice(iv);
+    nonce
+}
+
+fn gcm_seal(key: &[u8], iv: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new_from_slice(&key[..32])
+        .map_err(|e| VpnError::EncryptionError(format!("Invalid key: {}", e)))?;
+    let nonce = gcm_nonce(key, iv);
+    cipher
+        .encrypt(Nonce::from_slice(&nonce), AeadPayload { msg: plaintext, aad })
+        .map_err(|e| VpnError::EncryptionError(format!("Encryption failed: {}", e)))
+}
+
+fn gcm_open(key: &[u8], iv: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let cipher = Aes256Gcm::new_from_slice(&key[..32])
+        .map_err(|e| VpnError::EncryptionError(format!("Invalid key: {}", e)))?;
+    let nonce = gcm_nonce(key, iv);
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), AeadPayload { msg: ciphertext, aad })
+        .map_err(|e| VpnError::EncryptionError(format!("Decryption failed: {}", e)))
+}
+
+fn nat_detection_hash(spi_i: u64, spi_r: u64, addr: SocketAddr) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    hasher.update(spi_i.to_be_bytes());
+    hasher.update(spi_r.to_be_bytes());
+    match addr.ip() {
+        IpAddr::V4(ip) => hasher.update(ip.octets()),
+        IpAddr::V6(ip) => hasher.update(ip.octets()),
+    }
+    hasher.update(addr.port().to_be_bytes());
+    hasher.finalize().to_vec()
+}
+
+/// Octets covered by the AUTH payload (RFC 7296 2.15)
+fn signed_octets(sa_init_message: &[u8], peer_nonce: &[u8], sk_p: &[u8], id_payload: &Payload) -> Vec<u8> {
+    let mut octets = sa_init_message.to_vec();
+    octets.extend_from_slice(peer_nonce);
+    octets.extend_from_slice(&prf(sk_p, &id_payload.body));
+    octets
+}
+
+fn signature_auth(key: &SigningKey, octets: &[u8]) -> Payload {
+    let mut data = vec![ED25519_ALGORITHM_ID.len() as u8];
+    data.extend_from_slice(&ED25519_ALGORITHM_ID);
+    data.extend_from_slice(&key.sign(octets).to_bytes());
+    Payload::auth(AUTH_DIGITAL_SIGNATURE, &data)
+}
+
+fn verify_signature_auth(key: &VerifyingKey, auth: &Payload, octets: &[u8]) -> Result<()> {
+    let data = auth.data();
+    let prefix_len = 1 + ED25519_ALGORITHM_ID.len();
+    if auth.body.first() != Some(&AUTH_DIGITAL_SIGNATURE)
+        || data.len() != prefix_len + 64
+        || data[1..prefix_len] != ED25519_ALGORITHM_ID
+    {
+        return Err(VpnError::AuthenticationFailed(
+            "IKEv2 peer did not use an Ed25519 signature".to_string()
+        ));
+    }
+    let signature = Signature::from_slice(&data[prefix_len..])
+        .map_err(|_| VpnError::AuthenticationFailed("Malformed IKEv2 signature".to_string()))?;
+    key.verify(octets, &signature)
+        .map_err(|_| VpnError::AuthenticationFailed("IKEv2 peer signature is invalid".to_string()))
+}
+
+fn msk_auth(msk: &[u8], octets: &[u8]) -> Vec<u8> {
+    prf(&prf(msk, b"Key Pad for IKEv2"), octets)
+}
+
+fn certificate_key(cert: &Payload) -> Result<VerifyingKey> {
+    let body = &cert.body;
+    if body.first() != Some(&CERT_RAW_PUBLIC_KEY)
+        || body.len() != 1 + ED25519_SPKI_PREFIX.len() + 32
+        || body[1..1 + ED25519_SPKI_PREFIX.len()] != ED25519_SPKI_PREFIX
+    {
+        return Err(VpnError::AuthenticationFailed(
+            "IKEv2 peer certificate is not a raw Ed25519 public key".to_string()
+        ));
+    }
+    let key: [u8; 32] = body[1 + ED25519_SPKI_PREFIX.len()..].try_into().unwrap();
+    VerifyingKey::from_bytes(&key)
+        .map_err(|_| VpnError::AuthenticationFailed("Invalid Ed25519 public key".to_string()))
+}
+
+fn random_spi() -> u64 {
+    loop {
+        let spi = OsRng.next_u64();
+        if spi != 0 {
+            return spi;
+        }
+    }
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+// ----------------------------------------------------------------------------
+// MS-CHAPv2 (RFC 2759) and MPPE key derivation (RFC 3079)
+// ----------------------------------------------------------------------------
+
+mod mschapv2 {
+    use des::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+    use des::Des;
+    use md4::Md4;
+    use sha1::{Digest, Sha1};
+
+    const AUTH_MAGIC1: &[u8] = b"Magic server to client signing constant";
+    const AUTH_MAGIC2: &[u8] = b"Pad to make it do more than one iteration";
+    const MPPE_MAGIC1: &[u8] = b"This is the MPPE Master Key";
+    const MPPE_MAGIC2: &[u8] =
+        b"On the client side, this is the send key; on the server side, it is the receive key.";
+    const MPPE_MAGIC3: &[u8] =
+        b"On the client side, this is the receive key; on the server side, it is the send key.";
+
+    pub fn nt_password_hash(password: &str) -> [u8; 16] {
+        let unicode: Vec<u8> = password.encode_utf16().flat_map(|c| c.to_le_bytes()).collect();
+        Md4::digest(&unicode).into()
+    }
+
+    fn challenge_hash(peer_challenge: &[u8], auth_challenge: &[u8], username: &str) -> [u8; 8] {
+        let mut hasher = Sha1::new();
+        hasher.update(peer_challenge);
+        hasher.update(auth_challenge);
+        hasher.update(username.as_bytes());
+        hasher.finalize()[..8].try_into().unwrap()
+    }
+
+    fn des_encrypt(key7: &[u8], block: &[u8; 8]) -> [u8; 8] {
+        let key = [
+            key7[0],
+            (key7[0] << 7) | (key7[1] >> 1),
+            (key7[1] << 6) | (key7[2] >> 2),
+            (key7[2] << 5) | (key7[3] >> 3),
+            (key7[3] << 4) | (key7[4] >> 4),
+            (key7[4] << 3) | (key7[5] >> 5),
+            (key7[5] << 2) | (key7[6] >> 6),
+            key7[6] << 1,
+        ];
+        let cipher = Des::new_from_slice(&key).expect("DES key is 8 bytes");
+        let mut out = GenericArray::clone_from_slice(block);
+        cipher.encrypt_block(&mut out);
+        out.into()
+    }
+
+    pub fn nt_response(auth_challenge: &[u8], peer_challenge: &[u8], username: &str, password: &str) -> [u8; 24] {
+        let challenge = challenge_hash(peer_challenge, auth_challenge, username);
+        let mut padded = [0u8; 21];
+        padded[..16].copy_from_slice(&nt_password_hash(password));
+
+        let mut response = [0u8; 24];
+        for i in 0..3 {
+            response[i * 8..(i + 1) * 8].copy_from_slice(&des_encrypt(&padded[i * 7..(i + 1) * 7], &challenge));
+        }
+        response
+    }
+
+    pub fn authenticator_response(
+        password: &str,
+        nt_response: &[u8],
+        peer_challenge: &[u8],
+        auth_challenge: &[u8],
+        username: &str,
+    ) -> String {
+        let hash_hash = Md4::digest(nt_password_hash(password));
+
+        let mut hasher = Sha1::new();
+        hasher.update(hash_hash);
+        hasher.update(nt_response);
+        hasher.update(AUTH_MAGIC1);
+        let digest = hasher.finalize();
+
+        let mut hasher = Sha1::new();
+        hasher.update(digest);
+        hasher.update(challenge_hash(peer_challenge, auth_challenge, username));
+        hasher.update(AUTH_MAGIC2);
+        let digest = hasher.finalize();
+
+        let hex: String = digest.iter().map(|b| format!("{:02X}", b)).collect();
+        format!("S={}", hex)
+    }
+
+    /// 64 byte MSK: MasterReceiveKey | MasterSendKey (server view) | zero padding
+    pub fn msk(password: &str, nt_response: &[u8]) -> Vec<u8> {
+        let hash_hash = Md4::digest(nt_password_hash(password));
+        let mut hasher = Sha1::new();
+        hasher.update(hash_hash);
+        hasher.update(nt_response);
+        hasher.update(MPPE_MAGIC1);
+        let master_key = &hasher.finalize()[..16];
+
+        let start_key = |magic: &[u8]| -> Vec<u8> {
+            let mut hasher = Sha1::new();
+            hasher.update(master_key);
+            hasher.update([0u8; 40]);
+            hasher.update(magic);
+            hasher.update([0xf2u8; 40]);
+            hasher.finalize()[..16].to_vec()
+        };
+
+        let mut msk = start_key(MPPE_MAGIC2);
+        msk.extend_from_slice(&start_key(MPPE_MAGIC3));
+        msk.extend_from_slice(&[0u8; 32]);
+        msk
+    }
+}
+
+fn eap_packet(code: u8, identifier: u8, data: &[u8]) -> Vec<u8> {
+    let mut packet = vec![code, identifier];
+    packet.extend_from_slice(&((data.len() + 4) as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+fn mschapv2_packet(code: u8, identifier: u8, opcode: u8, value: &[u8]) -> Vec<u8> {
+    let mut data = vec![EAP_TYPE_MSCHAPV2, opcode, identifier];
+    data.extend_from_slice(&((value.len() + 4) as u16).to_be_bytes());
+    data.extend_from_slice(value);
+    eap_packet(code, identifier, &data)
+}
+
+// ----------------------------------------------------------------------------
+// ESP in UDP (RFC 3948, RFC 4106)
+// ----------------------------------------------------------------------------
+
+struct ChildSa {
+    spi_out: u32,
+    spi_in: u32,
+    key_out: Vec<u8>,
+    key_in: Vec<u8>,
+    next_seq: u32,
+    replay: Arc<Mutex<ReplayWindow>>,  // Shared with the session's receivers
+}
+
+#[derive(Default)]
+struct ReplayWindow {
+    highest: u32,
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    fn accept(&mut self, seq: u32) -> bool {
+        if seq == 0 {
+            return false;
+        }
+        if seq > self.highest {
+            let shift = seq - self.highest;
+            self.bitmap = if shift >= 64 { 0 } else { self.bitmap << shift };
+            self.bitmap |= 1;
+            self.highest = seq;
+            return true;
+        }
+        let offset = self.highest - seq;
+        if offset >= 64 || self.bitmap & (1 << offset) != 0 {
+            return false;
+        }
+        self.bitmap |= 1 << offset;
+        true
+    }
+}
+
+fn esp_seal(spi: u32, seq: u32, key: &[u8], packet: &[u8]) -> Result<Vec<u8>> {
+    let next_header = if packet.first().map(|b| b >> 4) == Some(6) { 41 } else { 4 };
+    let mut plaintext = packet.to_vec();
+    let pad_len = (4 - (plaintext.len() + 2) % 4) % 4;
+    plaintext.extend(1..=pad_len as u8);
+    plaintext.push(pad_len as u8);
+    plaintext.push(next_header);
+
+    let mut header = spi.to_be_bytes().to_vec();
+    header.extend_from_slice(&seq.to_be_bytes());
+    let iv = (seq as u64).to_be_bytes();
+    let ciphertext = gcm_seal(key, &iv, &header, &plaintext)?;
+
+    let mut out = header;
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn esp_open(key: &[u8], datagram: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    if datagram.len() < 8 + GCM_IV_LEN + GCM_ICV_LEN + 2 {
+        return Err(VpnError::NetworkError("ESP packet too short".to_string()));
+    }
+    let spi = read_u32(datagram, 0);
+    let seq = read_u32(datagram, 4);
+    let iv = &datagram[8..8 + GCM_IV_LEN];
+    let mut plaintext = gcm_open(key, iv, &datagram[..8], &datagram[8 + GCM_IV_LEN..])?;
+
+    let pad_len = plaintext[plaintext.len() - 2] as usize;
+    if pad_len + 2 > plaintext.len() {
+        return Err(VpnError::NetworkError("Invalid ESP padding".to_string()));
+    }
+    plaintext.truncate(plaintext.len() - pad_len - 2);
+    Ok((spi, seq, plaintext))
+}
+
+// ----------------------------------------------------------------------------
+// Initiator
+// ----------------------------------------------------------------------------
+
+pub struct Ikev2Session {
+    socket: Arc<UdpSocket>,
+    remote: SocketAddr,
+    encapsulated: bool,
+    spi_i: u64,
+    spi_r: u64,
+    keys: IkeKeys,
+    next_message_id: u32,
+    child: ChildSa,
+    nat: NatStatus,
+    mobike: bool,
+    tunnel_address: Option<Ipv4Addr>,
+    tunnel_address_v6: Option<Ipv6Addr>,
+    config: Ikev2Config,
+}
+
+impl Ikev2Session {
+    /// Runs IKE_SA_INIT and IKE_AUTH against `remote` and installs the first CHILD_SA
+    pub async fn connect(remote: SocketAddr, config: Ikev2Config) -> Result<Self> {
+        let socket = bind_socket(remote).await?;
+        let local = socket.local_addr()
+            .map_err(|e| VpnError::NetworkError(format!("Failed to read local address: {}", e)))?;
+
+        // IKE_SA_INIT
+        let spi_i = random_spi();
+        let nonce_i = random_bytes::<32>();
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public = X25519PublicKey::from(&secret);
+
+        // Faking our source hash makes the responder believe we are behind a
+        // NAT, which forces UDP encapsulation of ESP (RFC 7296 2.23)
+        let source_hash = if config.force_udp_encapsulation {
+            nat_detection_hash(spi_i, 0, SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
+        } else {
+            nat_detection_hash(spi_i, 0, local)
+        };
+
+        let request = IkeMessage {
+            spi_i,
+            spi_r: 0,
+            exchange: IKE_SA_INIT,
+            flags: FLAG_INITIATOR,
+            message_id: 0,
+            payloads: vec![
+                encode_proposal(PROTOCOL_IKE, &[]),
+                Payload::key_exchange(public.as_bytes()),
+                Payload::nonce(&nonce_i),
+                Payload::notify(NOTIFY_NAT_DETECTION_SOURCE_IP, &source_hash),
+                Payload::notify(NOTIFY_NAT_DETECTION_DESTINATION_IP, &nat_detection_hash(spi_i, 0, remote)),
+                Payload::notify(NOTIFY_SIGNATURE_HASH_ALGORITHMS, &[0, 5]),
+            ],
+        };
+        let request_bytes = request.encode(None)?;
+        let (response, response_bytes) = transact(&socket, false, &request_bytes, 0, None, &config).await?;
+        response.check_errors()?;
+        chosen_proposal(&response, PROTOCOL_IKE)?;
+
+        let spi_r = response.spi_r;
+        let ke = response.find(PAYLOAD_KE)
+            .ok_or_else(|| malformed("missing KE payload"))?;
+        let peer_public: [u8; 32] = ke.data().try_into()
+            .map_err(|_| malformed("Curve25519 public value must be 32 bytes"))?;
+        let nonce_r = response.find(PAYLOAD_NONCE)
+            .ok_or_else(|| malformed("missing Nonce payload"))?
+            .body.clone();
+
+        let shared = secret.diffie_hellman(&X25519PublicKey::from(peer_public));
+        if !shared.was_contributory() {
+            return Err(VpnError::EncryptionError("IKEv2 peer sent a low-order public value".to_string()));
+        }
+        let keys = IkeKeys::derive(&nonce_i, &nonce_r, shared.as_bytes(), spi_i, spi_r);
+
+        let mut nat = NatStatus { local_behind_nat: false, remote_behind_nat: false, encapsulated: false };
+        if let Some(hash) = response.find_notify(NOTIFY_NAT_DETECTION_SOURCE_IP) {
+            nat.remote_behind_nat = hash.notify_data() != nat_detection_hash(spi_i, spi_r, remote).as_slice();
+        }
+        if let Some(hash) = response.find_notify(NOTIFY_NAT_DETECTION_DESTINATION_IP) {
+            nat.local_behind_nat = hash.notify_data() != nat_detection_hash(spi_i, spi_r, local).as_slice();
+        }
+        log::debug!("IKEv2 NAT detection: local behind NAT: {}, remote behind NAT: {}",
+            nat.local_behind_nat, nat.remote_behind_nat);
+
+        // Float to the NAT-T port for everything after IKE_SA_INIT
+        let (socket, remote) = if nat.local_behind_nat || nat.remote_behind_nat || config.force_udp_encapsulation {
+            let natt_remote = SocketAddr::new(remote.ip(), config.natt_port);
+            nat.encapsulated = true;
+            (bind_socket(natt_remote).await?, natt_remote)
+        } else {
+            (socket, remote)
+        };
+
+        let spi_in = OsRng.next_u32() | 1;
+        let mut session = Self {
+            socket: Arc::new(socket),
+            remote,
+            encapsulated: nat.encapsulated,
+            spi_i,
+            spi_r,
+            keys,
+            next_message_id: 1,
+            child: ChildSa {
+                spi_out: 0,
+                spi_in,
+                key_out: Vec::new(),
+                key_in: Vec::new(),
+                next_seq: 1,
+                replay: Arc::default(),
+            },
+            nat,
+            mobike: false,
+            tunnel_address: None,
+            tunnel_address_v6: None,
+            config,
+        };
+
+        session.authenticate(&request_bytes, &response_bytes, &nonce_i, &nonce_r).await?;
+        log::info!("IKEv2 SA established with {} (CHILD_SA in 0x{:08x}, out 0x{:08x})",
+            session.remote, session.child.spi_in, session.child.spi_out);
+        Ok(session)
+    }
+
+    async fn authenticate(&mut self, init_request: &[u8], init_response: &[u8], nonce_i: &[u8], nonce_r: &[u8]) -> Result<()> {
+        let auth = self.config.auth.clone();
+        let id_i = match &auth {
+            IkeAuth::Certificate { .. } => Payload::identification(PAYLOAD_IDI, ID_FQDN, self.config.identity.as_bytes()),
+            IkeAuth::EapMschapv2 { username, .. } => Payload::identification(PAYLOAD_IDI, ID_RFC822_ADDR, username.as_bytes()),
+        };
+        let octets_i = signed_octets(init_request, nonce_r, &self.keys.sk_pi, &id_i);
+
+        let mut payloads = vec![id_i];
+        if let IkeAuth::Certificate { signing_key, .. } = &auth {
+            payloads.push(Payload::certificate(&signing_key.verifying_key()));
+            payloads.push(signature_auth(signing_key, &octets_i));
+        }
+        payloads.extend(self.child_sa_payloads());
+        let mut response = self.request(IKE_AUTH, payloads).await?;
+
+        // The responder always signs its first IKE_AUTH response
+        let id_r = response.find(PAYLOAD_IDR).cloned()
+            .ok_or_else(|| malformed("missing IDr payload"))?;
+        let auth_r = response.find(PAYLOAD_AUTH).cloned()
+            .ok_or_else(|| VpnError::AuthenticationFailed("IKEv2 responder did not authenticate".to_string()))?;
+        if let Some(cert) = response.find(PAYLOAD_CERT) {
+            if certificate_key(cert)? != *auth.responder_key() {
+                return Err(VpnError::AuthenticationFailed(
+                    "IKEv2 responder presented an unexpected public key".to_string()
+                ));
+            }
+        }
+        let octets_r = signed_octets(init_response, nonce_i, &self.keys.sk_pr, &id_r);
+        verify_signature_auth(auth.responder_key(), &auth_r, &octets_r)?;
+
+        if let IkeAuth::EapMschapv2 { username, password, .. } = &auth {
+            let msk = self.run_eap_mschapv2(response, username, password).await?;
+
+            let data = msk_auth(&msk, &octets_i);
+            response = self.request(IKE_AUTH, vec![Payload::auth(AUTH_SHARED_KEY_MIC, &data)]).await?;
+
+            let final_auth = response.find(PAYLOAD_AUTH)
+                .ok_or_else(|| VpnError::AuthenticationFailed("Missing EAP AUTH from responder".to_string()))?;
+            if final_auth.data() != msk_auth(&msk, &octets_r).as_slice() {
+                return Err(VpnError::AuthenticationFailed("IKEv2 responder EAP AUTH mismatch".to_string()));
+            }
+        }
+
+        self.install_child_sa(&response, nonce_i, nonce_r)
+    }
+
+    fn child_sa_payloads(&self) -> Vec<Payload> {
+        let mut cp = vec![CFG_REQUEST, 0, 0, 0];
+        cp.extend_from_slice(&INTERNAL_IP4_ADDRESS.to_be_bytes());
+        cp.extend_from_slice(&[0, 0]);
+        cp.extend_from_slice(&INTERNAL_IP6_ADDRESS.to_be_bytes());
+        cp.extend_from_slice(&[0, 0]);
+
+        vec![
+            Payload::new(PAYLOAD_CP, cp),
+            encode_proposal(PROTOCOL_ESP, &self.child.spi_in.to_be_bytes()),
+            Payload::traffic_selectors(PAYLOAD_TSI),
+            Payload::traffic_selectors(PAYLOAD_TSR),
+            Payload::notify(NOTIFY_MOBIKE_SUPPORTED, &[]),
+        ]
+    }
+
+    async fn run_eap_mschapv2(&mut self, mut response: IkeMessage, username: &str, password: &str) -> Result<Vec<u8>> {
+        let mut nt_response: Option<([u8; 24], [u8; 16], Vec<u8>)> = None;
+        loop {
+            let eap = response.find(PAYLOAD_EAP)
+                .ok_or_else(|| malformed("missing EAP payload"))?
+                .body.clone();
+            if eap.len() < 4 {
+                return Err(malformed("truncated EAP packet"));
+            }
+            let (code, identifier) = (eap[0], eap[1]);
+
+            match code {
+                EAP_SUCCESS => {
+                    let (nt, _, _) = nt_response.as_ref()
+                        .ok_or_else(|| malformed("EAP success before MS-CHAPv2 response"))?;
+                    return Ok(mschapv2::msk(password, nt));
+                }
+                EAP_FAILURE => {
+                    return Err(VpnError::AuthenticationFailed("EAP-MSCHAPv2 authentication failed".to_string()));
+                }
+                EAP_REQUEST if eap.len() >= 9 && eap[4] == EAP_TYPE_MSCHAPV2 => {}
+                _ => return Err(malformed("unexpected EAP method")),
+            }
+
+            let opcode = eap[5];
+            let reply = match opcode {
+                MSCHAPV2_CHALLENGE => {
+                    let value_size = *eap.get(9).unwrap_or(&0) as usize;
+                    let auth_challenge = eap.get(10..10 + value_size)
+                        .filter(|c| c.len() == 16)
+                        .ok_or_else(|| malformed("invalid MS-CHAPv2 challenge"))?
+                        .to_vec();
+                    let peer_challenge = random_bytes::<16>();
+                    let nt = mschapv2::nt_response(&auth_challenge, &peer_challenge, username, password);
+
+                    let mut value = vec![49];
+                    value.extend_from_slice(&peer_challenge);
+                    value.extend_from_slice(&[0u8; 8]);
+                    value.extend_from_slice(&nt);
+                    value.push(0);
+                    value.extend_from_slice(username.as_bytes());
+                    nt_response = Some((nt, peer_challenge, auth_challenge));
+                    mschapv2_packet(EAP_RESPONSE, identifier, MSCHAPV2_RESPONSE, &value)
+                }
+                MSCHAPV2_SUCCESS => {
+                    let (nt, peer_challenge, auth_challenge) = nt_response.as_ref()
+                        .ok_or_else(|| malformed("MS-CHAPv2 success before challenge"))?;
+                    let expected = mschapv2::authenticator_response(password, nt, peer_challenge, auth_challenge, username);
+                    let message = String::from_utf8_lossy(&eap[9..]);
+                    if !message.starts_with(&expected) {
+                        return Err(VpnError::AuthenticationFailed(
+                            "EAP-MSCHAPv2 server authenticator response mismatch".to_string()
+                        ));
+                    }
+                    eap_packet(EAP_RESPONSE, identifier, &[EAP_TYPE_MSCHAPV2, MSCHAPV2_SUCCESS])
+                }
+                _ => return Err(VpnError::AuthenticationFailed("EAP-MSCHAPv2 authentication failed".to_string())),
+            };
+
+            response = self.request(IKE_AUTH, vec![Payload::new(PAYLOAD_EAP, reply)]).await?;
+        }
+    }
+
+    fn install_child_sa(&mut self, response: &IkeMessage, nonce_i: &[u8], nonce_r: &[u8]) -> Result<()> {
+        let proposal = chosen_proposal(response, PROTOCOL_ESP)?;
+        self.child.spi_out = read_u32(&proposal.spi, 0);
+        let (key_out, key_in) = self.keys.child_keys(nonce_i, nonce_r);
+        self.child.key_out = key_out;
+        self.child.key_in = key_in;
+
+        self.mobike = response.find_notify(NOTIFY_MOBIKE_SUPPORTED).is_some();
+        if let Some(cp) = response.find(PAYLOAD_CP).filter(|cp| cp.body.first() == Some(&CFG_REPLY)) {
+            match cp.body.get(4..) {
+                Some(attributes) => (self.tunnel_address, self.tunnel_address_v6) = parse_internal_addresses(attributes),
+                None => log::debug!("Ignoring truncated CFG_REPLY ({} bytes)", cp.body.len()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn request(&mut self, exchange: u8, payloads: Vec<Payload>) -> Result<IkeMessage> {
+        let message = IkeMessage {
+            spi_i: self.spi_i,
+            spi_r: self.spi_r,
+            exchange,
+            flags: FLAG_INITIATOR,
+            message_id: self.next_message_id,
+            payloads,
+        };
+        let bytes = message.encode(Some(&self.keys.sk_ei))?;
+        let (response, _) = transact(
+            &self.socket,
+            self.encapsulated,
+            &bytes,
+            self.next_message_id,
+            Some(&self.keys.sk_er),
+            &self.config,
+        ).await?;
+        self.next_message_id += 1;
+        response.check_errors()?;
+        Ok(response)
+    }
+
+    /// Encrypts an IP packet into the CHILD_SA and sends it
+    pub async fn send_packet(&mut self, packet: &[u8]) -> Result<()> {
+        let seq = self.child.next_seq;
+        self.child.next_seq = seq.checked_add(1)
+            .ok_or_else(|| VpnError::EncryptionError("ESP sequence number exhausted".to_string()))?;
+        let datagram = esp_seal(self.child.spi_out, seq, &self.child.key_out, packet)?;
+        self.socket.send(&datagram).await
+            .map_err(|e| VpnError::NetworkError(format!("Failed to send ESP packet: {}", e)))?;
+        Ok(())
+    }
+
+    /// Waits for the next IP packet from the CHILD_SA, answering liveness
+    /// checks from the responder while doing so
+    pub async fn recv_packet(&mut self) -> Result<Vec<u8>> {
+        self.receiver().recv().await
+    }
+
+    /// The inbound half of the CHILD_SA, for receiving without holding the
+    /// session. It keeps the current socket, so take a new one after
+    /// `update_addresses`.
+    pub fn receiver(&self) -> EspReceiver {
+        EspReceiver {
+            socket: self.socket.clone(),
+            encapsulated: self.encapsulated,
+            spi_i: self.spi_i,
+            spi_r: self.spi_r,
+            sk_ei: self.keys.sk_ei.clone(),
+            sk_er: self.keys.sk_er.clone(),
+            spi_in: self.child.spi_in,
+            key_in: self.child.key_in.clone(),
+            replay: self.child.replay.clone(),
+            timeout: self.config.timeout,
+        }
+    }
+
+    pub async fn send_keepalive(&self) -> Result<()> {
+        if self.encapsulated {
+            self.socket.send(&[NATT_KEEPALIVE]).await
+                .map_err(|e| VpnError::NetworkError(format!("Failed to send keepalive: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// Moves the SA to a new local address (RFC 4555 MOBIKE), e.g. after
+    /// switching from Wi-Fi to cellular
+    pub async fn update_addresses(&mut self, local: SocketAddr) -> Result<()> {
+        if !self.mobike {
+            return Err(VpnError::ConnectionFailed(
+                "IKEv2 responder does not support MOBIKE".to_string()
+            ));
+        }
+
+        let socket = UdpSocket::bind(local).await
+            .map_err(|e| VpnError::NetworkError(format!("Failed to bind {}: {}", local, e)))?;
+        socket.connect(self.remote).await
+            .map_err(|e| VpnError::NetworkError(format!("Failed to reach {}: {}", self.remote, e)))?;
+        let local = socket.local_addr()
+            .map_err(|e| VpnError::NetworkError(format!("Failed to read local address: {}", e)))?;
+
+        let old = std::mem::replace(&mut self.socket, Arc::new(socket));
+        let payloads = vec![
+            Payload::notify(NOTIFY_UPDATE_SA_ADDRESSES, &[]),
+            Payload::notify(NOTIFY_NAT_DETECTION_SOURCE_IP, &nat_detection_hash(self.spi_i, self.spi_r, local)),
+            Payload::notify(NOTIFY_NAT_DETECTION_DESTINATION_IP, &nat_detection_hash(self.spi_i, self.spi_r, self.remote)),
+        ];
+        if let Err(e) = self.request(INFORMATIONAL, payloads).await {
+            self.socket = old;
+            return Err(e);
+        }
+
+        log::info!("IKEv2 SA moved to local address {}", local);
+        Ok(())
+    }
+
+    /// Deletes the IKE SA (and with it the CHILD_SA) on the responder
+    pub async fn close(mut self) -> Result<()> {
+        self.request(INFORMATIONAL, vec![Payload::delete_ike()]).await.map(|_| ())
+    }
+
+    pub fn tunnel_address(&self) -> Option<Ipv4Addr> {
+        self.tunnel_address
+    }
+
+    /// Only set when the responder carries IPv6 for this client
+    pub fn tunnel_address_v6(&self) -> Option<Ipv6Addr> {
+        self.tunnel_address_v6
+    }
+
+    pub fn nat_status(&self) -> NatStatus {
+        self.nat
+    }
+
+    pub fn mobike_enabled(&self) -> bool {
+        self.mobike
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote
+    }
+}
+
+/// Receives from a session's CHILD_SA; see `Ikev2Session::receiver`
+pub struct EspReceiver {
+    socket: Arc<UdpSocket>,
+    encapsulated: bool,
+    spi_i: u64,
+    spi_r: u64,
+    sk_ei: Vec<u8>,
+    sk_er: Vec<u8>,
+    spi_in: u32,
+    key_in: Vec<u8>,
+    replay: Arc<Mutex<ReplayWindow>>,
+    timeout: Duration,
+}
+
+impl EspReceiver {
+    /// Waits for the next IP packet, answering liveness checks from the
+    /// responder. Datagrams that do not decrypt are dropped, so stray or
+    /// forged traffic cannot end the wait.
+    pub async fn recv(&self) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let len = tokio::time::timeout(self.timeout, self.socket.recv(&mut buf)).await
+                .map_err(|_| VpnError::NetworkError("Timed out waiting for ESP packet".to_string()))?
+                .map_err(|e| VpnError::NetworkError(format!("Failed to receive: {}", e)))?;
+            let datagram = &buf[..len];
+
+            if len == 1 && datagram[0] == NATT_KEEPALIVE {
+                continue;
+            }
+            if self.encapsulated && datagram.starts_with(&NON_ESP_MARKER) {
+                if let Err(e) = self.answer_request(&datagram[4..]).await {
+                    log::debug!("Dropping IKE message: {}", e);
+                }
+                continue;
+            }
+
+            let (spi, seq, packet) = match esp_open(&self.key_in, datagram) {
+                Ok(opened) => opened,
+                Err(e) => {
+                    log::debug!("Dropping ESP packet: {}", e);
+                    continue;
+                }
+            };
+            if spi != self.spi_in || !self.replay.lock().unwrap().accept(seq) {
+                log::debug!("Dropping ESP packet (spi 0x{:08x}, seq {})", spi, seq);
+                continue;
+            }
+            return Ok(packet);
+        }
+    }
+
+    async fn answer_request(&self, bytes: &[u8]) -> Result<()> {
+        let request = IkeMessage::decode(bytes, Some(&self.sk_er))?;
+        if request.is_response() || request.exchange != INFORMATIONAL {
+            return Ok(());
+        }
+        let reply = IkeMessage {
+            spi_i: self.spi_i,
+            spi_r: self.spi_r,
+            exchange: INFORMATIONAL,
+            flags: FLAG_INITIATOR | FLAG_RESPONSE,
+            message_id: request.message_id,
+            payloads: Vec::new(),
+        };
+        send_ike(&self.socket, self.encapsulated, &reply.encode(Some(&self.sk_ei))?).await
+    }
+}
+
+fn parse_internal_addresses(mut attributes: &[u8]) -> (Option<Ipv4Addr>, Option<Ipv6Addr>) {
+    let (mut ipv4, mut ipv6) = (None, None);
+    while attributes.len() >= 4 {
+        let kind = read_u16(attributes, 0) & 0x7fff;
+        let len = read_u16(attributes, 2) as usize;
+        let Some(value) = attributes.get(4..4 + len) else { break };
+        if kind == INTERNAL_IP4_ADDRESS && len >= 4 {
+            ipv4 = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]));
+        } else if kind == INTERNAL_IP6_ADDRESS && len >= 17 {
+            // 16 address bytes followed by the prefix length
+            let octets: [u8; 16] = value[..16].try_into().unwrap();
+            ipv6 = Some(Ipv6Addr::from(octets));
+        }
+        attributes = &attributes[4 + len..];
+    }
+    (ipv4, ipv6)
+}
+
+async fn bind_socket(remote: SocketAddr) -> Result<UdpSocket> {
+    let local: SocketAddr = if remote.is_ipv4() {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local).await
+        .map_err(|e| VpnError::NetworkError(format!("Failed to bind UDP socket: {}", e)))?;
+    socket.connect(remote).await
+        .map_err(|e| VpnError::NetworkError(format!("Failed to reach {}: {}", remote, e)))?;
+    Ok(socket)
+}
+
+async fn send_ike(socket: &UdpSocket, encapsulated: bool, bytes: &[u8]) -> Result<()> {
+    let mut datagram = Vec::with_capacity(bytes.len() + 4);
+    if encapsulated {
+        datagram.extend_from_slice(&NON_ESP_MARKER);
+    }
+    datagram.extend_from_slice(bytes);
+    socket.send(&datagram).await
+        .map_err(|e| VpnError::NetworkError(format!("Failed to send IKEv2 message: {}", e)))?;
+    Ok(())
+}
+
+/// Sends a request and waits for the matching response, retransmitting on
+/// timeout. Returns the decoded response and its raw bytes.
+async fn transact(
+    socket: &UdpSocket,
+    encapsulated: bool,
+    request: &[u8],
+    message_id: u32,
+    recv_key: Option<&[u8]>,
+    config: &Ikev2Config,
+) -> Result<(IkeMessage, Vec<u8>)> {
+    let mut buf = vec![0u8; 65536];
+    for attempt in 0..=config.retransmits {
+        if attempt > 0 {
+            log::debug!("Retransmitting IKEv2 request {} (attempt {})", message_id, attempt + 1);
+        }
+        send_ike(socket, encapsulated, request).await?;
+
+        let deadline = tokio::time::Instant::now() + config.timeout;
+        loop {
+            let received = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await;
+            let len = match received {
+                Err(_) => break,
+                Ok(Err(e)) => return Err(VpnError::NetworkError(format!("Failed to receive: {}", e))),
+                Ok(Ok(len)) => len,
+            };
+
+            let datagram = if encapsulated {
+                match buf[..len].strip_prefix(&NON_ESP_MARKER) {
+                    Some(ike) => ike,
+                    None => continue, // Keepalive or ESP
+                }
+            } else {
+                &buf[..len]
+            };
+
+            match IkeMessage::decode(datagram, recv_key) {
+                Ok(message) if message.is_response() && message.message_id == message_id => {
+                    return Ok((message, datagram.to_vec()));
+                }
+                Ok(_) => continue,
+                Err(e) => log::debug!("Ignoring IKEv2 datagram: {}", e),
+            }
+        }
+    }
+
+    Err(VpnError::ConnectionFailed(format!(
+        "No IKEv2 response after {} attempts",
+        config.retransmits + 1
+    )))
+}
+
+#[cfg(test)]
+pub(crate) mod stub {
+    //! Minimal IKEv2 responder used to exercise the initiator locally
+
+    use super::*;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    pub(crate) enum StubAuth {
+        Certificate { trusted: VerifyingKey },
+        EapMschapv2 { username: String, password: String },
+    }
+
+    #[derive(Default)]
+    pub(crate) struct StubState {
+        pub peer: Option<SocketAddr>,
+        pub address_updates: u32,
+        pub deleted: bool,
+        pub echoed_packets: u32,
+    }
+
+    pub(crate) struct ResponderStub {
+        pub ike_addr: SocketAddr,
+        pub natt_addr: SocketAddr,
+        pub key: VerifyingKey,
+        pub state: Arc<Mutex<StubState>>,
+    }
+
+    struct Exchange {
+        spi_i: u64,
+        spi_r: u64,
+        keys: Option<IkeKeys>,
+        nonce_i: Vec<u8>,
+        nonce_r: Vec<u8>,
+        init_request: Vec<u8>,
+        init_response: Vec<u8>,
+        id_i: Option<Payload>,
+        spi_child_i: u32,
+        spi_child_r: u32,
+        mobike: bool,
+        auth_challenge: [u8; 16],
+        nt_response: Option<[u8; 24]>,
+        child_keys: Option<(Vec<u8>, Vec<u8>)>,
+        esp_seq: u32,
+        last_response: Option<(u32, Vec<u8>)>,
+    }
+
+    pub(crate) async fn spawn(auth: StubAuth) -> ResponderStub {
+        let ike = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let natt = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let signing_key = SigningKey::from_bytes(&random_bytes::<32>());
+        let stub = ResponderStub {
+            ike_addr: ike.local_addr().unwrap(),
+            natt_addr: natt.local_addr().unwrap(),
+            key: signing_key.verifying_key(),
+            state: Arc::new(Mutex::new(StubState::default())),
+        };
+
+        let state = stub.state.clone();
+        tokio::spawn(async move {
+            let mut exchange = Exchange {
+                spi_i: 0,
+                spi_r: random_spi(),
+                keys: None,
+                nonce_i: Vec::new(),
+                nonce_r: random_bytes::<32>().to_vec(),
+                init_request: Vec::new(),
+                init_response: Vec::new(),
+                id_i: None,
+                spi_child_i: 0,
+                spi_child_r: OsRng.next_u32() | 1,
+                mobike: false,
+                auth_challenge: random_bytes::<16>(),
+                nt_response: None,
+                child_keys: None,
+                esp_seq: 1,
+                last_response: None,
+            };
+            let mut ike_buf = vec![0u8; 65536];
+            let mut natt_buf = vec![0u8; 65536];
+            loop {
+                let (socket, datagram, from, encapsulated) = tokio::select! {
+                    Ok((len, from)) = ike.recv_from(&mut ike_buf) => (&ike, ike_buf[..len].to_vec(), from, false),
+                    Ok((len, from)) = natt.recv_from(&mut natt_buf) => (&natt, natt_buf[..len].to_vec(), from, true),
+                };
+
+                let reply = if encapsulated && !datagram.starts_with(&NON_ESP_MARKER) {
+                    echo_esp(&mut exchange, &datagram, &state).await
+                } else {
+                    let ike_bytes = if encapsulated { &datagram[4..] } else { &datagram[..] };
+                    let local = socket.local_addr().unwrap();
+                    handle_ike(&mut exchange, &auth, &signing_key, ike_bytes, local, from, &state).await
+                        .map(|bytes| if encapsulated { [NON_ESP_MARKER.to_vec(), bytes].concat() } else { bytes })
+                };
+                if let Some(reply) = reply {
+                    let _ = socket.send_to(&reply, from).await;
+                }
+            }
+        });
+        stub
+    }
+
+    async fn echo_esp(exchange: &mut Exchange, datagram: &[u8], state: &Arc<Mutex<StubState>>) -> Option<Vec<u8>> {
+        if datagram.len() == 1 {
+            return None;
+        }
+        let (key_i, key_r) = exchange.child_keys.as_ref()?;
+        let (_, _, packet) = esp_open(key_i, datagram).ok()?;
+        let reply = esp_seal(exchange.spi_child_i, exchange.esp_seq, key_r, &packet).ok()?;
+        exchange.esp_seq += 1;
+        state.lock().await.echoed_packets += 1;
+        Some(reply)
+    }
+
+    async fn handle_ike(
+        exchange: &mut Exchange,
+        auth: &StubAuth,
+        signing_key: &SigningKey,
+        bytes: &[u8],
+        local: SocketAddr,
+        from: SocketAddr,
+        state: &Arc<Mutex<StubState>>,
+    ) -> Option<Vec<u8>> {
+        let keys = exchange.keys.clone();
+        let request = IkeMessage::decode(bytes, keys.as_ref().map(|k| k.sk_ei.as_slice())).ok()?;
+        if request.is_response() {
+            return None;
+        }
+        if let Some((mid, cached)) = &exchange.last_response {
+            if *mid == request.message_id {
+                return Some(cached.clone());
+            }
+        }
+
+        let payloads = match (request.exchange, request.message_id) {
+            (IKE_SA_INIT, 0) => {
+                let response = sa_init(exchange, &request, bytes, local, from);
+                exchange.last_response = Some((0, response.clone()));
+                return Some(response);
+            }
+            (IKE_AUTH, _) => ike_auth(exchange, auth, signing_key, &request),
+            (INFORMATIONAL, _) => {
+                let mut state = state.lock().await;
+                if request.find_notify(NOTIFY_UPDATE_SA_ADDRESSES).is_some() {
+                    state.address_updates += 1;
+                }
+                if request.find(PAYLOAD_DELETE).is_some() {
+                    state.deleted = true;
+                }
+                Vec::new()
+            }
+            _ => return None,
+        };
+        state.lock().await.peer = Some(from);
+
+        let response = IkeMessage {
+            spi_i: exchange.spi_i,
+            spi_r: exchange.spi_r,
+            exchange: request.exchange,
+            flags: FLAG_RESPONSE,
+            message_id: request.message_id,
+            payloads,
+        };
+        let bytes = response.encode(exchange.keys.as_ref().map(|k| k.sk_er.as_slice())).ok()?;
+        exchange.last_response = Some((request.message_id, bytes.clone()));
+        Some(bytes)
+    }
+
+    fn sa_init(exchange: &mut Exchange, request: &IkeMessage, bytes: &[u8], local: SocketAddr, from: SocketAddr) -> Vec<u8> {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public = X25519PublicKey::from(&secret);
+        let peer: [u8; 32] = request.find(PAYLOAD_KE).unwrap().data().try_into().unwrap();
+        let shared = secret.diffie_hellman(&X25519PublicKey::from(peer));
+
+        exchange.spi_i = request.spi_i;
+        exchange.nonce_i = request.find(PAYLOAD_NONCE).unwrap().body.clone();
+        exchange.init_request = bytes.to_vec();
+        exchange.keys = Some(IkeKeys::derive(&exchange.nonce_i, &exchange.nonce_r, shared.as_bytes(), exchange.spi_i, exchange.spi_r));
+
+        let response = IkeMessage {
+            spi_i: exchange.spi_i,
+            spi_r: exchange.spi_r,
+            exchange: IKE_SA_INIT,
+            flags: FLAG_RESPONSE,
+            message_id: 0,
+            payloads: vec![
+                encode_proposal(PROTOCOL_IKE, &[]),
+                Payload::key_exchange(public.as_bytes()),
+                Payload::nonce(&exchange.nonce_r),
+                Payload::notify(NOTIFY_NAT_DETECTION_SOURCE_IP, &nat_detection_hash(exchange.spi_i, exchange.spi_r, local)),
+                Payload::notify(NOTIFY_NAT_DETECTION_DESTINATION_IP, &nat_detection_hash(exchange.spi_i, exchange.spi_r, from)),
+            ],
+        };
+        exchange.init_response = response.encode(None).unwrap();
+        exchange.init_response.clone()
+    }
+
+    fn ike_auth(exchange: &mut Exchange, auth: &StubAuth, signing_key: &SigningKey, request: &IkeMessage) -> Vec<Payload> {
+        let keys = exchange.keys.clone().unwrap();
+        let id_r = Payload::identification(PAYLOAD_IDR, ID_FQDN, b"vpn.stub.test");
+        let octets_r = signed_octets(&exchange.init_response, &exchange.nonce_i, &keys.sk_pr, &id_r);
+        let auth_failed = vec![Payload::notify(NOTIFY_AUTHENTICATION_FAILED, &[])];
+
+        // First IKE_AUTH request carries IDi and the CHILD_SA proposal
+        if let Some(id_i) = request.find(PAYLOAD_IDI) {
+            exchange.id_i = Some(id_i.clone());
+            exchange.mobike = request.find_notify(NOTIFY_MOBIKE_SUPPORTED).is_some();
+            let proposal = chosen_proposal(request, PROTOCOL_ESP).unwrap();
+            exchange.spi_child_i = read_u32(&proposal.spi, 0);
+        }
+        let id_i = exchange.id_i.clone().unwrap();
+        let octets_i = signed_octets(&exchange.init_request, &exchange.nonce_r, &keys.sk_pi, &id_i);
+
+        match auth {
+            StubAuth::Certificate { trusted } => {
+                let cert_key = request.find(PAYLOAD_CERT).and_then(|c| certificate_key(c).ok());
+                let valid = cert_key == Some(*trusted)
+                    && request.find(PAYLOAD_AUTH)
+                        .map(|a| verify_signature_auth(trusted, a, &octets_i).is_ok())
+                        .unwrap_or(false);
+                if !valid {
+                    return auth_failed;
+                }
+                let mut payloads = vec![
+                    id_r,
+                    Payload::certificate(&signing_key.verifying_key()),
+                    signature_auth(signing_key, &octets_r),
+                ];
+                payloads.extend(child_sa(exchange, &keys));
+                payloads
+            }
+            StubAuth::EapMschapv2 { username, password } => {
+                if request.find(PAYLOAD_IDI).is_some() {
+                    let mut value = vec![16];
+                    value.extend_from_slice(&exchange.auth_challenge);
+                    value.extend_from_slice(b"stub");
+                    let challenge = mschapv2_packet(EAP_REQUEST, 1, MSCHAPV2_CHALLENGE, &value);
+                    return vec![id_r, signature_auth(signing_key, &octets_r), Payload::new(PAYLOAD_EAP, challenge)];
+                }
+
+                if let Some(auth_payload) = request.find(PAYLOAD_AUTH) {
+                    let msk = mschapv2::msk(password, &exchange.nt_response.unwrap());
+                    if auth_payload.data() != msk_auth(&msk, &octets_i).as_slice() {
+                        return auth_failed;
+                    }
+                    let mut payloads = vec![Payload::auth(AUTH_SHARED_KEY_MIC, &msk_auth(&msk, &octets_r))];
+                    payloads.extend(child_sa(exchange, &keys));
+                    return payloads;
+                }
+
+                let eap = request.find(PAYLOAD_EAP).unwrap().body.clone();
+                match eap.get(5) {
+                    Some(&MSCHAPV2_RESPONSE) => {
+                        let peer_challenge = &eap[10..26];
+                        let nt = &eap[34..58];
+                        let expected = mschapv2::nt_response(&exchange.auth_challenge, peer_challenge, username, password);
+                        if nt != expected || &eap[59..] != username.as_bytes() {
+                            return vec![Payload::new(PAYLOAD_EAP, eap_packet(EAP_FAILURE, 2, &[]))];
+                        }
+                        exchange.nt_response = Some(expected);
+                        let message = mschapv2::authenticator_response(password, nt, peer_challenge, &exchange.auth_challenge, username);
+                        let success = mschapv2_packet(EAP_REQUEST, 2, MSCHAPV2_SUCCESS, format!("{} M=Welcome", message).as_bytes());
+                        vec![Payload::new(PAYLOAD_EAP, success)]
+                    }
+                    Some(&MSCHAPV2_SUCCESS) => vec![Payload::new(PAYLOAD_EAP, eap_packet(EAP_SUCCESS, 3, &[]))],
+                    _ => auth_failed,
+                }
+            }
+        }
+    }
+
+    fn child_sa(exchange: &mut Exchange, keys: &IkeKeys) -> Vec<Payload> {
+        exchange.child_keys = Some(keys.child_keys(&exchange.nonce_i, &exchange.nonce_r));
+
+        let mut cp = vec![CFG_REPLY, 0, 0, 0];
+        cp.extend_from_slice(&INTERNAL_IP4_ADDRESS.to_be_bytes());
+        cp.extend_from_slice(&4u16.to_be_bytes());
+        cp.extend_from_slice(&[10, 10, 0, 2]);
+        cp.extend_from_slice(&INTERNAL_IP6_ADDRESS.to_be_bytes());
+        cp.extend_from_slice(&17u16.to_be_bytes());
+        cp.extend_from_slice(&"fd00:10::2".parse::<Ipv6Addr>().unwrap().octets());
+        cp.push(64);
+
+        let mut payloads = vec![
+            Payload::new(PAYLOAD_CP, cp),
+            encode_proposal(PROTOCOL_ESP, &exchange.spi_child_r.to_be_bytes()),
+            Payload::traffic_selectors(PAYLOAD_TSI),
+            Payload::traffic_selectors(PAYLOAD_TSR),
+        ];
+        if exchange.mobike {
+            payloads.push(Payload::notify(NOTIFY_MOBIKE_SUPPORTED, &[]));
+        }
+        payloads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::stub::{spawn, StubAuth};
+
+    fn config_for(stub: &stub::ResponderStub, auth: IkeAuth) -> Ikev2Config {
+        Ikev2Config::new(auth, "client.vpn.test")
+            .with_natt_port(stub.natt_addr.port())
+            .with_timeout(Duration::from_millis(500))
+    }
+
+    #[test]
+    fn test_mschapv2_rfc2759_vectors() {
+        let auth_challenge = [0x5B, 0x5D, 0x7C, 0x7D, 0x7B, 0x3F, 0x2F, 0x3E, 0x3C, 0x2C, 0x60, 0x21, 0x32, 0x26, 0x26, 0x28];
+        let peer_challenge = [0x21, 0x40, 0x23, 0x24, 0x25, 0x5E, 0x26, 0x2A, 0x28, 0x29, 0x5F, 0x2B, 0x3A, 0x33, 0x7C, 0x7E];
+
+        assert_eq!(
+            mschapv2::nt_password_hash("clientPass"),
+            [0x44, 0xEB, 0xBA, 0x8D, 0x53, 0x12, 0xB8, 0xD6, 0x11, 0x47, 0x44, 0x11, 0xF5, 0x69, 0x89, 0xAE]
+        );
+
+        let nt = mschapv2::nt_response(&auth_challenge, &peer_challenge, "User", "clientPass");
+        assert_eq!(nt, [
+            0x82, 0x30, 0x9E, 0xCD, 0x8D, 0x70, 0x8B, 0x5E, 0xA0, 0x8F, 0xAA, 0x39,
+            0x81, 0xCD, 0x83, 0x54, 0x42, 0x33, 0x11, 0x4A, 0x3D, 0x85, 0xD6, 0xDF,
+        ]);
+
+        assert_eq!(
+            mschapv2::authenticator_response("clientPass", &nt, &peer_challenge, &auth_challenge, "User"),
+            "S=407A5589115FD0D6209F510FE9C04566932CDA56"
+        );
+    }
+
+    #[test]
+    fn test_message_roundtrip() {
+        let key = random_bytes::<36>();
+        let message = IkeMessage {
+            spi_i: 1,
+            spi_r: 2,
+            exchange: IKE_AUTH,
+            flags: FLAG_INITIATOR,
+            message_id: 7,
+            payloads: vec![
+                Payload::nonce(&[9; 32]),
+                Payload::notify(NOTIFY_MOBIKE_SUPPORTED, &[]),
+            ],
+        };
+
+        let plain = IkeMessage::decode(&message.encode(None).unwrap(), None).unwrap();
+        assert_eq!(plain.payloads.len(), 2);
+
+        let encrypted = message.encode(Some(&key)).unwrap();
+        let decoded = IkeMessage::decode(&encrypted, Some(&key)).unwrap();
+        assert_eq!(decoded.message_id, 7);
+        assert!(decoded.find_notify(NOTIFY_MOBIKE_SUPPORTED).is_some());
+
+        let mut tampered = encrypted.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(IkeMessage::decode(&tampered, Some(&key)).is_err());
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::default();
+        assert!(window.accept(1));
+        assert!(window.accept(3));
+        assert!(!window.accept(3));
+        assert!(window.accept(2));
+        assert!(window.accept(100));
+        assert!(!window.accept(20));
+    }
+
+    #[tokio::test]
+    async fn test_receiver_drops_garbage() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(peer.local_addr().unwrap()).await.unwrap();
+        peer.connect(socket.local_addr().unwrap()).await.unwrap();
+
+        let key = random_bytes::<GCM_KEY_LEN>().to_vec();
+        let receiver = EspReceiver {
+            socket: Arc::new(socket),
+            encapsulated: true,
+            spi_i: 1,
+            spi_r: 2,
+            sk_ei: key.clone(),
+            sk_er: key.clone(),
+            spi_in: 7,
+            key_in: key.clone(),
+            replay: Arc::default(),
+            timeout: Duration::from_secs(2),
+        };
+
+        let packet = [0x45, 0, 0, 20, 1, 2, 3, 4];
+        peer.send(&[0xde, 0xad, 0xbe, 0xef, 0, 0, 0, 1, 2, 3]).await.unwrap();
+        peer.send(&[NON_ESP_MARKER.as_slice(), &[0xff; 40]].concat()).await.unwrap();
+        peer.send(&esp_seal(7, 1, &key, &packet).unwrap()).await.unwrap();
+        assert_eq!(receiver.recv().await.unwrap(), packet.to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_certificate_auth_and_esp() {
+        let client_key = SigningKey::from_bytes(&random_bytes::<32>());
+        let stub = spawn(StubAuth::Certificate { trusted: client_key.verifying_key() }).await;
+        let auth = IkeAuth::Certificate { signing_key: client_key, responder_key: stub.key };
+
+        let mut session = Ikev2Session::connect(stub.ike_addr, config_for(&stub, auth)).await.unwrap();
+        assert_eq!(session.tunnel_address(), Some(Ipv4Addr::new(10, 10, 0, 2)));
+        assert_eq!(session.tunnel_address_v6(), Some("fd00:10::2".parse().unwrap()));
+        assert!(session.nat_status().encapsulated);
+        assert_eq!(session.remote_addr(), stub.natt_addr);
+        assert!(session.mobike_enabled());
+
+        let packet = [0x45, 0, 0, 20, 1, 2, 3, 4, 64, 17, 0, 0, 10, 10, 0, 2, 1, 1, 1, 1];
+        session.send_packet(&packet).await.unwrap();
+        assert_eq!(session.recv_packet().await.unwrap(), packet.to_vec());
+
+        session.close().await.unwrap();
+        assert!(stub.state.lock().await.deleted);
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_certificate_is_rejected() {
+        let stub = spawn(StubAuth::Certificate {
+            trusted: SigningKey::from_bytes(&random_bytes::<32>()).verifying_key(),
+        }).await;
+        let auth = IkeAuth::Certificate {
+            signing_key: SigningKey::from_bytes(&random_bytes::<32>()),
+            responder_key: stub.key,
+        };
+
+        let result = Ikev2Session::connect(stub.ike_addr, config_for(&stub, auth)).await;
+        assert!(matches!(result, Err(VpnError::AuthenticationFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_wrong_responder_key_is_rejected() {
+        let client_key = SigningKey::from_bytes(&random_bytes::<32>());
+        let stub = spawn(StubAuth::Certificate { trusted: client_key.verifying_key() }).await;
+        let auth = IkeAuth::Certificate {
+            signing_key: client_key,
+            responder_key: SigningKey::from_bytes(&random_bytes::<32>()).verifying_key(),
+        };
+
+        let result = Ikev2Session::connect(stub.ike_addr, config_for(&stub, auth)).await;
+        assert!(matches!(result, Err(VpnError::AuthenticationFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_eap_mschapv2_auth() {
+        let stub = spawn(StubAuth::EapMschapv2 {
+            username: "alice".to_string(),
+            password: "correct horse".to_string(),
+        }).await;
+
+        let auth = IkeAuth::EapMschapv2 {
+            username: "alice".to_string(),
+            password: "correct horse".to_string(),
+            responder_key: stub.key,
+        };
+        let session = Ikev2Session::connect(stub.ike_addr, config_for(&stub, auth)).await.unwrap();
+        assert_eq!(session.tunnel_address(), Some(Ipv4Addr::new(10, 10, 0, 2)));
+
+        let auth = IkeAuth::EapMschapv2 {
+            username: "alice".to_string(),
+            password: "wrong".to_string(),
+            responder_key: stub.key,
+        };
+        let result = Ikev2Session::connect(stub.ike_addr, config_for(&stub, auth)).await;
+        assert!(matches!(result, Err(VpnError::AuthenticationFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn test_mobike_address_update() {
+        let client_key = SigningKey::from_bytes(&random_bytes::<32>());
+        let stub = spawn(StubAuth::Certificate { trusted: client_key.verifying_key() }).await;
+        let auth = IkeAuth::Certificate { signing_key: client_key, responder_key: stub.key };
+
+        let mut session = Ikev2Session::connect(stub.ike_addr, config_for(&stub, auth)).await.unwrap();
+        let before = session.local_addr().unwrap();
+
+        session.update_addresses("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let after = session.local_addr().unwrap();
+        assert_ne!(before, after);
+
+        let state = stub.state.lock().await;
+        assert_eq!(state.address_updates, 1);
+        assert_eq!(state.peer, Some(after));
+        drop(state);
+
+        let packet = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 6, 0, 0, 10, 10, 0, 2, 8, 8, 8, 8];
+        session.send_packet(&packet).await.unwrap();
+        assert_eq!(session.recv_packet().await.unwrap(), packet.to_vec());
+    }
+}
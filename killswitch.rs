@@ -1,4 +1,6 @@
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use crate::net::{lan_networks, IpNetwork};
 use crate::{Result, VpnError};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -9,6 +11,7 @@ pub enum KillSwitchMode {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct KillSwitchConfig {
     pub mode: KillSwitchMode,
     pub allow_lan: bool,
@@ -30,6 +33,8 @@ impl Default for KillSwitchConfig {
 pub struct KillSwitch {
     config: KillSwitchConfig,
     active: bool,
+    block_ipv6: bool,  // Set while the tunnel has no IPv6
+    locked_mode: Option<KillSwitchMode>,  // Fixed by a managed policy
 }
 
 impl KillSwitch {
@@ -37,6 +42,8 @@ impl KillSwitch {
         Self {
             config,
             active: false,
+            block_ipv6: false,
+            locked_mode: None,
         }
     }
 
@@ -54,14 +61,18 @@ impl KillSwitch {
         // 2. Block all non-VPN traffic
         // 3. Allow exceptions (LAN, specific IPs/apps)
         
-        self.active = true;
         self.apply_firewall_rules()?;
+        self.active = true;
         
         log::info!("Kill switch enabled successfully");
         Ok(())
     }
 
+    /// Refused while a managed policy requires the kill switch
     pub fn disable(&mut self) -> Result<()> {
+        if self.locked_mode.is_some_and(|mode| mode != KillSwitchMode::Disabled) {
+            return Err(self.locked());
+        }
         log::info!("Disabling kill switch");
         
         // Remove firewall rules
@@ -76,8 +87,52 @@ impl KillSwitch {
         self.active
     }
 
-    pub fn set_mode(&mut self, mode: KillSwitchMode) {
+    /// Refused while a managed policy fixes another mode
+    pub fn set_mode(&mut self, mode: KillSwitchMode) -> Result<()> {
+        if self.locked_mode.is_some_and(|locked| locked != mode) {
+            return Err(self.locked());
+        }
         self.config.mode = mode;
+        Ok(())
+    }
+
+    /// Pins the mode, as required by a managed policy; None lifts the lock
+    pub fn set_locked_mode(&mut self, mode: Option<KillSwitchMode>) {
+        if let Some(mode) = mode {
+            self.config.mode = mode;
+        }
+        self.locked_mode = mode;
+    }
+
+    fn locked(&self) -> VpnError {
+        VpnError::PolicyViolation("The kill switch is set by your organization's policy".to_string())
+    }
+
+    pub fn config(&self) -> &KillSwitchConfig {
+        &self.config
+    }
+
+    /// Swaps in new rules. While active the firewall is updated at once,
+    /// and a mode of Disabled turns the kill switch off. If the new rules
+    /// cannot be applied the old ones stay.
+    pub fn update_config(&mut self, config: KillSwitchConfig) -> Result<()> {
+        if self.locked_mode.is_some_and(|locked| locked != config.mode) {
+            return Err(self.locked());
+        }
+        let previous = std::mem::replace(&mut self.config, config);
+        if !self.active {
+            return Ok(());
+        }
+        if self.config.mode == KillSwitchMode::Disabled {
+            return self.disable();
+        }
+
+        log::info!("Updating kill switch rules (mode: {:?})", self.config.mode);
+        if let Err(e) = self.apply_firewall_rules() {
+            self.config = previous;
+            return Err(e);
+        }
+        Ok(())
     }
 
     pub fn get_mode(&self) -> KillSwitchMode {
@@ -108,23 +163,62 @@ impl KillSwitch {
         self.config.allowed_apps.retain(|a| a != app);
     }
 
+    /// Drops all IPv6 outside the tunnel, except LAN traffic if allowed.
+    /// Used when the connected server has no IPv6 to carry it.
+    pub fn set_ipv6_blackhole(&mut self, block: bool) {
+        if block == self.block_ipv6 {
+            return;
+        }
+        self.block_ipv6 = block;
+        if block {
+            log::info!("Blocking IPv6 traffic outside the tunnel");
+        } else {
+            log::info!("No longer blocking IPv6 traffic");
+        }
+    }
+
+    pub fn is_blocking_ipv6(&self) -> bool {
+        self.block_ipv6
+    }
+
+    /// Whether traffic to `ip` may leave outside the tunnel
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        let blocked = self.active || (self.block_ipv6 && ip.is_ipv6());
+        if !blocked {
+            return true;
+        }
+
+        if self.config.allow_lan && lan_networks().iter().any(|n| n.contains(ip)) {
+            return true;
+        }
+        self.config.allowed_ips.iter()
+            .filter_map(|s| s.parse::<IpNetwork>().ok())
+            .any(|n| n.contains(ip))
+    }
+
+    fn allowed_networks(&self) -> Result<Vec<IpNetwork>> {
+        self.config.allowed_ips.iter().map(|ip| ip.parse()).collect()
+    }
+
     fn apply_firewall_rules(&self) -> Result<()> {
         log::info!("Applying firewall rules");
         
-        // Base rule: block all traffic
-        log::debug!("Blocking all non-VPN traffic");
+        // Base rule: block all traffic, for both address families
+        log::debug!("Blocking all non-VPN IPv4 and IPv6 traffic");
         
         // Allow VPN server connections
         log::debug!("Allowing VPN server connections");
         
         // Allow LAN if configured
         if self.config.allow_lan {
-            log::debug!("Allowing LAN traffic (192.168.0.0/16, 10.0.0.0/8, 172.16.0.0/12)");
+            for network in lan_networks() {
+                log::debug!("Allowing LAN traffic ({})", network);
+            }
         }
         
         // Allow specific IPs
-        for ip in &self.config.allowed_ips {
-            log::debug!("Allowing traffic to/from: {}", ip);
+        for network in self.allowed_networks()? {
+            log::debug!("Allowing traffic to/from: {}", network);
         }
         
         // Allow specific apps
@@ -208,4 +302,43 @@ mod tests {
         assert_eq!(kill_switch.config.allowed_ips.len(), 1);
         assert_eq!(kill_switch.config.allowed_apps.len(), 1);
     }
+
+    #[test]
+    fn test_kill_switch_ipv6_rules() {
+        let config = KillSwitchConfig::default();
+        let mut kill_switch = KillSwitch::new(config);
+        kill_switch.add_allowed_ip("2001:db8:1::/48".to_string());
+
+        // Blackhole on its own only affects IPv6
+        kill_switch.set_ipv6_blackhole(true);
+        assert!(kill_switch.allows("93.184.216.34".parse().unwrap()));
+        assert!(!kill_switch.allows("2606:2800:220:1::1".parse().unwrap()));
+        assert!(kill_switch.allows("fe80::1".parse().unwrap()));
+        assert!(kill_switch.allows("2001:db8:1::5".parse().unwrap()));
+
+        kill_switch.enable().unwrap();
+        assert!(!kill_switch.allows("93.184.216.34".parse().unwrap()));
+        assert!(kill_switch.allows("192.168.1.10".parse().unwrap()));
+
+        kill_switch.disable().unwrap();
+        kill_switch.add_allowed_ip("not-an-ip".to_string());
+        assert!(kill_switch.enable().is_err());
+        assert!(!kill_switch.is_active());
+    }
+
+    #[test]
+    fn test_locked_mode_cannot_change() {
+        let mut kill_switch = KillSwitch::new(KillSwitchConfig::default());
+        kill_switch.set_locked_mode(Some(KillSwitchMode::Always));
+        assert_eq!(kill_switch.get_mode(), KillSwitchMode::Always);
+        kill_switch.enable().unwrap();
+
+        assert!(kill_switch.disable().is_err());
+        assert!(kill_switch.set_mode(KillSwitchMode::Disabled).is_err());
+        assert!(kill_switch.update_config(KillSwitchConfig::default()).is_err());
+        assert!(kill_switch.is_active());
+
+        kill_switch.set_locked_mode(None);
+        kill_switch.disable().unwrap();
+    }
 }
@@ -0,0 +1,540 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use crate::config::{VpnConfig, CONFIG_BACKUPS};
+use crate::format::ConfigFormat;
+use crate::migration;
+use crate::policy::ManagedPolicy;
+use crate::storage::load_newest_backup;
+use crate::{Result, VpnError};
+
+const ENV_PREFIX: &str = "VPN_MOBILE_";
+
+// (key, raw value) pairs from --set, in order
+type Overrides = Vec<(String, String)>;
+
+/// Where a setting came from, lowest precedence first
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigLayer {
+    Default,
+    System,       // Machine-wide file, see VpnConfig::get_system_config_path
+    User,         // The user's config.json or active profile
+    Environment,  // VPN_MOBILE_* variables
+    CommandLine,  // --set key=value
+    Policy,       // Locked by an administrator, see ManagedPolicy
+}
+
+impl ConfigLayer {
+    pub fn name(&self) -> &str {
+        match self {
+            ConfigLayer::Default => "default",
+            ConfigLayer::System => "system file",
+            ConfigLayer::User => "user config",
+            ConfigLayer::Environment => "environment",
+            ConfigLayer::CommandLine => "command line",
+            ConfigLayer::Policy => "managed policy",
+        }
+    }
+}
+
+impl fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Builds a `VpnConfig` from defaults, then a system-wide file, then the
+/// user's file, then `VPN_MOBILE_*` environment variables, then command-line
+/// flags. Each layer only needs the keys it changes. Settings locked by a
+/// managed policy win over all of them.
+///
+/// Keys are dotted paths into the JSON config, e.g. `kill_switch.mode`. In
+/// environment variable names `__` separates path segments, so
+/// `VPN_MOBILE_KILL_SWITCH__MODE=Always` sets `kill_switch.mode`. Values are
+/// JSON; plain words are taken as strings.
+///
+/// Files from older versions are upgraded in memory only;
+/// `VpnConfig::load_from_file` is what rewrites them on disk.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLoader {
+    system_path: Option<PathBuf>,
+    policy_path: Option<PathBuf>,
+    user_path: Option<PathBuf>,
+    user_config: Option<VpnConfig>,  // Replaces the user file, e.g. the active profile
+    env: Vec<(String, String)>,
+    args: Vec<String>,
+}
+
+impl ConfigLoader {
+    /// Defaults only; add layers with the `with_*` methods
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The standard files plus this process's environment and `args`
+    pub fn standard(args: impl IntoIterator<Item = String>) -> Self {
+        let mut loader = Self::new()
+            .with_user_file(VpnConfig::get_config_path())
+            .with_env(std::env::vars())
+            .with_args(args);
+        loader.system_path = VpnConfig::get_system_config_path();
+        loader.policy_path = VpnConfig::get_policy_path();
+        loader
+    }
+
+    /// Skipped if the file does not exist
+    pub fn with_system_file(mut self, path: PathBuf) -> Self {
+        self.system_path = Some(path);
+        self
+    }
+
+    /// Skipped if the file does not exist
+    pub fn with_policy_file(mut self, path: PathBuf) -> Self {
+        self.policy_path = Some(path);
+        self
+    }
+
+    /// Skipped if the file does not exist
+    pub fn with_user_file(mut self, path: PathBuf) -> Self {
+        self.user_path = Some(path);
+        self
+    }
+
+    /// Uses `config` as the user layer instead of a file
+    pub fn with_user_config(mut self, config: VpnConfig) -> Self {
+        self.user_config = Some(config);
+        self
+    }
+
+    /// Variables without the `VPN_MOBILE_` prefix are ignored
+    pub fn with_env(mut self, vars: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.env = vars.into_iter().filter(|(name, _)| name.starts_with(ENV_PREFIX)).collect();
+        self
+    }
+
+    /// `--set key=value` (repeatable) and `--config PATH`, which replaces
+    /// the user file
+    pub fn with_args(mut self, args: impl IntoIterator<Item = String>) -> Self {
+        self.args = args.into_iter().collect();
+        self
+    }
+
+    /// The files `load` reads, whether or not they exist yet
+    pub fn files(&self) -> Vec<PathBuf> {
+        let cli_path = self.parse_args().ok().and_then(|(path, _)| path);
+        let user_path = match &self.user_config {
+            Some(_) if cli_path.is_none() => None,
+            _ => cli_path.or_else(|| self.user_path.clone()),
+        };
+        self.system_path.iter().chain(&self.policy_path).cloned().chain(user_path).collect()
+    }
+
+    pub fn load(&self) -> Result<LayeredConfig> {
+        let (user_path, overrides) = self.parse_args()?;
+        let mut merger = Merger::new()?;
+
+        if let Some(value) = self.system_path.as_deref().map(read_layer).transpose()?.flatten() {
+            merger.merge("", value, ConfigLayer::System, false)?;
+        }
+        let user = match &self.user_config {
+            Some(config) if user_path.is_none() => Some(to_value(config)?),
+            _ => user_path.as_deref().or(self.user_path.as_deref()).map(read_layer).transpose()?.flatten(),
+        };
+        if let Some(value) = user {
+            merger.merge("", value, ConfigLayer::User, false)?;
+        }
+        let base = merger.value.clone();
+
+        for (name, raw) in &self.env {
+            let key = name[ENV_PREFIX.len()..].to_lowercase().replace("__", ".");
+            merger.set(&key, raw, ConfigLayer::Environment)
+                .map_err(|e| VpnError::ConfigError(format!("{}: {}", name, e)))?;
+        }
+        for (key, raw) in &overrides {
+            merger.set(key, raw, ConfigLayer::CommandLine)
+                .map_err(|e| VpnError::ConfigError(format!("--set {}: {}", key, e)))?;
+        }
+        let policy = self.policy_path.as_deref().map(ManagedPolicy::load).transpose()?.flatten().unwrap_or_default();
+        for (key, value) in policy.settings() {
+            merger.merge(key, value.clone(), ConfigLayer::Policy, true)?;
+            merger.overridden.push(key.to_string());
+        }
+
+        let config: VpnConfig = serde_json::from_value(merger.value.clone())
+            .map_err(|e| VpnError::ConfigError(format!("Failed to parse config: {}", e)))?;
+        let layered = LayeredConfig {
+            config,
+            policy,
+            effective: merger.value,
+            base,
+            sources: merger.sources,
+            overridden: merger.overridden,
+        };
+
+        let report = layered.config.check();
+        if !report.is_valid() {
+            let errors: Vec<String> = report.errors()
+                .map(|issue| format!("{} (from {})", issue, layered.source(&issue.path).unwrap_or(ConfigLayer::Default)))
+                .collect();
+            return Err(VpnError::ConfigError(format!("Invalid config:\n{}", errors.join("\n"))));
+        }
+        Ok(layered)
+    }
+
+    // Returns the --config file, if any, and the --set overrides
+    fn parse_args(&self) -> Result<(Option<PathBuf>, Overrides)> {
+        let mut user_path = None;
+        let mut overrides = Vec::new();
+        let mut args = self.args.iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || inline.clone().or_else(|| args.next().cloned())
+                .ok_or_else(|| VpnError::ConfigError(format!("{} needs a value", flag)));
+            match flag {
+                "--config" => user_path = Some(PathBuf::from(value()?)),
+                "--set" => {
+                    let setting = value()?;
+                    let (key, raw) = setting.split_once('=')
+                        .ok_or_else(|| VpnError::ConfigError(format!("Expected --set key=value, got {}", setting)))?;
+                    overrides.push((key.trim().to_string(), raw.to_string()));
+                }
+                _ => return Err(VpnError::ConfigError(format!("Unknown option: {}", arg))),
+            }
+        }
+        Ok((user_path, overrides))
+    }
+}
+
+/// The resolved config plus the layer each setting came from
+#[derive(Debug, Clone)]
+pub struct LayeredConfig {
+    config: VpnConfig,
+    policy: ManagedPolicy,
+    effective: Value,
+    base: Value,                              // Files only, before environment and flags
+    sources: BTreeMap<String, ConfigLayer>,   // By leaf key
+    overridden: Vec<String>,                  // Keys set by environment, flags or policy
+}
+
+impl LayeredConfig {
+    pub fn config(&self) -> &VpnConfig {
+        &self.config
+    }
+
+    pub fn into_config(self) -> VpnConfig {
+        self.config
+    }
+
+    /// Empty when no policy file is installed
+    pub fn policy(&self) -> &ManagedPolicy {
+        &self.policy
+    }
+
+    /// Effective value of a key, e.g. `kill_switch.mode`
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.effective.pointer(&pointer(key))
+    }
+
+    /// The last layer that changed `key`; a layer repeating the value below
+    /// it gets no credit. For a key holding an object, the highest layer
+    /// that changed anything inside it.
+    pub fn source(&self, key: &str) -> Option<ConfigLayer> {
+        let prefix = format!("{}.", key);
+        self.sources.iter()
+            .filter(|(k, _)| k.as_str() == key || k.starts_with(&prefix))
+            .map(|(_, layer)| *layer)
+            .max()
+    }
+
+    /// Every setting with its effective value and layer, sorted by key
+    pub fn entries(&self) -> Vec<(&str, &Value, ConfigLayer)> {
+        self.sources.iter()
+            .filter_map(|(key, layer)| Some((key.as_str(), self.get(key)?, *layer)))
+            .collect()
+    }
+
+    /// `edited` with environment, command-line and policy values taken back
+    /// out, so saving it does not make them permanent. Overridden settings the
+    /// user has since changed are kept.
+    pub fn strip_overrides(&self, edited: &VpnConfig) -> VpnConfig {
+        let Ok(mut value) = serde_json::to_value(edited) else {
+            return edited.clone();
+        };
+        for key in &self.overridden {
+            let path = pointer(key);
+            if value.pointer(&path) != self.effective.pointer(&path) {
+                continue;
+            }
+            if let (Some(slot), Some(original)) = (value.pointer_mut(&path), self.base.pointer(&path)) {
+                *slot = original.clone();
+            }
+        }
+        serde_json::from_value(value).unwrap_or_else(|_| edited.clone())
+    }
+}
+
+struct Merger {
+    value: Value,
+    sources: BTreeMap<String, ConfigLayer>,
+    overridden: Vec<String>,
+}
+
+impl Merger {
+    fn new() -> Result<Self> {
+        let value = to_value(&VpnConfig::default())?;
+        let mut sources = BTreeMap::new();
+        for key in leaves("", &value) {
+            sources.insert(key, ConfigLayer::Default);
+        }
+        Ok(Self { value, sources, overridden: Vec::new() })
+    }
+
+    /// Deep-merges objects. Unknown keys are an error when `strict`;
+    /// otherwise they are kept for serde to judge, like any file key.
+    fn merge(&mut self, key: &str, new: Value, layer: ConfigLayer, strict: bool) -> Result<()> {
+        let path = pointer(key);
+        let Some(current) = self.value.pointer_mut(&path) else {
+            if strict {
+                return Err(VpnError::ConfigError(format!("Unknown setting: {}", key)));
+            }
+            let (parent, name) = key.rsplit_once('.').unwrap_or(("", key));
+            if let Some(Value::Object(parent)) = self.value.pointer_mut(&pointer(parent)) {
+                parent.insert(name.to_string(), new.clone());
+                self.record(key, &new, layer);
+            }
+            return Ok(());
+        };
+
+        match (current, new) {
+            (Value::Object(_), Value::Object(fields)) => {
+                for (name, field) in fields {
+                    let child = if key.is_empty() { name } else { format!("{}.{}", key, name) };
+                    self.merge(&child, field, layer, strict)?;
+                }
+            }
+            (current, new) => {
+                if *current != new {
+                    *current = new.clone();
+                    self.record(key, &new, layer);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets one key from a raw environment or flag value
+    fn set(&mut self, key: &str, raw: &str, layer: ConfigLayer) -> Result<()> {
+        let current = self.value.pointer(&pointer(key))
+            .ok_or_else(|| VpnError::ConfigError(format!("Unknown setting: {}", key)))?;
+        let structured = raw.trim_start().starts_with(['{', '[']);
+        let value = match current {
+            Value::String(_) if !structured => Value::String(raw.to_string()),
+            _ => serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string())),
+        };
+        self.merge(key, value, layer, true)?;
+        self.overridden.push(key.to_string());
+        Ok(())
+    }
+
+    fn record(&mut self, key: &str, value: &Value, layer: ConfigLayer) {
+        let prefix = format!("{}.", key);
+        self.sources.retain(|k, _| k != key && !k.starts_with(&prefix));
+        for leaf in leaves(key, value) {
+            self.sources.insert(leaf, layer);
+        }
+    }
+}
+
+fn read_layer(path: &Path) -> Result<Option<Value>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(VpnError::ConfigError(format!("Failed to read {}: {}", path.display(), e))),
+    };
+    // A damaged file falls back to its newest usable backup; a file from a
+    // newer version is reported as it is
+    let mut value = match parse_layer(path, &contents) {
+        Ok(value) => value,
+        Err(e) => return load_newest_backup(path, CONFIG_BACKUPS, &e, |backup, contents| {
+            let mut value = parse_layer(backup, contents)?;
+            migration::migrate(&mut value)?;
+            Ok::<_, VpnError>(value)
+        }).map(Some).ok_or(e),
+    };
+    migration::migrate(&mut value)?;
+    Ok(Some(value))
+}
+
+fn parse_layer(path: &Path, contents: &str) -> Result<Value> {
+    ConfigFormat::detect(path, contents).parse(contents)
+        .map_err(|e| VpnError::ConfigError(format!("{}: {}", path.display(), e)))
+}
+
+fn to_value(config: &VpnConfig) -> Result<Value> {
+    serde_json::to_value(config)
+        .map_err(|e| VpnError::ConfigError(format!("Failed to serialize config: {}", e)))
+}
+
+// "kill_switch.mode" -> "/kill_switch/mode"
+pub(crate) fn pointer(key: &str) -> String {
+    if key.is_empty() {
+        return String::new();
+    }
+    format!("/{}", key.replace('.', "/"))
+}
+
+// Keys of all non-object values; arrays count as single values
+pub(crate) fn leaves(key: &str, value: &Value) -> Vec<String> {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => fields.iter()
+            .flat_map(|(name, field)| {
+                let child = if key.is_empty() { name.clone() } else { format!("{}.{}", key, name) };
+                leaves(&child, field)
+            })
+            .collect(),
+        _ => vec![key.to_string()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::killswitch::KillSwitchMode;
+    use crate::protocol::VpnProtocol;
+    use std::fs;
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("vpn-layers-{}-{}.json", name, rand::random::<u32>()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_layers_in_order() {
+        let system = temp_file("system", r#"{ "mtu": 1400, "block_ads": true, "kill_switch": { "mode": "Always" } }"#);
+        let user = temp_file("user", r#"{ "mtu": 1380, "kill_switch": { "allow_lan": false } }"#);
+
+        let layered = ConfigLoader::new()
+            .with_system_file(system.clone())
+            .with_user_file(user.clone())
+            .with_env(env(&[
+                ("VPN_MOBILE_BLOCK_ADS", "false"),
+                ("VPN_MOBILE_PROTOCOL_CONFIG__PROTOCOL", "OpenVPN"),
+                ("HOME", "/root"),
+            ]))
+            .with_args(["--set", "protocol_config.port=443", "--set=connect_timeout=60"].map(String::from))
+            .load()
+            .unwrap();
+        fs::remove_file(system).unwrap();
+        fs::remove_file(user).unwrap();
+
+        let config = layered.config();
+        assert_eq!(config.mtu, 1380);
+        assert_eq!(config.kill_switch.mode, KillSwitchMode::Always);
+        assert!(!config.kill_switch.allow_lan);
+        assert!(!config.block_ads);
+        assert_eq!(config.protocol_config.protocol, VpnProtocol::OpenVPN);
+        assert_eq!(config.protocol_config.port, 443);
+        assert_eq!(config.connect_timeout, 60);
+
+        assert_eq!(layered.source("mtu"), Some(ConfigLayer::User));
+        assert_eq!(layered.source("kill_switch.mode"), Some(ConfigLayer::System));
+        assert_eq!(layered.source("kill_switch"), Some(ConfigLayer::User));
+        // Back to the default value, but set explicitly
+        assert_eq!(layered.source("block_ads"), Some(ConfigLayer::Environment));
+        assert_eq!(layered.source("protocol_config.port"), Some(ConfigLayer::CommandLine));
+        assert_eq!(layered.source("reconnect_attempts"), Some(ConfigLayer::Default));
+        assert_eq!(layered.get("kill_switch.mode"), Some(&Value::from("Always")));
+        assert!(layered.entries().iter().any(|(key, _, layer)| *key == "mtu" && *layer == ConfigLayer::User));
+    }
+
+    #[test]
+    fn test_backup_only_replaces_a_damaged_file() {
+        let path = temp_file("backups", "");
+        let mut config = VpnConfig::default();
+        for mtu in [1400, 1380] {
+            config.mtu = mtu;
+            config.save_to_file(&path).unwrap();
+        }
+        let saved = fs::read_to_string(&path).unwrap();
+        let loader = ConfigLoader::new().with_user_file(path.clone());
+
+        fs::write(&path, &saved[..saved.len() / 2]).unwrap();
+        assert_eq!(loader.load().unwrap().config().mtu, 1400);
+
+        // A newer version is an error, not a reason to go back to a backup
+        let newer = saved.replace(&format!("\"schema_version\": {}", migration::CURRENT_SCHEMA_VERSION), "\"schema_version\": 999");
+        fs::write(&path, newer).unwrap();
+        assert!(loader.load().is_err());
+
+        for file in crate::storage::backup_paths(&path, CONFIG_BACKUPS).into_iter().chain([path]) {
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn test_bad_overrides_are_rejected() {
+        let load = |vars: &[(&str, &str)], args: &[&str]| {
+            ConfigLoader::new().with_env(env(vars)).with_args(args.iter().map(|a| a.to_string())).load()
+        };
+        assert!(load(&[("VPN_MOBILE_NO_SUCH_SETTING", "1")], &[]).is_err());
+        assert!(load(&[], &["--set", "mtu"]).is_err());
+        assert!(load(&[], &["--verbose"]).is_err());
+        assert!(load(&[], &["--set"]).is_err());
+
+        // Invalid results name the layer responsible
+        let error = load(&[("VPN_MOBILE_MTU", "100")], &[]).unwrap_err().to_string();
+        assert!(error.contains("mtu") && error.contains("environment"), "{}", error);
+
+        // Structured values
+        let layered = load(&[("VPN_MOBILE_KILL_SWITCH__ALLOWED_IPS", r#"["10.0.0.0/8"]"#)], &[]).unwrap();
+        assert_eq!(layered.config().kill_switch.allowed_ips, vec!["10.0.0.0/8"]);
+    }
+
+    #[test]
+    fn test_policy_beats_every_layer() {
+        let policy = temp_file("policy", r#"{ "settings": { "kill_switch.mode": "Always" } }"#);
+        let layered = ConfigLoader::new()
+            .with_policy_file(policy.clone())
+            .with_env(env(&[("VPN_MOBILE_KILL_SWITCH__MODE", "Disabled")]))
+            .with_args(["--set", "kill_switch.mode=Automatic"].map(String::from))
+            .load()
+            .unwrap();
+        fs::remove_file(policy).unwrap();
+
+        assert_eq!(layered.config().kill_switch.mode, KillSwitchMode::Always);
+        assert_eq!(layered.source("kill_switch.mode"), Some(ConfigLayer::Policy));
+        assert!(layered.policy().is_locked("kill_switch.mode"));
+        // Not saved into the user's settings
+        assert_eq!(layered.strip_overrides(layered.config()).kill_switch.mode, KillSwitchMode::Automatic);
+    }
+
+    #[test]
+    fn test_strip_overrides_keeps_user_edits() {
+        let mut profile = VpnConfig::default();
+        profile.mtu = 1380;
+        let layered = ConfigLoader::new()
+            .with_user_config(profile)
+            .with_env(env(&[("VPN_MOBILE_MTU", "1400"), ("VPN_MOBILE_BLOCK_ADS", "true")]))
+            .load()
+            .unwrap();
+        assert_eq!(layered.source("mtu"), Some(ConfigLayer::Environment));
+
+        let mut edited = layered.config().clone();
+        edited.block_ads = false;
+        edited.auto_connect = true;
+        let saved = layered.strip_overrides(&edited);
+        assert_eq!(saved.mtu, 1380);
+        assert!(!saved.block_ads);
+        assert!(saved.auto_connect);
+    }
+}
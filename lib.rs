@@ -2,16 +2,40 @@ use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
+use net::{IpNetwork, Ipv6Mode};
 
 pub mod connection;
 pub mod server;
+pub mod country;
 pub mod protocol;
+pub mod ikev2;
 pub mod encryption;
 pub mod dns;
+pub mod entitlements;
 pub mod killswitch;
+pub mod layers;
 pub mod split_tunnel;
 pub mod analytics;
+pub mod balance;
 pub mod config;
+pub mod datacap;
+pub mod format;
+pub mod net;
+pub mod ports;
+pub mod multihop;
+pub mod manifest;
+pub mod migration;
+pub mod policy;
+pub mod probe;
+pub mod profiles;
+pub mod reload;
+pub mod geo;
+pub mod query;
+pub mod scoring;
+pub mod favorites;
+pub mod health;
+pub mod storage;
+pub mod validation;
 
 // Re-export main types
 pub use connection::VpnConnection;
@@ -38,6 +62,16 @@ pub struct ConnectionInfo {
     pub bytes_received: u64,
     pub duration: Duration,
     pub ip_address: Option<String>,
+    #[serde(default)]
+    pub port: Option<u16>,  // Server port dialled, see VpnServer::resolve_port
+    #[serde(default)]
+    pub ipv6_address: Option<String>,
+    #[serde(default)]
+    pub ipv6_mode: Ipv6Mode,
+    #[serde(default)]
+    pub allowed_ips: Vec<IpNetwork>,  // Routed into the tunnel
+    #[serde(default)]
+    pub chain: Vec<VpnServer>,  // Entry first, exit last
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,6 +103,18 @@ pub enum VpnError {
     
     #[error("Encryption error: {0}")]
     EncryptionError(String),
+    
+    #[error("Security policy violation: {0}")]
+    PolicyViolation(String),
+    
+    #[error("Server manifest error: {0}")]
+    ManifestError(String),
+    
+    #[error("Upgrade required: {0}")]
+    UpgradeRequired(String),
+    
+    #[error("Data limit reached: {0}")]
+    DataLimitReached(String),
 }
 
 pub type Result<T> = std::result::Result<T, VpnError>;
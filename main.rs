@@ -1,15 +1,34 @@
 use vpn_mobile::*;
 use connection::VpnConnection;
-use server::{ServerManager, Country};
+use server::{ServerManager, Country, Continent};
+use query::{ServerQuery, ServerSort};
 use protocol::{VpnProtocol, ProtocolConfig};
-use config::VpnConfig;
+use config::{UserProfile, VpnConfig};
 use dns::DnsManager;
 use killswitch::{KillSwitch, KillSwitchMode};
 use split_tunnel::SplitTunnel;
+use net::Ipv6Mode;
+use manifest::{ManifestLoader, ManifestSource};
+use probe::LatencyProber;
+use scoring::{ScoringProfile, ScoringStrategy};
+use profiles::{ProfileStore, ProfileTemplate, ServerSelection};
+use layers::{ConfigLayer, ConfigLoader, LayeredConfig};
+use reload::{ConfigDiff, ConfigEvent, ConfigWatcher, LiveComponents};
+use policy::ManagedPolicy;
+use entitlements::{Entitlements, Feature};
+use datacap::{DataCapAction, DataCapEvent, DataCapTracker};
+use geo::GeoPoint;
+use balance::LoadBalancer;
+use health::HealthEventKind;
 use analytics::{Analytics, ConnectionLog};
 use chrono::Utc;
 use std::io::{self, Write};
-use tokio::time::{sleep, Duration};
+use tokio::time::{sleep, Duration, Instant};
+use tokio::sync::{Mutex, RwLock};
+use std::sync::Arc;
+use std::path::Path;
+
+const CATALOG_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
 
 #[tokio::main]
 async fn main() {
@@ -18,21 +37,122 @@ async fn main() {
         .filter_level(log::LevelFilter::Info)
         .init();
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("convert") {
+        std::process::exit(convert_config(&args[1..]));
+    }
+
     println!("╔═══════════════════════════════════════════╗");
     println!("║     VPN Mobile - Rust Edition v0.1.0     ║");
     println!("║   Secure, Fast, and Feature-Rich VPN     ║");
     println!("╚═══════════════════════════════════════════╝\n");
 
     // Initialize components
-    let mut config = VpnConfig::default();
-    let mut server_manager = ServerManager::new();
+    let profiles_path = VpnConfig::get_profiles_path();
+    let stored_profiles = ProfileStore::load(&profiles_path).unwrap_or_else(|e| {
+        println!("⚠️  {}, starting from config.json", e);
+        None
+    });
+    // The active profile stands in for config.json once profiles exist
+    let loader = ConfigLoader::standard(args);
+    let user_config = stored_profiles.as_ref().and_then(|p| p.active()).map(|p| p.config.clone());
+    let layers_loader = match user_config {
+        Some(user_config) => loader.clone().with_user_config(user_config),
+        None => loader.clone(),
+    };
+    let mut layers = layers_loader.load().unwrap_or_else(|e| {
+        println!("❌ {}", e);
+        std::process::exit(1);
+    });
+    let mut profiles = stored_profiles
+        .unwrap_or_else(|| ProfileStore::with_config(layers.strip_overrides(layers.config())));
+    let mut config = layers.config().clone();
+    let mut policy = layers.policy().clone();
+    for (key, value, layer) in layers.entries() {
+        if layer >= ConfigLayer::Environment {
+            println!("⚙️  {} = {} (from {})", key, value, layer);
+        }
+    }
+    let server_manager = match &config.server_manifest_url {
+        Some(url) => {
+            let mut loader = ManifestLoader::new(ManifestSource::Http(url.clone()))
+                .with_cache(VpnConfig::get_manifest_cache_path());
+            let manager = ServerManager::load(&mut loader).await.unwrap_or_else(|e| {
+                println!("⚠️  {}, using the built-in server list", e);
+                ServerManager::new()
+            });
+            let manager = Arc::new(RwLock::new(manager));
+            ServerManager::spawn_refresher(manager.clone(), loader, CATALOG_REFRESH_INTERVAL);
+            manager
+        }
+        None => Arc::new(RwLock::new(ServerManager::new())),
+    };
+    let origin = match config.location {
+        Some(location) => Some(location),
+        // Sends the real IP to a third party, so only when asked to
+        None if config.public_ip_lookup && config.geoip_database.is_some() => locate_by_public_ip(&config).await,
+        None => None,
+    };
+    server_manager.write().await.set_origin(origin);
+    server_manager.write().await.set_balancer(LoadBalancer::from_config(&config.load_balancing));
+    if let Err(e) = server_manager.write().await.persist_to(VpnConfig::get_saved_servers_path()) {
+        println!("⚠️  {}, favorites will not be saved", e);
+    }
+    let account = UserProfile::load(&VpnConfig::get_account_path()).unwrap_or_else(|e| {
+        println!("⚠️  {}, continuing on the Free plan", e);
+        None
+    }).unwrap_or_default();
+    let entitlements = Entitlements::for_profile(&account);
+    println!("👤 {} plan", entitlements.tier().name());
+    server_manager.write().await.set_entitlements(entitlements);
     let mut connection = VpnConnection::new(config.protocol_config.clone());
+    connection.set_security_policy(config.security_policy.clone());
+    connection.set_entitlements(entitlements);
+    connection.set_other_sessions(account.active_sessions);
+    let mut data_cap = DataCapTracker::new(entitlements.data_limit(), account.billing_day(), config.data_cap.clone());
+    if let Err(e) = data_cap.persist_to(VpnConfig::get_data_usage_path()) {
+        println!("⚠️  {}, data usage will not be saved", e);
+    }
+    let mut data_cap_events = data_cap.subscribe();
+    let data_cap = Arc::new(Mutex::new(data_cap));
+    connection.set_data_cap(data_cap.clone());
+    connection.set_ipv6_leak_protection(config.ipv6_leak_protection);
     let mut dns_manager = DnsManager::new();
     let mut kill_switch = KillSwitch::new(config.kill_switch.clone());
     let mut split_tunnel = SplitTunnel::new(config.split_tunnel.clone());
+    LiveComponents {
+        dns: &mut dns_manager,
+        kill_switch: &mut kill_switch,
+        split_tunnel: &mut split_tunnel,
+        servers: &mut *server_manager.write().await,
+    }.lock(&policy);
+    if !policy.is_empty() {
+        println!("🔒 Some settings are managed by your organization");
+    }
     let mut analytics = Analytics::new();
+    // Picks up edits to the config files while the app runs; only the
+    // files the running config came from, so not config.json once the
+    // active profile replaces it
+    let (mut config_watcher, mut config_events) = ConfigWatcher::new(layers_loader).spawn();
 
     loop {
+        // Traffic since the last pass through the menu
+        if connection.is_connected().await {
+            connection.update_stats().await;
+        }
+        while let Ok(event) = data_cap_events.try_recv() {
+            data_cap_notice(event, &connection, &config).await;
+        }
+        sync_ipv6_blackhole(&connection, &mut kill_switch).await;
+        while let Ok(event) = config_events.try_recv() {
+            let live = LiveComponents {
+                dns: &mut dns_manager,
+                kill_switch: &mut kill_switch,
+                split_tunnel: &mut split_tunnel,
+                servers: &mut *server_manager.write().await,
+            };
+            apply_config_change(event, &mut config, &mut policy, &mut connection, live).await;
+        }
         print_main_menu();
         
         let choice = get_user_input("Enter your choice: ");
@@ -40,11 +160,11 @@ async fn main() {
         match choice.trim() {
             "1" => {
                 // Quick connect
-                quick_connect(&mut connection, &server_manager).await;
+                quick_connect(&mut connection, &mut *server_manager.write().await, &config).await;
             }
             "2" => {
                 // Select server by country
-                select_server_by_country(&mut connection, &server_manager).await;
+                select_server_by_country(&mut connection, &mut *server_manager.write().await, &config).await;
             }
             "3" => {
                 // Disconnect
@@ -65,11 +185,11 @@ async fn main() {
             }
             "5" => {
                 // Server list
-                show_server_list(&server_manager);
+                show_server_list(&*server_manager.read().await);
             }
             "6" => {
                 // Protocol settings
-                protocol_settings(&mut connection, &mut config).await;
+                protocol_settings(&mut connection, &mut config, &policy).await;
             }
             "7" => {
                 // Security settings
@@ -77,21 +197,60 @@ async fn main() {
             }
             "8" => {
                 // Split tunneling
-                split_tunnel_menu(&mut split_tunnel);
+                split_tunnel_menu(&mut split_tunnel, &entitlements);
             }
             "9" => {
                 // Statistics
-                show_statistics(&analytics);
+                show_statistics(&analytics, &*data_cap.lock().await);
             }
             "10" => {
                 // Settings
-                settings_menu(&mut config);
+                settings_menu(&mut config, &layers, &policy);
+            }
+            "11" => {
+                // Multi-hop
+                multi_hop_connect(&mut connection, &*server_manager.read().await, &config).await;
+            }
+            "12" => {
+                // Latency probe
+                measure_latency(&server_manager, &config).await;
+            }
+            "13" => {
+                // Location
+                set_location(&mut *server_manager.write().await, &config);
+            }
+            "14" => {
+                // Reconnect
+                reconnect_last(&mut connection, &mut *server_manager.write().await).await;
+            }
+            "15" => {
+                // Favorites
+                favorites_menu(&mut connection, &mut *server_manager.write().await).await;
+            }
+            "16" => {
+                // Profiles
+                let live = LiveComponents {
+                    dns: &mut dns_manager,
+                    kill_switch: &mut kill_switch,
+                    split_tunnel: &mut split_tunnel,
+                    servers: &mut *server_manager.write().await,
+                };
+                let active = profiles.active().map(|p| p.name.clone());
+                profiles_menu(&mut connection, live, &mut profiles, &loader, &mut layers, &mut config).await;
+                save_profiles(&mut profiles, &layers, &config, &profiles_path);
+                // Watch what the newly active profile is layered with
+                if let Some(profile) = profiles.active().filter(|p| Some(&p.name) != active.as_ref()) {
+                    config_watcher.abort();
+                    (config_watcher, config_events) = ConfigWatcher::new(loader.clone().with_user_config(profile.config.clone())).spawn();
+                }
             }
             "0" => {
                 // Exit
+                save_profiles(&mut profiles, &layers, &config, &profiles_path);
                 if connection.is_connected().await {
                     println!("\n⚠️  Disconnecting before exit...");
                     let _ = connection.disconnect().await;
+                    sync_ipv6_blackhole(&connection, &mut kill_switch).await;
                 }
                 println!("\n👋 Thank you for using VPN Mobile!");
                 break;
@@ -100,12 +259,119 @@ async fn main() {
                 println!("\n❌ Invalid choice. Please try again.");
             }
         }
+        // Whatever the menu did, the kill switch follows the tunnel
+        sync_ipv6_blackhole(&connection, &mut kill_switch).await;
         
         println!("\nPress Enter to continue...");
         let _ = get_user_input("");
     }
 }
 
+/// Blocks IPv6 outside the tunnel while connected to a server without
+/// IPv6, and lifts the block once disconnected
+async fn sync_ipv6_blackhole(connection: &VpnConnection, kill_switch: &mut KillSwitch) {
+    let blackhole = connection.is_connected().await
+        && connection.get_info().await.ipv6_mode == Ipv6Mode::Blackhole;
+    kill_switch.set_ipv6_blackhole(blackhole);
+}
+
+/// Takes over the settings an edit to the config files changed; everything
+/// else, including changes made in the menus, stays as it is
+async fn apply_config_change(
+    event: ConfigEvent,
+    config: &mut VpnConfig,
+    policy: &mut ManagedPolicy,
+    connection: &mut VpnConnection,
+    mut live: LiveComponents<'_>,
+) {
+    let (diff, file_config, new_policy) = match event {
+        ConfigEvent::Reloaded { diff, config, policy } => (diff, config, policy),
+        ConfigEvent::Rejected(e) => {
+            println!("\n⚠️  Config file change ignored, keeping the current settings:\n{}", e);
+            return;
+        }
+    };
+    let patched = diff.patch(config, &file_config)
+        .and_then(|patched| new_policy.enforce(&patched))
+        .and_then(|patched| patched.validate().map(|_| patched).map_err(VpnError::ConfigError));
+    
+    // Against what is running, so settings a new policy locks count too
+    let changes = patched.as_ref().map(|patched| ConfigDiff::between(config, patched)).unwrap_or_default();
+    live.lock(&new_policy);
+    let patched = patched.and_then(|patched| live.apply(&changes, &patched).map(|_| patched));
+    let patched = match patched {
+        Ok(patched) => patched,
+        Err(e) => {
+            live.lock(policy);
+            println!("\n⚠️  Config file change ignored, keeping the current settings: {}", e);
+            return;
+        }
+    };
+    *policy = new_policy;
+    if changes.is_empty() {
+        return;
+    }
+    
+    // Used from the next connection on
+    connection.set_protocol_config(patched.protocol_config.clone());
+    connection.set_security_policy(patched.security_policy.clone());
+    connection.set_ipv6_leak_protection(patched.ipv6_leak_protection);
+    if let Some(data_cap) = connection.get_data_cap() {
+        data_cap.lock().await.set_config(patched.data_cap.clone());
+    }
+    *config = patched;
+    
+    println!("\n🔄 Config reloaded: {}", changes.summary());
+    let pending = changes.needs_reconnect();
+    if !pending.is_empty() && connection.is_connected().await {
+        println!("   Reconnect to apply: {}", pending.join(", "));
+    }
+}
+
+/// Tells the user as their monthly data runs low, and what happens once it
+/// is gone
+async fn data_cap_notice(event: DataCapEvent, connection: &VpnConnection, config: &VpnConfig) {
+    match event {
+        DataCapEvent::Warning { percent, used, limit, resets } => {
+            println!("\n⚠️  You have used {}% of your monthly data ({} of {}); it resets on {}",
+                percent, Analytics::format_bytes(used), Analytics::format_bytes(limit), resets.format("%Y-%m-%d"));
+        }
+        DataCapEvent::LimitReached { action, limit, resets, .. } => {
+            println!("\n🛑 You have used all {} of your monthly data", Analytics::format_bytes(limit));
+            match action {
+                DataCapAction::Disconnect => {
+                    if connection.is_connected().await {
+                        let _ = connection.disconnect().await;
+                        println!("   Disconnected.");
+                    }
+                    println!("   You can connect again on {}, or upgrade to Premium for unlimited data", resets.format("%Y-%m-%d"));
+                }
+                DataCapAction::Throttle => {
+                    println!("   Speed is limited to {:.2} MB/s until {}", config.data_cap.throttle_speed, resets.format("%Y-%m-%d"));
+                }
+            }
+        }
+    }
+}
+
+/// `vpn-cli convert INPUT OUTPUT`; the output's extension picks the format
+fn convert_config(args: &[String]) -> i32 {
+    let [from, to] = args else {
+        println!("Usage: vpn-cli convert INPUT OUTPUT  (.json, .toml, .yaml or .yml)");
+        return 2;
+    };
+    match VpnConfig::convert(Path::new(from), Path::new(to)) {
+        Ok(format) => {
+            println!("✅ Wrote {} as {}", to, format);
+            0
+        }
+        Err(e) => {
+            println!("❌ {}", e);
+            1
+        }
+    }
+}
+
 fn print_main_menu() {
     println!("\n╔═══════════════════════════════════════════╗");
     println!("║              MAIN MENU                    ║");
@@ -120,6 +386,12 @@ fn print_main_menu() {
     println!("║  8. 🔀 Split Tunneling                    ║");
     println!("║  9. 📈 Statistics                         ║");
     println!("║ 10. ⚙️  Settings                          ║");
+    println!("║ 11. 🔗 Multi-hop Connect                  ║");
+    println!("║ 12. 📡 Measure Server Latency             ║");
+    println!("║ 13. 📍 Set My Location                    ║");
+    println!("║ 14. ↩️  Reconnect to Last Server           ║");
+    println!("║ 15. ⭐ Favorites                          ║");
+    println!("║ 16. 👤 Profiles                           ║");
     println!("║  0. 🚪 Exit                               ║");
     println!("╚═══════════════════════════════════════════╝");
 }
@@ -133,40 +405,77 @@ fn get_user_input(prompt: &str) -> String {
     input
 }
 
-async fn quick_connect(connection: &mut VpnConnection, server_manager: &ServerManager) {
+async fn quick_connect(connection: &mut VpnConnection, server_manager: &mut ServerManager, config: &VpnConfig) {
+    // Double VPN configs, e.g. Maximum Security, always go through a chain
+    if config.multi_hop.enabled {
+        println!("\n🔍 Finding the best multi-hop route...");
+        connect_best_chain(connection, server_manager, config.multi_hop.entry_country, config.multi_hop.exit_country).await;
+        return;
+    }
+    let filter = get_user_input("\nFilter (blank for any, e.g. \"p2p europe load<50\"): ");
+    let query = match parse_server_filter(&filter) {
+        Ok(query) => query
+            .with_protocol(config.protocol_config.protocol)
+            .with_scoring(config.scoring),
+        Err(e) => {
+            println!("❌ {}", e);
+            return;
+        }
+    };
+    if let Err(e) = connection.get_entitlements().check_query(&query) {
+        println!("❌ {}", e);
+        return;
+    }
+    
     println!("\n🔍 Finding the fastest server...");
     
-    if let Some(server) = server_manager.get_fastest_server() {
+    if let Some(server) = server_manager.pick(&query).cloned() {
         println!("✨ Found: {} {} ({})", 
             server.location.country.flag_emoji(),
             server.name,
             server.location.country.name()
         );
-        println!("   Load: {}% | Latency: {}ms | Score: {:.1}/100", 
-            server.load, server.latency, server.score()
+        println!("   Load: {}% | Latency: {}ms | {} score: {:.1}/100", 
+            server.load, server.latency, config.scoring.name(), config.scoring.score(&server)
         );
         
         println!("\n🔐 Connecting...");
-        match connection.connect(server.clone()).await {
+        let server_id = server.id.clone();
+        let started = Instant::now();
+        match connection.connect(server).await {
             Ok(_) => {
+                server_manager.record_health(&server_id, HealthEventKind::Connected { handshake: started.elapsed() });
+                server_manager.add_recent(server_id);
                 println!("✅ Connected successfully!");
                 show_connection_info(connection).await;
             }
+            Err(e @ VpnError::DataLimitReached(_)) => println!("❌ {}", e),
             Err(e) => {
+                server_manager.record_health(&server_id, HealthEventKind::ConnectFailed);
                 println!("❌ Connection failed: {}", e);
             }
         }
+    } else if let Err(e) = premium_only(connection, server_manager, &query) {
+        println!("❌ {}", e);
     } else {
         println!("❌ No available servers found");
     }
 }
 
-async fn select_server_by_country(connection: &mut VpnConnection, server_manager: &ServerManager) {
+// When nothing on the plan matched, whether premium servers would have
+fn premium_only(connection: &VpnConnection, server_manager: &ServerManager, query: &ServerQuery) -> Result<()> {
+    if server_manager.query(query).total > 0 {
+        return connection.get_entitlements().check(Feature::PremiumServers);
+    }
+    Ok(())
+}
+
+async fn select_server_by_country(connection: &mut VpnConnection, server_manager: &mut ServerManager, config: &VpnConfig) {
     println!("\n╔═══════════════════════════════════════════╗");
     println!("║        SELECT COUNTRY                     ║");
     println!("╚═══════════════════════════════════════════╝");
     
-    let countries = Country::all();
+    let countries = server_manager.get_countries();
     for (idx, country) in countries.iter().enumerate() {
         if idx % 2 == 0 {
             print!("{:2}. {} {:<20}", idx + 1, country.flag_emoji(), country.name());
@@ -187,22 +496,30 @@ async fn select_server_by_country(connection: &mut VpnConnection, server_manager
         if num > 0 && num <= countries.len() {
             let country = &countries[num - 1];
             
-            if let Some(server) = server_manager.get_fastest_in_country(country) {
+            if let Some(server) = server_manager.get_fastest_in_country(country, config.scoring).cloned() {
                 println!("\n✨ Selected: {} {}", server.location.country.flag_emoji(), server.name);
-                println!("   Load: {}% | Latency: {}ms | Score: {:.1}/100", 
-                    server.load, server.latency, server.score()
+                println!("   Load: {}% | Latency: {}ms | {} score: {:.1}/100", 
+                    server.load, server.latency, config.scoring.name(), config.scoring.score(&server)
                 );
                 
                 println!("\n🔐 Connecting...");
-                match connection.connect(server.clone()).await {
+                let server_id = server.id.clone();
+                let started = Instant::now();
+                match connection.connect(server).await {
                     Ok(_) => {
+                        server_manager.record_health(&server_id, HealthEventKind::Connected { handshake: started.elapsed() });
+                        server_manager.add_recent(server_id);
                         println!("✅ Connected successfully!");
                         show_connection_info(connection).await;
                     }
+                    Err(e @ VpnError::DataLimitReached(_)) => println!("❌ {}", e),
                     Err(e) => {
+                        server_manager.record_health(&server_id, HealthEventKind::ConnectFailed);
                         println!("❌ Connection failed: {}", e);
                     }
                 }
+            } else if let Err(e) = premium_only(connection, server_manager, &ServerQuery::new().with_country(*country)) {
+                println!("❌ {}", e);
             } else {
                 println!("❌ No servers available in {}", country.name());
             }
@@ -210,6 +527,363 @@ async fn select_server_by_country(connection: &mut VpnConnection, server_manager
     }
 }
 
+async fn multi_hop_connect(connection: &mut VpnConnection, server_manager: &ServerManager, config: &VpnConfig) {
+    println!("\n╔═══════════════════════════════════════════╗");
+    println!("║        MULTI-HOP CONNECT                  ║");
+    println!("╚═══════════════════════════════════════════╝");
+    if let Err(e) = connection.get_entitlements().check(Feature::MultiHop) {
+        println!("❌ {}", e);
+        return;
+    }
+    
+    let countries = server_manager.get_countries();
+    for (idx, country) in countries.iter().enumerate() {
+        if idx % 2 == 0 {
+            print!("{:2}. {} {:<20}", idx + 1, country.flag_emoji(), country.name());
+        } else {
+            println!("{:2}. {} {}", idx + 1, country.flag_emoji(), country.name());
+        }
+    }
+    if countries.len() % 2 != 0 {
+        println!();
+    }
+    
+    let pick = |prompt: &str, default: &Option<Country>| -> Option<Country> {
+        let choice = get_user_input(prompt);
+        match choice.trim().parse::<usize>() {
+            Ok(num) if num > 0 && num <= countries.len() => Some(countries[num - 1]),
+            _ => *default,
+        }
+    };
+    let entry = pick("\nEntry country number (blank for best): ", &config.multi_hop.entry_country);
+    let exit = pick("Exit country number (blank for best): ", &config.multi_hop.exit_country);
+    connect_best_chain(connection, server_manager, entry, exit).await;
+}
+
+async fn connect_best_chain(connection: &mut VpnConnection, server_manager: &ServerManager, entry: Option<Country>, exit: Option<Country>) {
+    match server_manager.get_best_chain(entry.as_ref(), exit.as_ref()) {
+        Some(chain) => {
+            println!("\n✨ Route: {}", chain.describe());
+            println!("   Latency: {}ms | Score: {:.1}/100",
+                chain.hops().iter().map(|h| h.latency).sum::<u32>(), chain.combined_score()
+            );
+            
+            println!("\n🔐 Connecting through {} hops...", chain.len());
+            match connection.connect_chain(chain).await {
+                Ok(_) => {
+                    println!("✅ Connected successfully!");
+                    show_connection_info(connection).await;
+                }
+                Err(e) => {
+                    println!("❌ Connection failed: {}", e);
+                }
+            }
+        }
+        None => {
+            println!("❌ No server chain available for that route");
+        }
+    }
+}
+
+/// The catalog is only locked to read the targets and to store the results,
+/// so a refresh can land while the probes are out
+async fn measure_latency(server_manager: &RwLock<ServerManager>, config: &VpnConfig) {
+    let targets = server_manager.read().await.probe_targets(&config.protocol_config);
+    println!("\n📡 Probing {} servers...", targets.len());
+    
+    let results = LatencyProber::for_protocol(&config.protocol_config).probe_all(targets).await;
+    let mut server_manager = server_manager.write().await;
+    let answered = server_manager.record_latencies(&results);
+    println!("✅ {} of {} servers answered", answered, results.len());
+    
+    println!("\n⚡ Fastest servers:");
+    let mut servers: Vec<_> = server_manager.get_all_servers().into_iter().filter(|s| s.online).collect();
+    servers.sort_by_key(|s| s.latency);
+    for server in servers.into_iter().take(5) {
+        println!("   {} {} - {}ms", server.location.country.flag_emoji(), server.name, server.latency);
+    }
+}
+
+/// Places the user by their public IP when no coordinates are configured
+async fn locate_by_public_ip(config: &VpnConfig) -> Option<GeoPoint> {
+    match geo::public_ip().await.and_then(|ip| config.locate(Some(ip))) {
+        Ok(Some(point)) => {
+            println!("📍 Located near {}", point);
+            Some(point)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            println!("⚠️  {}, nearest servers are unknown until you set a location", e);
+            None
+        }
+    }
+}
+
+fn set_location(server_manager: &mut ServerManager, config: &VpnConfig) {
+    let input = get_user_input("\nYour coordinates (lat,lon) or public IP address: ");
+    let input = input.trim();
+    
+    let origin = if let Ok(point) = input.parse::<GeoPoint>() {
+        Some(point)
+    } else if let Ok(ip) = input.parse() {
+        match config.locate(Some(ip)) {
+            Ok(Some(point)) => Some(point),
+            Ok(None) => {
+                println!("❌ {} is not in the GeoIP database", ip);
+                return;
+            }
+            Err(e) => {
+                println!("❌ {}", e);
+                return;
+            }
+        }
+    } else {
+        println!("❌ Enter coordinates like 47.37,8.54 or an IP address");
+        return;
+    };
+    
+    server_manager.set_origin(origin);
+    if let Some(origin) = origin {
+        println!("\n📍 Nearest servers to {}:", origin);
+        for server in server_manager.get_nearest_servers(&origin).into_iter().take(5) {
+            println!("   {} {} - {:.0} km",
+                server.location.country.flag_emoji(), server.name,
+                origin.distance_km(&server.location.point())
+            );
+        }
+    }
+}
+
+async fn connect_saved(connection: &mut VpnConnection, server_manager: &mut ServerManager, server: VpnServer) {
+    println!("\n🔐 Connecting to {} {}...", server.location.country.flag_emoji(), server.name);
+    let server_id = server.id.clone();
+    let started = Instant::now();
+    match connection.connect(server).await {
+        Ok(_) => {
+            server_manager.record_health(&server_id, HealthEventKind::Connected { handshake: started.elapsed() });
+            server_manager.add_recent(server_id);
+            println!("✅ Connected successfully!");
+            show_connection_info(connection).await;
+        }
+        // Not the server's fault
+        Err(e @ (VpnError::UpgradeRequired(_) | VpnError::DataLimitReached(_))) => println!("❌ {}", e),
+        Err(e) => {
+            server_manager.record_health(&server_id, HealthEventKind::ConnectFailed);
+            println!("❌ Connection failed: {}", e);
+        }
+    }
+}
+
+async fn reconnect_last(connection: &mut VpnConnection, server_manager: &mut ServerManager) {
+    // Reconnecting a live session means the tunnel stopped working
+    let info = connection.get_info().await;
+    if let (true, Some(server)) = (connection.is_connected().await, &info.server) {
+        server_manager.record_health(&server.id, HealthEventKind::Dropped { after: info.duration });
+    }
+    match server_manager.get_last_server().cloned() {
+        Some(server) => connect_saved(connection, server_manager, server).await,
+        None => println!("\n❌ No recent server to reconnect to"),
+    }
+}
+
+async fn favorites_menu(connection: &mut VpnConnection, server_manager: &mut ServerManager) {
+    println!("\n╔═══════════════════════════════════════════╗");
+    println!("║            FAVORITES                      ║");
+    println!("╚═══════════════════════════════════════════╝");
+    
+    let favorites: Vec<(String, VpnServer)> = server_manager.get_favorites()
+        .iter()
+        .map(|f| (f.server.id.clone(), f.server.clone()))
+        .collect();
+    for (idx, saved) in server_manager.get_favorites().iter().enumerate() {
+        println!("{:2}. {} {}{}{}", idx + 1,
+            saved.server.location.country.flag_emoji(), saved.server.name,
+            saved.label.map(|l| format!(" \"{}\"", l)).unwrap_or_default(),
+            if saved.removed { " (no longer offered)" } else { "" });
+    }
+    if favorites.is_empty() {
+        println!("No favorites yet");
+    }
+    
+    println!("\na. Add current server");
+    println!("c N. Connect | r N. Remove | l N. Label | m N P. Move to position P");
+    let choice = get_user_input("\nEnter your choice: ");
+    let words: Vec<&str> = choice.split_whitespace().collect();
+    let pick = |word: Option<&&str>| -> Option<&(String, VpnServer)> {
+        word.and_then(|w| w.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .and_then(|n| favorites.get(n - 1))
+    };
+    
+    match (words.first().copied(), pick(words.get(1))) {
+        (Some("a"), _) => match connection.get_info().await.server {
+            Some(server) => {
+                server_manager.add_favorite(server.id.clone());
+                println!("✅ Added {} to favorites", server.name);
+            }
+            None => println!("❌ Not connected to any server"),
+        },
+        (Some("c"), Some((_, server))) => {
+            connect_saved(connection, server_manager, server.clone()).await;
+        }
+        (Some("r"), Some((id, server))) => {
+            server_manager.remove_favorite(id);
+            println!("✅ Removed {}", server.name);
+        }
+        (Some("l"), Some((id, _))) => {
+            let label = get_user_input("Label (blank to clear): ");
+            server_manager.set_favorite_label(id, Some(label));
+            println!("✅ Label updated");
+        }
+        (Some("m"), Some((id, _))) => {
+            match words.get(2).and_then(|p| p.parse::<usize>().ok()) {
+                Some(position) if position > 0 => {
+                    server_manager.move_favorite(id, position - 1);
+                    println!("✅ Favorite moved");
+                }
+                _ => println!("❌ Enter a position, e.g. m 3 1"),
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Keeps edits made in the menus with the active profile, then saves.
+/// Environment and command-line overrides are not saved.
+fn save_profiles(profiles: &mut ProfileStore, layers: &LayeredConfig, config: &VpnConfig, path: &Path) {
+    if let Some(active) = profiles.active_mut() {
+        active.config = layers.strip_overrides(config);
+    }
+    if let Err(e) = profiles.save(path) {
+        println!("⚠️  {}", e);
+    }
+}
+
+async fn profiles_menu(
+    connection: &mut VpnConnection,
+    mut live: LiveComponents<'_>,
+    profiles: &mut ProfileStore,
+    loader: &ConfigLoader,
+    layers: &mut LayeredConfig,
+    config: &mut VpnConfig,
+) {
+    println!("\n╔═══════════════════════════════════════════╗");
+    println!("║            PROFILES                       ║");
+    println!("╚═══════════════════════════════════════════╝");
+    
+    // Settings changed since the last switch belong to the active profile
+    if let Some(active) = profiles.active_mut() {
+        active.config = layers.strip_overrides(config);
+    }
+    let active = profiles.active().map(|p| p.name.clone()).unwrap_or_default();
+    let names: Vec<String> = profiles.profiles().iter().map(|p| p.name.clone()).collect();
+    for (idx, profile) in profiles.profiles().iter().enumerate() {
+        println!("{:2}. {}{} - {} | {}", idx + 1, profile.name,
+            if profile.name == active { " (active)" } else { "" },
+            profile.config.protocol_config.protocol.name(), profile.server);
+    }
+    
+    println!("\nn. New from template");
+    println!("s N. Switch | g N. Switch and connect | c N. Clone | r N. Rename | x N. Delete | b N. Bind server");
+    let choice = get_user_input("\nEnter your choice: ");
+    let words: Vec<&str> = choice.split_whitespace().collect();
+    let name = words.get(1)
+        .and_then(|w| w.parse::<usize>().ok())
+        .filter(|n| *n > 0)
+        .and_then(|n| names.get(n - 1));
+    
+    let result = match (words.first().copied(), name) {
+        (Some("n"), _) => {
+            let templates = ProfileTemplate::all();
+            for (idx, template) in templates.iter().enumerate() {
+                println!("{}. {}", idx + 1, template.name());
+            }
+            let template = get_user_input("Template: ").trim().parse::<usize>().ok()
+                .filter(|n| *n > 0)
+                .and_then(|n| templates.get(n - 1).copied());
+            match template {
+                Some(template) => {
+                    let new_name = get_user_input("Profile name: ");
+                    profiles.create(&new_name, template).map(|p| format!("Created {} from {}", p.name, template.name()))
+                }
+                None => return,
+            }
+        }
+        (Some("s"), Some(name)) | (Some("g"), Some(name)) => {
+            // Overrides still apply on top of the new profile. The running
+            // kill switch, DNS and routes change with it, or the switch fails.
+            let reloaded = profiles.get(name)
+                .map(|profile| loader.clone().with_user_config(profile.config.clone()).load())
+                .transpose()
+                .and_then(|reloaded| match reloaded {
+                    Some(reloaded) => {
+                        let diff = ConfigDiff::between(config, reloaded.config());
+                        live.apply(&diff, reloaded.config()).map(|_| Some(reloaded))
+                    }
+                    None => Ok(None),
+                });
+            match reloaded {
+                Err(e) => Err(e),
+                Ok(reloaded) => profiles.switch(name).map(|profile| {
+                    if let Some(reloaded) = reloaded {
+                        *layers = reloaded;
+                    }
+                    *config = layers.config().clone();
+                    connection.set_protocol_config(config.protocol_config.clone());
+                    connection.set_security_policy(config.security_policy.clone());
+                    connection.set_ipv6_leak_protection(config.ipv6_leak_protection);
+                    format!("Switched to {}", profile.name)
+                }),
+            }
+        }
+        (Some("c"), Some(name)) => {
+            let new_name = get_user_input("Name for the copy: ");
+            profiles.clone_profile(name, &new_name).map(|p| format!("Created {}", p.name))
+        }
+        (Some("r"), Some(name)) => {
+            let new_name = get_user_input("New name: ");
+            profiles.rename(name, &new_name).map(|_| "Profile renamed".to_string())
+        }
+        (Some("x"), Some(name)) => profiles.delete(name).map(|p| format!("Deleted {}", p.name)),
+        (Some("b"), Some(name)) => {
+            let input = get_user_input("Server (blank for fastest, a country or a server id): ");
+            let input = input.trim();
+            let selection = if input.is_empty() {
+                Some(ServerSelection::Fastest)
+            } else if let Some(server) = live.servers.get_all_servers().into_iter().find(|s| s.id.eq_ignore_ascii_case(input)) {
+                Some(ServerSelection::Server(server.id.clone()))
+            } else {
+                input.parse::<Country>().ok().map(ServerSelection::Country)
+            };
+            match (selection, profiles.get_mut(name)) {
+                (Some(selection), Some(profile)) => {
+                    profile.server = selection;
+                    Ok(format!("{} now uses: {}", profile.name, profile.server))
+                }
+                _ => Err(VpnError::ConfigError(format!("No country or server called {}", input))),
+            }
+        }
+        _ => return,
+    };
+    
+    match result {
+        Ok(message) => println!("✅ {}", message),
+        Err(e) => {
+            println!("❌ {}", e);
+            return;
+        }
+    }
+    
+    // "g" connects with the profile it just switched to
+    if words.first() == Some(&"g") {
+        let selection = profiles.active().map(|p| p.server.clone()).unwrap_or_default();
+        match selection.resolve(live.servers, config.scoring).cloned() {
+            Some(server) => connect_saved(connection, live.servers, server).await,
+            None => println!("❌ No available server for: {}", selection),
+        }
+    }
+}
+
 async fn show_connection_status(connection: &VpnConnection) {
     let info = connection.get_info().await;
     let stats = connection.get_stats().await;
@@ -224,11 +898,27 @@ async fn show_connection_status(connection: &VpnConnection) {
         println!("\n🌍 Server: {} {}", server.location.country.flag_emoji(), server.name);
         println!("   Location: {}, {}", server.location.city, server.location.country.name());
         println!("   Protocol: {:?}", connection.get_protocol_config().protocol);
+        if let Some((src, dst)) = connection.current_ports().await {
+            println!("   Port: {} (hopping, from local port {})", dst, src);
+        } else if let Some(port) = info.port {
+            println!("   Port: {}", port);
+        }
+        if info.chain.len() > 1 {
+            let route: Vec<&str> = info.chain.iter().map(|h| h.name.as_str()).collect();
+            println!("   Route: {}", route.join(" → "));
+        }
     }
     
     if let Some(ip) = &info.ip_address {
         println!("\n🔐 Your IP: {}", ip);
     }
+    if info.server.is_some() {
+        match (&info.ipv6_address, info.ipv6_mode) {
+            (Some(ip), _) => println!("   IPv6: {}", ip),
+            (None, Ipv6Mode::Blackhole) => println!("   IPv6: 🚫 Blocked (server has no IPv6)"),
+            (None, _) => println!("   IPv6: ⚠️  Not protected"),
+        }
+    }
     
     if let Some(connected_at) = info.connected_at {
         println!("\n⏱️  Connected: {}", connected_at.format("%Y-%m-%d %H:%M:%S"));
@@ -251,36 +941,116 @@ async fn show_connection_info(connection: &VpnConnection) {
     if let Some(ip) = &info.ip_address {
         println!("\n🔐 Your new IP: {}", ip);
     }
+    if let Some(ip) = &info.ipv6_address {
+        println!("🔐 Your new IPv6: {}", ip);
+    }
     println!("🛡️  You are now protected!");
 }
 
+const SERVERS_PER_PAGE: usize = 15;
+
 fn show_server_list(server_manager: &ServerManager) {
     println!("\n╔═══════════════════════════════════════════╗");
     println!("║         AVAILABLE SERVERS                 ║");
     println!("╚═══════════════════════════════════════════╝");
     
-    for country in Country::all() {
-        if let Some(servers) = server_manager.get_servers_by_country(&country) {
-            println!("\n{} {} ({} servers)", 
-                country.flag_emoji(), country.name(), servers.len());
-            
-            for server in servers.iter().take(3) {
-                let status = if server.online { "🟢" } else { "🔴" };
-                println!("  {} {} - Load: {}% | Latency: {}ms | Score: {:.1}", 
-                    status, server.name, server.load, server.latency, server.score());
+    println!("Filters: p2p streaming premium free ipv6 <continent> load<N latency<N city=NAME");
+    println!("Sort: sort=score|latency|load|bandwidth|distance|name");
+    let filter = get_user_input("Filter (blank for all): ");
+    let query = match parse_server_filter(&filter) {
+        Ok(query) => query.with_unavailable(true),
+        Err(e) => {
+            println!("❌ {}", e);
+            return;
+        }
+    };
+    
+    let mut page = 0;
+    loop {
+        let results = server_manager.query(&query.clone().with_page(page, SERVERS_PER_PAGE));
+        if results.total == 0 {
+            println!("\nNo servers match");
+            return;
+        }
+        
+        println!("\nPage {} of {} ({} servers)", page + 1, results.page_count(), results.total);
+        for server in &results.servers {
+            let status = if server.online { "🟢" } else { "🔴" };
+            println!("  {} {} {} - Load: {}% | Latency: {}ms | Score: {:.1}", 
+                status, server.location.country.flag_emoji(), server.name,
+                server.load, server.latency, server.score());
+            if let Some(health) = server_manager.get_health(&server.id) {
+                println!("      ✔ {} ✘ {} ⚡ {} drops | Reliability: {:.0}%{}",
+                    health.successes(), health.failures(), health.drops(),
+                    server.reliability.unwrap_or(1.0) * 100.0,
+                    if server.is_backing_off() { " | ⏸ backing off" } else { "" });
             }
         }
+        
+        if !results.has_next() || !get_user_input("Enter for more, q to stop: ").trim().is_empty() {
+            return;
+        }
+        page += 1;
     }
 }
 
-async fn protocol_settings(connection: &mut VpnConnection, config: &mut VpnConfig) {
+/// Builds a query from words like "p2p europe load<50 sort=latency"
+fn parse_server_filter(input: &str) -> std::result::Result<ServerQuery, String> {
+    let mut query = ServerQuery::new();
+    for word in input.split_whitespace() {
+        let lower = word.to_lowercase();
+        query = match lower.as_str() {
+            "p2p" => query.with_p2p(true),
+            "streaming" => query.with_streaming(true),
+            "premium" => query.with_premium(true),
+            "free" => query.with_premium(false),
+            "ipv6" => query.with_ipv6(true),
+            _ => {
+                let number = |s: &str| s.parse::<u32>().map_err(|_| format!("Not a number: {}", word));
+                // The limits are "at most", so "load<50" allows up to 49
+                if let Some(load) = lower.strip_prefix("load<") {
+                    query.with_max_load(number(load)?.clamp(1, 101) as u8 - 1)
+                } else if let Some(latency) = lower.strip_prefix("latency<") {
+                    query.with_max_latency(number(latency)?.saturating_sub(1))
+                } else if let Some(city) = word.strip_prefix("city=") {
+                    query.with_city(&city.replace('_', " "))
+                } else if let Some(sort) = lower.strip_prefix("sort=") {
+                    query.with_sort(match sort {
+                        "score" => ServerSort::Score,
+                        "latency" => ServerSort::Latency,
+                        "load" => ServerSort::Load,
+                        "bandwidth" => ServerSort::Bandwidth,
+                        "distance" => ServerSort::Distance,
+                        "name" => ServerSort::Name,
+                        _ => return Err(format!("Unknown sort: {}", sort)),
+                    })
+                } else if let Some(continent) = Continent::all().into_iter()
+                    .find(|c| c.name().replace(' ', "").eq_ignore_ascii_case(word))
+                {
+                    query.with_continent(continent)
+                } else {
+                    return Err(format!("Unknown filter: {}", word));
+                }
+            }
+        };
+    }
+    Ok(query)
+}
+
+async fn protocol_settings(connection: &mut VpnConnection, config: &mut VpnConfig, policy: &ManagedPolicy) {
     println!("\n╔═══════════════════════════════════════════╗");
     println!("║        PROTOCOL SETTINGS                  ║");
     println!("╚═══════════════════════════════════════════╝");
     
     let protocols = VpnProtocol::all();
     for (idx, protocol) in protocols.iter().enumerate() {
-        let recommended = if protocol.is_recommended() { "⭐" } else { "  " };
+        let recommended = if !config.security_policy.permits(*protocol) {
+            "🚫"
+        } else if protocol.is_recommended() {
+            "⭐"
+        } else {
+            "  "
+        };
         println!("{}. {} {} - {}", 
             idx + 1, recommended, protocol.name(), protocol.description());
         println!("   Security: {}/10 | Speed: {}/10", 
@@ -292,7 +1062,18 @@ async fn protocol_settings(connection: &mut VpnConnection, config: &mut VpnConfi
     if let Ok(num) = choice.trim().parse::<usize>() {
         if num > 0 && num <= protocols.len() {
             let protocol = protocols[num - 1];
+            if let Err(e) = config.security_policy.check(protocol) {
+                println!("\n❌ {}", e);
+                return;
+            }
+            
             let new_config = ProtocolConfig::new(protocol);
+            let mut changed = config.clone();
+            changed.protocol_config = new_config.clone();
+            if let Err(e) = policy.check_change(config, &changed) {
+                println!("\n🔒 {}", e);
+                return;
+            }
             
             config.protocol_config = new_config.clone();
             connection.set_protocol_config(new_config);
@@ -312,6 +1093,9 @@ fn security_settings(kill_switch: &mut KillSwitch, dns_manager: &mut DnsManager)
     println!("╚═══════════════════════════════════════════╝");
     println!("1. Kill Switch: {}", 
         if kill_switch.is_active() { "🟢 Enabled" } else { "🔴 Disabled" });
+    if kill_switch.is_blocking_ipv6() {
+        println!("   IPv6 is blocked, the server has no IPv6");
+    }
     println!("2. DNS Leak Protection: {}", 
         if dns_manager.is_leak_protected() { "🟢 Enabled" } else { "🔴 Disabled" });
     println!("3. DNS Settings");
@@ -322,8 +1106,10 @@ fn security_settings(kill_switch: &mut KillSwitch, dns_manager: &mut DnsManager)
     match choice.trim() {
         "1" => {
             if kill_switch.is_active() {
-                let _ = kill_switch.disable();
-                println!("✅ Kill switch disabled");
+                match kill_switch.disable() {
+                    Ok(()) => println!("✅ Kill switch disabled"),
+                    Err(e) => println!("🔒 {}", e),
+                }
             } else {
                 let _ = kill_switch.enable();
                 println!("✅ Kill switch enabled");
@@ -331,8 +1117,10 @@ fn security_settings(kill_switch: &mut KillSwitch, dns_manager: &mut DnsManager)
         }
         "2" => {
             let enabled = dns_manager.is_leak_protected();
-            dns_manager.enable_leak_protection(!enabled);
-            println!("✅ DNS leak protection {}", if !enabled { "enabled" } else { "disabled" });
+            match dns_manager.enable_leak_protection(!enabled) {
+                Ok(()) => println!("✅ DNS leak protection {}", if !enabled { "enabled" } else { "disabled" }),
+                Err(e) => println!("🔒 {}", e),
+            }
         }
         "3" => {
             println!("\nAvailable DNS Servers:");
@@ -345,7 +1133,7 @@ fn security_settings(kill_switch: &mut KillSwitch, dns_manager: &mut DnsManager)
     }
 }
 
-fn split_tunnel_menu(split_tunnel: &mut SplitTunnel) {
+fn split_tunnel_menu(split_tunnel: &mut SplitTunnel, entitlements: &Entitlements) {
     println!("\n╔═══════════════════════════════════════════╗");
     println!("║        SPLIT TUNNELING                    ║");
     println!("╚═══════════════════════════════════════════╝");
@@ -364,6 +1152,8 @@ fn split_tunnel_menu(split_tunnel: &mut SplitTunnel) {
             if split_tunnel.is_active() {
                 let _ = split_tunnel.disable();
                 println!("✅ Split tunneling disabled");
+            } else if let Err(e) = entitlements.check(Feature::SplitTunneling) {
+                println!("❌ {}", e);
             } else {
                 let _ = split_tunnel.enable();
                 println!("✅ Split tunneling enabled");
@@ -391,23 +1181,35 @@ fn split_tunnel_menu(split_tunnel: &mut SplitTunnel) {
     }
 }
 
-fn show_statistics(analytics: &Analytics) {
+fn show_statistics(analytics: &Analytics, data_cap: &DataCapTracker) {
     println!("\n{}", analytics.generate_summary_report());
+    println!("\nThis billing period: {}, resets on {}", data_cap.describe(), data_cap.resets_at().format("%Y-%m-%d"));
 }
 
-fn settings_menu(config: &mut VpnConfig) {
+fn settings_menu(config: &mut VpnConfig, layers: &LayeredConfig, policy: &ManagedPolicy) {
+    // The setting each numbered item changes
+    const KEYS: [&str; 6] = ["auto_connect", "start_on_boot", "show_notifications", "block_ads", "block_malware", "scoring"];
+    let lock = |idx: usize| if policy.is_locked(KEYS[idx]) { " 🔒" } else { "" };
+    
     println!("\n╔═══════════════════════════════════════════╗");
     println!("║            SETTINGS                       ║");
     println!("╚═══════════════════════════════════════════╝");
-    println!("1. Auto-connect: {}", if config.auto_connect { "🟢 On" } else { "🔴 Off" });
-    println!("2. Start on boot: {}", if config.start_on_boot { "🟢 On" } else { "🔴 Off" });
-    println!("3. Show notifications: {}", if config.show_notifications { "🟢 On" } else { "🔴 Off" });
-    println!("4. Block ads: {}", if config.block_ads { "🟢 On" } else { "🔴 Off" });
-    println!("5. Block malware: {}", if config.block_malware { "🟢 On" } else { "🔴 Off" });
-    println!("6. Load Preset Configuration");
+    println!("1. Auto-connect: {}{}", if config.auto_connect { "🟢 On" } else { "🔴 Off" }, lock(0));
+    println!("2. Start on boot: {}{}", if config.start_on_boot { "🟢 On" } else { "🔴 Off" }, lock(1));
+    println!("3. Show notifications: {}{}", if config.show_notifications { "🟢 On" } else { "🔴 Off" }, lock(2));
+    println!("4. Block ads: {}{}", if config.block_ads { "🟢 On" } else { "🔴 Off" }, lock(3));
+    println!("5. Block malware: {}{}", if config.block_malware { "🟢 On" } else { "🔴 Off" }, lock(4));
+    println!("6. Server scoring: {}{}", config.scoring.name(), lock(5));
+    println!("7. Check Configuration");
+    println!("8. Show Setting Sources");
     println!("0. Back");
     
     let choice = get_user_input("\nEnter your choice: ");
+    let item = choice.trim().parse::<usize>().ok().filter(|n| (1..=KEYS.len()).contains(n));
+    if let Some(Err(e)) = item.map(|n| policy.check(KEYS[n - 1])) {
+        println!("🔒 {}", e);
+        return;
+    }
     
     match choice.trim() {
         "1" => {
@@ -431,26 +1233,39 @@ fn settings_menu(config: &mut VpnConfig) {
             println!("✅ Malware blocking {}", if config.block_malware { "enabled" } else { "disabled" });
         }
         "6" => {
-            println!("\nPreset Configurations:");
-            println!("1. Maximum Security");
-            println!("2. Streaming Optimized");
-            println!("3. Torrenting");
+            println!("\nScoring Profiles:");
+            let profiles = ScoringProfile::all();
+            for (idx, profile) in profiles.iter().enumerate() {
+                let weights = profile.weights();
+                println!("{}. {} (load {:.2}, latency {:.2}, bandwidth {:.2}, reliability {:.2})",
+                    idx + 1, profile.name(),
+                    weights.load, weights.latency, weights.bandwidth, weights.reliability);
+            }
             
-            let preset_choice = get_user_input("Select preset: ");
-            match preset_choice.trim() {
-                "1" => {
-                    *config = VpnConfig::preset_maximum_security();
-                    println!("✅ Loaded Maximum Security preset");
+            let profile_choice = get_user_input("Select profile: ");
+            if let Ok(num) = profile_choice.trim().parse::<usize>() {
+                if num > 0 && num <= profiles.len() {
+                    config.scoring = profiles[num - 1];
+                    println!("✅ Servers are now ranked for {}", config.scoring.name());
                 }
-                "2" => {
-                    *config = VpnConfig::preset_streaming();
-                    println!("✅ Loaded Streaming preset");
-                }
-                "3" => {
-                    *config = VpnConfig::preset_torrenting();
-                    println!("✅ Loaded Torrenting preset");
+            }
+        }
+        "7" => {
+            let report = config.check();
+            if report.issues().is_empty() {
+                println!("✅ No problems found");
+            } else {
+                println!("\n{}", report);
+                if report.is_valid() {
+                    println!("\n⚠️  Usable, but see the warnings above");
                 }
-                _ => {}
+            }
+        }
+        "8" => {
+            // As loaded; edits made since then are not reflected
+            println!();
+            for (key, value, layer) in layers.entries() {
+                println!("{} = {} [{}]", key, value, layer);
             }
         }
         _ => {}
@@ -0,0 +1,440 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::storage::write_atomic;
+use crate::server::VpnServer;
+use crate::{Result, VpnError};
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Ed25519 key the server catalog is signed with. The private half never
+/// ships with the client.
+pub const MANIFEST_PUBLIC_KEY: &str = "k77lmBIAK5LmsG8PdN6ZSAcIFEDJxmpIkztmzxMIpV4=";
+
+// Offline fallback when neither the network nor the cache has a catalog
+const BUNDLED_MANIFEST: &str = include_str!("servers.manifest.json");
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerManifest {
+    pub version: u32,
+    pub generated_at: DateTime<Utc>,
+    pub servers: Vec<VpnServer>,
+}
+
+/// On-disk and on-the-wire form. The signature covers the exact payload
+/// bytes, so nothing has to agree on a canonical JSON encoding.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedManifest {
+    pub payload: String,    // Base64 of the manifest JSON
+    pub signature: String,  // Base64 Ed25519 signature over the payload
+}
+
+impl ServerManifest {
+    pub fn bundled() -> Self {
+        SignedManifest::parse(BUNDLED_MANIFEST.as_bytes())
+            .and_then(|signed| signed.verify(&pinned_key()))
+            .expect("bundled server manifest is signed with the pinned key")
+    }
+
+    pub fn sign(&self, key: &SigningKey) -> Result<SignedManifest> {
+        let payload = serde_json::to_vec(self)
+            .map_err(|e| VpnError::ManifestError(format!("Failed to serialize manifest: {}", e)))?;
+        Ok(SignedManifest {
+            signature: BASE64.encode(key.sign(&payload).to_bytes()),
+            payload: BASE64.encode(payload),
+        })
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.version > MANIFEST_VERSION {
+            return Err(VpnError::ManifestError(format!(
+                "Manifest version {} is newer than supported version {}",
+                self.version, MANIFEST_VERSION
+            )));
+        }
+
+        let mut ids = HashSet::new();
+        for server in &self.servers {
+            if !ids.insert(&server.id) {
+                return Err(VpnError::ManifestError(format!("Duplicate server id {}", server.id)));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SignedManifest {
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes)
+            .map_err(|e| VpnError::ManifestError(format!("Failed to parse manifest: {}", e)))
+    }
+
+    pub fn verify(&self, key: &VerifyingKey) -> Result<ServerManifest> {
+        let payload = BASE64.decode(&self.payload)
+            .map_err(|e| VpnError::ManifestError(format!("Invalid manifest payload: {}", e)))?;
+        let signature = BASE64.decode(&self.signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| VpnError::ManifestError("Invalid manifest signature encoding".to_string()))?;
+
+        key.verify(&payload, &signature)
+            .map_err(|_| VpnError::ManifestError("Manifest signature does not match the pinned key".to_string()))?;
+
+        let manifest: ServerManifest = serde_json::from_slice(&payload)
+            .map_err(|e| VpnError::ManifestError(format!("Failed to parse manifest payload: {}", e)))?;
+        manifest.check()?;
+        Ok(manifest)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| VpnError::ManifestError(format!("Failed to serialize manifest: {}", e)))
+    }
+}
+
+// A 304 is only a valid answer to a conditional request
+fn unexpected_not_modified() -> VpnError {
+    VpnError::ManifestError("server answered 304 to an unconditional request".to_string())
+}
+
+pub fn pinned_key() -> VerifyingKey {
+    let bytes: [u8; 32] = BASE64.decode(MANIFEST_PUBLIC_KEY)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .expect("pinned manifest key is 32 bytes of base64");
+    VerifyingKey::from_bytes(&bytes).expect("pinned manifest key is a valid Ed25519 key")
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ManifestSource {
+    File(PathBuf),
+    Http(String),
+}
+
+/// What the last successful fetch returned, sent back to skip unchanged
+/// downloads. Files use their modification time as Last-Modified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Fetched {
+    NotModified,
+    Modified { body: Vec<u8>, validators: CacheValidators },
+}
+
+impl ManifestSource {
+    pub async fn fetch(&self) -> Result<Vec<u8>> {
+        match self.fetch_if_modified(&CacheValidators::default()).await? {
+            Fetched::Modified { body, .. } => Ok(body),
+            Fetched::NotModified => Err(unexpected_not_modified()),
+        }
+    }
+
+    pub async fn fetch_if_modified(&self, validators: &CacheValidators) -> Result<Fetched> {
+        match self {
+            ManifestSource::File(path) => {
+                let read_error = |e: std::io::Error| {
+                    VpnError::ManifestError(format!("Failed to read {}: {}", path.display(), e))
+                };
+                let modified = tokio::fs::metadata(path).await
+                    .and_then(|m| m.modified())
+                    .map_err(read_error)?;
+                let last_modified = Some(DateTime::<Utc>::from(modified).to_rfc3339_opts(SecondsFormat::Nanos, true));
+                if validators.last_modified.is_some() && validators.last_modified == last_modified {
+                    return Ok(Fetched::NotModified);
+                }
+
+                let body = tokio::fs::read(path).await.map_err(read_error)?;
+                Ok(Fetched::Modified {
+                    body,
+                    validators: CacheValidators { etag: None, last_modified },
+                })
+            }
+            ManifestSource::Http(url) => {
+                let client = reqwest::Client::builder()
+                    .timeout(Duration::from_secs(15))
+                    .build()
+                    .map_err(|e| VpnError::NetworkError(e.to_string()))?;
+                let mut request = client.get(url);
+                if let Some(etag) = &validators.etag {
+                    request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+                }
+
+                let response = request.send().await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| VpnError::NetworkError(format!("Failed to fetch manifest: {}", e)))?;
+                if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+                    return Ok(Fetched::NotModified);
+                }
+
+                let header = |name| {
+                    response.headers().get(name)
+                        .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+                        .map(str::to_string)
+                };
+                let validators = CacheValidators {
+                    etag: header(reqwest::header::ETAG),
+                    last_modified: header(reqwest::header::LAST_MODIFIED),
+                };
+                let body = response.bytes().await
+                    .map_err(|e| VpnError::NetworkError(format!("Failed to fetch manifest: {}", e)))?;
+                Ok(Fetched::Modified { body: body.to_vec(), validators })
+            }
+        }
+    }
+}
+
+/// Fetches and verifies the catalog, keeping the last copy that verified
+/// so the client can still start offline. A catalog older than one already
+/// seen is refused, so an old signed copy cannot be replayed.
+pub struct ManifestLoader {
+    source: ManifestSource,
+    key: VerifyingKey,
+    cache_path: Option<PathBuf>,
+    validators: CacheValidators,
+    newest: Option<DateTime<Utc>>,  // generated_at of the latest accepted catalog
+}
+
+impl ManifestLoader {
+    pub fn new(source: ManifestSource) -> Self {
+        Self {
+            source,
+            key: pinned_key(),
+            cache_path: None,
+            validators: CacheValidators::default(),
+            newest: None,
+        }
+    }
+
+    pub fn with_pinned_key(mut self, key: VerifyingKey) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn with_cache(mut self, path: PathBuf) -> Self {
+        self.cache_path = Some(path);
+        self
+    }
+
+    pub fn source(&self) -> &ManifestSource {
+        &self.source
+    }
+
+    pub async fn load(&mut self) -> Result<ServerManifest> {
+        self.validators = CacheValidators::default();
+        let error = match self.refresh().await {
+            Ok(Some(manifest)) => return Ok(manifest),
+            Ok(None) => unexpected_not_modified(),
+            Err(e) => e,
+        };
+
+        log::warn!("Server manifest unavailable ({}), trying cached copy", error);
+        match self.load_cached() {
+            Some(manifest) => {
+                self.newest = self.newest.max(Some(manifest.generated_at));
+                Ok(manifest)
+            }
+            None => Err(error),
+        }
+    }
+
+    /// The cached copy is verified again; a tampered cache is ignored
+    pub fn load_cached(&self) -> Option<ServerManifest> {
+        let path = self.cache_path.as_ref()?;
+        let raw = fs::read(path).ok()?;
+        match SignedManifest::parse(&raw).and_then(|signed| signed.verify(&self.key)) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                log::warn!("Ignoring cached manifest {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Conditional fetch against the previous response. `None` means the
+    /// source has not changed since the last load or refresh.
+    pub async fn refresh(&mut self) -> Result<Option<ServerManifest>> {
+        let (raw, validators) = match self.source.fetch_if_modified(&self.validators).await? {
+            Fetched::NotModified => return Ok(None),
+            Fetched::Modified { body, validators } => (body, validators),
+        };
+
+        let manifest = SignedManifest::parse(&raw)?.verify(&self.key)?;
+        let newest = self.newest.max(self.load_cached().map(|cached| cached.generated_at));
+        if let Some(newest) = newest.filter(|newest| manifest.generated_at < *newest) {
+            return Err(VpnError::ManifestError(format!(
+                "Manifest generated at {} is older than the one already seen from {}",
+                manifest.generated_at, newest
+            )));
+        }
+
+        self.store_cache(&raw);
+        self.validators = validators;
+        self.newest = Some(manifest.generated_at);
+        Ok(Some(manifest))
+    }
+
+    fn store_cache(&self, raw: &[u8]) {
+        let Some(path) = &self.cache_path else { return };
+        if let Err(e) = write_atomic(path, raw) {
+            log::warn!("Failed to cache server manifest at {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ServerManager;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("vpn-manifest-{}-{}", name, rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn signed(key: &SigningKey, servers: usize) -> SignedManifest {
+        let mut manifest = ServerManifest::bundled();
+        manifest.servers.truncate(servers);
+        manifest.sign(key).unwrap()
+    }
+
+    #[test]
+    fn test_bundled_manifest() {
+        let manifest = ServerManifest::bundled();
+        assert!(manifest.servers.len() >= 25);
+        assert!(manifest.servers.iter().all(|s| s.location.latitude != 0.0));
+
+        let manager = ServerManager::from_manifest(manifest);
+        assert!(manager.get_fastest_server(crate::scoring::ScoringProfile::Balanced).is_some());
+    }
+
+    #[test]
+    fn test_signature_is_checked() {
+        let key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let mut manifest = signed(&key, 3);
+        assert_eq!(manifest.verify(&key.verifying_key()).unwrap().servers.len(), 3);
+        assert!(manifest.verify(&pinned_key()).is_err());
+
+        // Re-encode a modified payload under the original signature
+        let mut payload = String::from_utf8(BASE64.decode(&manifest.payload).unwrap()).unwrap();
+        payload = payload.replacen("\"load\":0", "\"load\":1", 1);
+        manifest.payload = BASE64.encode(payload);
+        assert!(matches!(manifest.verify(&key.verifying_key()), Err(VpnError::ManifestError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_load_from_file_with_cache_fallback() {
+        let dir = temp_dir("load");
+        let key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let source = dir.join("servers.json");
+        let cache = dir.join("cache/servers.json");
+        fs::write(&source, signed(&key, 5).to_json().unwrap()).unwrap();
+
+        let mut loader = ManifestLoader::new(ManifestSource::File(source.clone()))
+            .with_pinned_key(key.verifying_key())
+            .with_cache(cache.clone());
+        assert_eq!(loader.load().await.unwrap().servers.len(), 5);
+        assert!(cache.exists());
+
+        // Source gone: start from the last-known-good copy
+        fs::remove_file(&source).unwrap();
+        assert_eq!(loader.load().await.unwrap().servers.len(), 5);
+
+        // A source signed by someone else is rejected and the cache is kept
+        let attacker = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        fs::write(&source, signed(&attacker, 1).to_json().unwrap()).unwrap();
+        assert_eq!(loader.load().await.unwrap().servers.len(), 5);
+
+        // An older catalog, even if properly signed, does not replace the cache
+        let mut old = ServerManifest::bundled();
+        old.servers.truncate(1);
+        old.generated_at = loader.load_cached().unwrap().generated_at - chrono::Duration::days(1);
+        fs::write(&source, old.sign(&key).unwrap().to_json().unwrap()).unwrap();
+        let error = loader.refresh().await.unwrap_err();
+        assert!(error.to_string().contains("older"), "{}", error);
+        assert_eq!(loader.load().await.unwrap().servers.len(), 5);
+
+        // Without a usable cache the loader still remembers what it saw
+        fs::write(&cache, "{}").unwrap();
+        assert!(loader.load().await.is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_http_conditional_refresh() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let body = signed(&key, 2).to_json().unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/servers.json", listener.local_addr().unwrap());
+
+        // Minimal HTTP server that honours If-None-Match
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                let response = if request.contains("if-none-match: \"v1\"") {
+                    "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n".to_string()
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(), body
+                    )
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let mut loader = ManifestLoader::new(ManifestSource::Http(url))
+            .with_pinned_key(key.verifying_key());
+        assert_eq!(loader.load().await.unwrap().servers.len(), 2);
+        assert!(loader.refresh().await.unwrap().is_none());
+
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+    }
+
+    #[tokio::test]
+    async fn test_unconditional_304_is_an_error() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/servers.json", listener.local_addr().unwrap());
+        // Answers everything with 304, whatever was asked
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0u8; 4096];
+                let _ = stream.read(&mut buf).await.unwrap();
+                stream.write_all(b"HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n").await.unwrap();
+            }
+        });
+
+        let source = ManifestSource::Http(url);
+        let error = source.fetch().await.unwrap_err();
+        assert!(error.to_string().contains("304"), "{}", error);
+        let mut loader = ManifestLoader::new(source);
+        assert!(matches!(loader.load().await, Err(VpnError::ManifestError(_))));
+        server.await.unwrap();
+    }
+}
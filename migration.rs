@@ -0,0 +1,102 @@
+use serde_json::Value;
+use crate::country::Country;
+use crate::{Result, VpnError};
+
+/// Version written by this build. Bump it and add a step to `MIGRATIONS`
+/// whenever a config field is renamed, moved or changes meaning; new
+/// fields with a sensible default only need `#[serde(default)]`.
+pub const CURRENT_SCHEMA_VERSION: u32 = 2;
+
+// Files written before schema_version existed
+const UNVERSIONED: u32 = 1;
+
+type Migration = fn(&mut Value) -> Result<()>;
+
+// Entry N upgrades version N + 1 to version N + 2
+const MIGRATIONS: &[Migration] = &[
+    v1_to_v2,
+];
+
+/// Schema version of a parsed config file
+pub fn schema_version(config: &Value) -> Result<u32> {
+    match config.get("schema_version") {
+        None => Ok(UNVERSIONED),
+        Some(version) => version.as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= UNVERSIONED)
+            .ok_or_else(|| VpnError::ConfigError(format!("Invalid schema_version: {}", version))),
+    }
+}
+
+/// Upgrades `config` in place to `CURRENT_SCHEMA_VERSION`, one version at
+/// a time. Returns the version it started from.
+pub fn migrate(config: &mut Value) -> Result<u32> {
+    if !config.is_object() {
+        return Err(VpnError::ConfigError("Config must be a JSON object".to_string()));
+    }
+
+    let from = schema_version(config)?;
+    if from > CURRENT_SCHEMA_VERSION {
+        return Err(VpnError::ConfigError(format!(
+            "Config schema version {} is newer than this app supports ({})",
+            from, CURRENT_SCHEMA_VERSION
+        )));
+    }
+
+    for version in from..CURRENT_SCHEMA_VERSION {
+        MIGRATIONS[(version - UNVERSIONED) as usize](config)?;
+        config["schema_version"] = Value::from(version + 1);
+        log::info!("Migrated config from schema version {} to {}", version, version + 1);
+    }
+    Ok(from)
+}
+
+// Countries were stored by enum name ("UnitedStates") before the ISO table;
+// they are ISO alpha-2 codes from v2 on
+fn v1_to_v2(config: &mut Value) -> Result<()> {
+    let Some(multi_hop) = config.get_mut("multi_hop").and_then(Value::as_object_mut) else {
+        return Ok(());
+    };
+    for key in ["entry_country", "exit_country"] {
+        if let Some(Value::String(name)) = multi_hop.get(key) {
+            let country: Country = name.parse()?;
+            multi_hop.insert(key.to_string(), Value::from(country.code()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_migrations_cover_every_version() {
+        assert_eq!(MIGRATIONS.len() as u32, CURRENT_SCHEMA_VERSION - UNVERSIONED);
+    }
+
+    #[test]
+    fn test_v1_country_names_become_codes() {
+        let mut config = json!({
+            "multi_hop": { "enabled": true, "entry_country": "Switzerland", "exit_country": null }
+        });
+        assert_eq!(migrate(&mut config).unwrap(), 1);
+        assert_eq!(config["schema_version"], CURRENT_SCHEMA_VERSION);
+        assert_eq!(config["multi_hop"]["entry_country"], "CH");
+        assert!(config["multi_hop"]["exit_country"].is_null());
+
+        // Already current: nothing to do
+        let before = config.clone();
+        assert_eq!(migrate(&mut config).unwrap(), CURRENT_SCHEMA_VERSION);
+        assert_eq!(config, before);
+    }
+
+    #[test]
+    fn test_rejects_unknown_versions() {
+        assert!(migrate(&mut json!({ "schema_version": CURRENT_SCHEMA_VERSION + 1 })).is_err());
+        assert!(migrate(&mut json!({ "schema_version": 0 })).is_err());
+        assert!(migrate(&mut json!({ "schema_version": "2" })).is_err());
+        assert!(migrate(&mut json!([])).is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use serde::{Deserialize, Serialize};
+use crate::encryption::{generate_random_key, EncryptionLevel, EncryptionManager};
+use crate::scoring::ScoringWeights;
+use crate::server::{Country, VpnServer};
+use crate::{Result, VpnError};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MultiHopConfig {
+    pub enabled: bool,
+    pub entry_country: Option<Country>,  // None = pick by score
+    pub exit_country: Option<Country>,
+}
+
+/// An ordered list of servers; traffic enters at the first hop and leaves
+/// the VPN at the last one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerChain {
+    hops: Vec<VpnServer>,
+}
+
+impl ServerChain {
+    pub fn new(hops: Vec<VpnServer>) -> Result<Self> {
+        if hops.len() < 2 {
+            return Err(VpnError::ConfigError(
+                "A multi-hop chain needs at least two servers".to_string()
+            ));
+        }
+
+        for (idx, hop) in hops.iter().enumerate() {
+            if hops[..idx].iter().any(|h| h.id == hop.id) {
+                return Err(VpnError::ConfigError(format!(
+                    "Server {} appears more than once in the chain",
+                    hop.name
+                )));
+            }
+        }
+
+        Ok(Self { hops })
+    }
+
+    pub fn hops(&self) -> &[VpnServer] {
+        &self.hops
+    }
+
+    pub fn entry(&self) -> &VpnServer {
+        &self.hops[0]
+    }
+
+    pub fn exit(&self) -> &VpnServer {
+        &self.hops[self.hops.len() - 1]
+    }
+
+    pub fn len(&self) -> usize {
+        self.hops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hops.is_empty()
+    }
+
+    pub fn into_hops(self) -> Vec<VpnServer> {
+        self.hops
+    }
+
+    /// A chain is as loaded as its busiest hop, as slow as all hops combined,
+    /// as wide as its narrowest link and as reliable as its flakiest hop.
+    pub fn combined_score(&self) -> f64 {
+        let load = self.hops.iter().map(|h| h.load).max().unwrap_or(100);
+        let latency = self.hops.iter().map(|h| h.latency).sum();
+        let bandwidth = self.hops.iter().map(|h| h.bandwidth).min().unwrap_or(0);
+        let reliability = self.hops.iter().filter_map(|h| h.reliability).fold(1.0, f64::min);
+        ScoringWeights::default().combine(load, latency, bandwidth, reliability)
+    }
+
+    pub fn encryption_level(&self) -> EncryptionLevel {
+        if self.hops.len() >= EncryptionLevel::Maximum.min_hops() {
+            EncryptionLevel::Maximum
+        } else {
+            EncryptionLevel::Strong
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        self.hops.iter()
+            .map(|h| format!("{} {}", h.location.country.flag_emoji(), h.name))
+            .collect::<Vec<_>>()
+            .join(" → ")
+    }
+}
+
+/// Nested per-hop encryption. Outgoing packets are sealed for the exit hop
+/// first and the entry hop last, so each server can only remove its own
+/// layer and never sees both the client and the destination.
+pub struct OnionLayers {
+    layers: Vec<EncryptionManager>,  // Index 0 = entry hop
+}
+
+impl OnionLayers {
+    pub fn new(hops: usize) -> Self {
+        // In a real implementation each key would come from the handshake
+        // with that hop, tunnelled through the hops before it
+        Self {
+            layers: (0..hops)
+                .map(|_| EncryptionManager::new(&generate_random_key()))
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub fn wrap(&self, packet: &[u8]) -> Result<Vec<u8>> {
+        let mut data = packet.to_vec();
+        for layer in self.layers.iter().rev() {
+            data = layer.encrypt(&data)?;
+        }
+        Ok(data)
+    }
+
+    /// Removes the layer belonging to `hop`, as that server would
+    pub fn peel(&self, hop: usize, data: &[u8]) -> Result<Vec<u8>> {
+        self.layers
+            .get(hop)
+            .ok_or_else(|| VpnError::EncryptionError(format!("No layer for hop {}", hop)))?
+            .decrypt(data)
+    }
+
+    /// Opens a reply, which the exit hop sealed first and the entry hop last
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut data = data.to_vec();
+        for layer in &self.layers {
+            data = layer.decrypt(&data)?;
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{ServerLocation, ServerManager};
+    use std::collections::HashMap;
+
+    fn country(code: &str) -> Country {
+        Country::from_alpha2(code).unwrap()
+    }
+
+    fn server(id: &str, country: Country, load: u8, latency: u32) -> VpnServer {
+        VpnServer {
+            id: id.to_string(),
+            name: id.to_string(),
+            location: ServerLocation {
+                city: "City".to_string(),
+                country,
+                latitude: 0.0,
+                longitude: 0.0,
+            },
+            host: format!("{}.vpn.test", id),
+            port: 443,
+            port_ranges: HashMap::new(),
+            public_keys: HashMap::new(),
+            load,
+            latency,
+            bandwidth: 1000,
+            is_premium: false,
+            supports_p2p: true,
+            supports_streaming: true,
+            supports_ipv6: true,
+            online: true,
+            former_ids: Vec::new(),
+            distance_km: None,
+            reliability: None,
+            backoff_until: None,
+        }
+    }
+
+    #[test]
+    fn test_chain_validation() {
+        let a = server("a", country("CH"), 10, 20);
+        let b = server("b", country("SE"), 10, 20);
+
+        assert!(ServerChain::new(vec![a.clone()]).is_err());
+        assert!(ServerChain::new(vec![a.clone(), a.clone()]).is_err());
+
+        let chain = ServerChain::new(vec![a, b]).unwrap();
+        assert_eq!(chain.entry().id, "a");
+        assert_eq!(chain.exit().id, "b");
+        assert!(matches!(chain.encryption_level(), EncryptionLevel::Maximum));
+    }
+
+    #[test]
+    fn test_combined_score_penalizes_slow_hops() {
+        let fast = ServerChain::new(vec![
+            server("a", country("CH"), 10, 20),
+            server("b", country("SE"), 10, 20),
+        ]).unwrap();
+        let slow = ServerChain::new(vec![
+            server("a", country("CH"), 10, 20),
+            server("c", country("JP"), 10, 250),
+        ]).unwrap();
+
+        assert!(fast.combined_score() > slow.combined_score());
+        assert!(fast.combined_score() < fast.entry().score());
+    }
+
+    #[test]
+    fn test_best_chain_respects_countries() {
+        let manager = ServerManager::new();
+
+        let chain = manager.get_best_chain(None, None).unwrap();
+        assert_ne!(chain.entry().location.country, chain.exit().location.country);
+
+        let chain = manager
+            .get_best_chain(Some(&country("CH")), Some(&country("SE")))
+            .unwrap();
+        assert_eq!(chain.entry().location.country, country("CH"));
+        assert_eq!(chain.exit().location.country, country("SE"));
+
+        assert!(manager.get_best_chain(Some(&country("JP")), Some(&country("JP"))).is_none());
+    }
+
+    #[test]
+    fn test_onion_layers() {
+        let layers = OnionLayers::new(2);
+        let packet = b"GET / HTTP/1.1";
+
+        let wrapped = layers.wrap(packet).unwrap();
+        assert!(layers.peel(1, &wrapped).is_err());
+
+        // Entry hop peels its layer and forwards, exit hop peels the last one
+        let at_exit = layers.peel(0, &wrapped).unwrap();
+        assert_ne!(at_exit, packet.to_vec());
+        assert_eq!(layers.peel(1, &at_exit).unwrap(), packet.to_vec());
+
+        // Replies get the same nesting: exit layer innermost, entry outermost
+        let reply = layers.wrap(b"HTTP/1.1 200 OK").unwrap();
+        assert_eq!(layers.open(&reply).unwrap(), b"HTTP/1.1 200 OK".to_vec());
+    }
+}
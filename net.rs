@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use crate::{Result, VpnError};
+
+/// An address prefix such as 10.0.0.0/8 or fc00::/7. A bare address is
+/// read as a single host (/32 or /128).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn new(addr: IpAddr, prefix: u8) -> Result<Self> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(VpnError::ConfigError(format!(
+                "Prefix length /{} is too long for {}", prefix, addr
+            )));
+        }
+
+        // Keep only the network bits so equal prefixes compare equal
+        let addr = match addr {
+            IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) & mask_v4(prefix))),
+            IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask_v6(prefix))),
+        };
+        Ok(Self { addr, prefix })
+    }
+
+    /// 0.0.0.0/0 or ::/0
+    pub fn default_route(ipv6: bool) -> Self {
+        if ipv6 {
+            Self { addr: IpAddr::V6(Ipv6Addr::UNSPECIFIED), prefix: 0 }
+        } else {
+            Self { addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED), prefix: 0 }
+        }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        self.addr.is_ipv6()
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                u32::from(ip) & mask_v4(self.prefix) == u32::from(net)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                u128::from(ip) & mask_v6(self.prefix) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+impl FromStr for IpNetwork {
+    type Err = VpnError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || VpnError::ConfigError(format!("Invalid IP range: {}", s));
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => {
+                let addr: IpAddr = addr.parse().map_err(|_| invalid())?;
+                (addr, prefix.parse::<u8>().map_err(|_| invalid())?)
+            }
+            None => {
+                let addr: IpAddr = s.trim().parse().map_err(|_| invalid())?;
+                (addr, if addr.is_ipv4() { 32 } else { 128 })
+            }
+        };
+        Self::new(addr, prefix)
+    }
+}
+
+impl TryFrom<String> for IpNetwork {
+    type Error = VpnError;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<IpNetwork> for String {
+    fn from(network: IpNetwork) -> Self {
+        network.to_string()
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Private, link-local and unique-local ranges for both address families
+pub fn lan_networks() -> Vec<IpNetwork> {
+    ["10.0.0.0/8", "172.16.0.0/12", "192.168.0.0/16", "169.254.0.0/16", "fe80::/10", "fc00::/7"]
+        .iter()
+        .map(|s| s.parse().expect("valid LAN range"))
+        .collect()
+}
+
+/// What happens to IPv6 traffic while the tunnel is up
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Ipv6Mode {
+    Tunnel,      // Server carries IPv6, route it through the tunnel
+    #[default]
+    Blackhole,   // Server has no IPv6, drop it instead of leaking
+    Unprotected, // Leak protection off, IPv6 uses the local network
+}
+
+impl Ipv6Mode {
+    pub fn select(tunnel_has_ipv6: bool, leak_protection: bool) -> Self {
+        if tunnel_has_ipv6 {
+            Ipv6Mode::Tunnel
+        } else if leak_protection {
+            Ipv6Mode::Blackhole
+        } else {
+            Ipv6Mode::Unprotected
+        }
+    }
+
+    /// Prefixes routed into the tunnel interface (WireGuard's AllowedIPs).
+    /// A blackhole still claims ::/0 so IPv6 dies inside the tunnel rather
+    /// than falling back to the physical interface.
+    pub fn allowed_ips(&self) -> Vec<IpNetwork> {
+        match self {
+            Ipv6Mode::Tunnel | Ipv6Mode::Blackhole => {
+                vec![IpNetwork::default_route(false), IpNetwork::default_route(true)]
+            }
+            Ipv6Mode::Unprotected => vec![IpNetwork::default_route(false)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_contains() {
+        let lan: IpNetwork = "192.168.1.77/24".parse().unwrap();
+        assert_eq!(lan.to_string(), "192.168.1.0/24");
+        assert!(lan.contains("192.168.1.200".parse().unwrap()));
+        assert!(!lan.contains("192.168.2.1".parse().unwrap()));
+        assert!(!lan.contains("::1".parse().unwrap()));
+
+        let ula: IpNetwork = "fd00:1234::/32".parse().unwrap();
+        assert!(ula.contains("fd00:1234::42".parse().unwrap()));
+        assert!(!ula.contains("fd00:1235::42".parse().unwrap()));
+
+        let host: IpNetwork = "2001:db8::1".parse().unwrap();
+        assert_eq!(host.prefix(), 128);
+        assert!(IpNetwork::default_route(true).contains("2001:db8::1".parse().unwrap()));
+
+        assert!("10.0.0.0/33".parse::<IpNetwork>().is_err());
+        assert!("example.com/8".parse::<IpNetwork>().is_err());
+    }
+
+    #[test]
+    fn test_ipv6_mode_selection() {
+        assert_eq!(Ipv6Mode::select(true, true), Ipv6Mode::Tunnel);
+        assert_eq!(Ipv6Mode::select(false, true), Ipv6Mode::Blackhole);
+        assert_eq!(Ipv6Mode::select(false, false), Ipv6Mode::Unprotected);
+
+        assert!(Ipv6Mode::Blackhole.allowed_ips().iter().any(|n| n.is_ipv6()));
+        assert!(!Ipv6Mode::Unprotected.allowed_ips().iter().any(|n| n.is_ipv6()));
+    }
+}
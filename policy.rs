@@ -0,0 +1,229 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use crate::config::VpnConfig;
+use crate::format::ConfigFormat;
+use crate::layers::pointer;
+use crate::reload::ConfigDiff;
+use crate::{Result, VpnError};
+
+/// Settings an administrator has fixed for everyone on the machine, read
+/// from a system file the app never writes, e.g.
+///
+/// ```json
+/// {
+///   "message": "Contact IT at help@example.com",
+///   "settings": {
+///     "kill_switch.mode": "Always",
+///     "dns_mode": "Auto",
+///     "security_policy.banned_protocols": ["PPTP", "L2TP"]
+///   }
+/// }
+/// ```
+///
+/// Keys are dotted paths into the config, as for `--set`. A locked key
+/// holding an object locks everything inside it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ManagedPolicy {
+    #[serde(default)]
+    message: Option<String>,                // Shown when a change is refused
+    #[serde(default)]
+    settings: BTreeMap<String, Value>,
+}
+
+impl ManagedPolicy {
+    /// `None` when there is no policy file. A policy that names unknown
+    /// settings or values the config would refuse is an error, not ignored.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(VpnError::ConfigError(format!("Failed to read policy {}: {}", path.display(), e))),
+        };
+        warn_if_writable(path);
+
+        let invalid = |e: String| VpnError::ConfigError(format!("Invalid policy {}: {}", path.display(), e));
+        let value = ConfigFormat::detect(path, &contents).parse(&contents).map_err(|e| invalid(e.to_string()))?;
+        let policy: Self = serde_json::from_value(value).map_err(|e| invalid(e.to_string()))?;
+
+        let defaults = serde_json::to_value(VpnConfig::default()).map_err(|e| invalid(e.to_string()))?;
+        if let Some(key) = policy.settings.keys().find(|key| defaults.pointer(&pointer(key)).is_none()) {
+            return Err(invalid(format!("Unknown setting: {}", key)));
+        }
+        let enforced = policy.enforce(&VpnConfig::default()).map_err(|e| invalid(e.to_string()))?;
+        let report = enforced.check();
+        if !report.is_valid() {
+            return Err(invalid(report.to_string()));
+        }
+
+        // Objects are locked whole: fields the file leaves out are locked
+        // to their defaults
+        let mut policy = policy;
+        let enforced = serde_json::to_value(enforced).map_err(|e| invalid(e.to_string()))?;
+        for (key, value) in policy.settings.iter_mut() {
+            if let Some(full) = enforced.pointer(&pointer(key)) {
+                *value = full.clone();
+            }
+        }
+        Ok(Some(policy))
+    }
+
+    /// Locks `key` to `value`
+    pub fn with_locked(mut self, key: &str, value: Value) -> Self {
+        self.settings.insert(key.to_string(), value);
+        self
+    }
+
+    pub fn with_message(mut self, message: &str) -> Self {
+        self.message = Some(message.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+
+    pub fn settings(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.settings.iter().map(|(key, value)| (key.as_str(), value))
+    }
+
+    /// Whether `key`, or something inside it, is locked
+    pub fn is_locked(&self, key: &str) -> bool {
+        self.settings.keys().any(|locked| within(key, locked) || within(locked, key))
+    }
+
+    /// The fixed value of `key`, also when it lies inside a locked object
+    pub fn locked_value(&self, key: &str) -> Option<&Value> {
+        self.settings.iter()
+            .filter(|(locked, _)| within(key, locked))
+            .find_map(|(locked, value)| match &key[locked.len()..] {
+                "" => Some(value),
+                rest => value.pointer(&pointer(&rest[1..])),
+            })
+    }
+
+    /// `locked_value` as a typed setting
+    pub fn locked<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        self.locked_value(key).and_then(|value| serde_json::from_value(value.clone()).ok())
+    }
+
+    /// Refuses any change to `key` while it is locked
+    pub fn check(&self, key: &str) -> Result<()> {
+        if self.is_locked(key) {
+            return Err(self.refusal(key));
+        }
+        Ok(())
+    }
+
+    /// Refuses going from `old` to `new` if that changes a locked setting
+    pub fn check_change(&self, old: &VpnConfig, new: &VpnConfig) -> Result<()> {
+        let diff = ConfigDiff::between(old, new);
+        match diff.keys().iter().find(|key| self.is_locked(key)) {
+            Some(key) => Err(self.refusal(key)),
+            None => Ok(()),
+        }
+    }
+
+    /// `config` with every locked setting at its fixed value
+    pub fn enforce(&self, config: &VpnConfig) -> Result<VpnConfig> {
+        let mut value = serde_json::to_value(config)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to serialize config: {}", e)))?;
+        for (key, locked) in &self.settings {
+            if let Some(slot) = value.pointer_mut(&pointer(key)) {
+                *slot = locked.clone();
+            }
+        }
+        serde_json::from_value(value)
+            .map_err(|e| VpnError::ConfigError(format!("Policy value does not fit the config: {}", e)))
+    }
+
+    fn refusal(&self, key: &str) -> VpnError {
+        let mut message = format!("{} is locked by your organization's policy", key);
+        if let Some(contact) = &self.message {
+            message.push_str(&format!(". {}", contact));
+        }
+        VpnError::PolicyViolation(message)
+    }
+}
+
+// "kill_switch.mode" is within "kill_switch" and within itself
+fn within(key: &str, parent: &str) -> bool {
+    key == parent || key.strip_prefix(parent).is_some_and(|rest| rest.starts_with('.'))
+}
+
+// Anyone who can edit the policy can lift it
+#[cfg(unix)]
+fn warn_if_writable(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o022 != 0 {
+            log::warn!("Policy {} is writable by other users", path.display());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_writable(_path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::killswitch::KillSwitchMode;
+    use crate::protocol::VpnProtocol;
+    use serde_json::json;
+
+    fn policy() -> ManagedPolicy {
+        ManagedPolicy::default()
+            .with_locked("kill_switch.mode", json!("Always"))
+            .with_locked("security_policy", json!({ "min_security_level": 8, "banned_protocols": ["PPTP", "L2TP"] }))
+            .with_message("Ask IT")
+    }
+
+    #[test]
+    fn test_locks_and_enforces() {
+        let policy = policy();
+        assert!(policy.is_locked("kill_switch") && policy.is_locked("kill_switch.mode"));
+        assert!(!policy.is_locked("kill_switch.allow_lan") && !policy.is_locked("kill"));
+        assert!(policy.is_locked("security_policy.banned_protocols"));
+        assert_eq!(policy.locked_value("kill_switch.mode"), Some(&json!("Always")));
+        assert_eq!(policy.locked_value("security_policy.min_security_level"), Some(&json!(8)));
+        assert_eq!(policy.locked_value("kill_switch.allow_lan"), None);
+
+        let config = policy.enforce(&VpnConfig::default()).unwrap();
+        assert_eq!(config.kill_switch.mode, KillSwitchMode::Always);
+        assert!(!config.security_policy.permits(VpnProtocol::PPTP));
+
+        let mut changed = config.clone();
+        changed.kill_switch.allow_lan = false;
+        assert!(policy.check_change(&config, &changed).is_ok());
+        changed.kill_switch.mode = KillSwitchMode::Disabled;
+        let error = policy.check_change(&config, &changed).unwrap_err().to_string();
+        assert!(error.contains("kill_switch.mode") && error.contains("Ask IT"), "{}", error);
+        assert!(policy.check("security_policy").is_err());
+        assert!(policy.check("block_ads").is_ok());
+    }
+
+    #[test]
+    fn test_load_rejects_bad_policies() {
+        let path = std::env::temp_dir().join(format!("vpn-policy-{}.json", rand::random::<u32>()));
+        assert!(ManagedPolicy::load(&path).unwrap().is_none());
+
+        for bad in [
+            r#"{ "settings": { "kill_switch.moed": "Always" } }"#,
+            r#"{ "settings": { "kill_switch.mode": "Sometimes" } }"#,
+            r#"{ "settings": { "mtu": 100 } }"#,
+        ] {
+            std::fs::write(&path, bad).unwrap();
+            assert!(ManagedPolicy::load(&path).is_err(), "{}", bad);
+        }
+
+        // Fields left out of a locked object are locked to their defaults
+        std::fs::write(&path, serde_json::to_string(&policy()).unwrap()).unwrap();
+        let loaded = ManagedPolicy::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.locked::<KillSwitchMode>("kill_switch.mode"), Some(KillSwitchMode::Always));
+        assert_eq!(loaded.locked_value("security_policy.allowed_protocols"), Some(&json!([])));
+    }
+}
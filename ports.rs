@@ -0,0 +1,219 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use crate::protocol::VpnProtocol;
+use crate::{Result, VpnError};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Source ports are drawn from the IANA dynamic range
+const EPHEMERAL_START: u16 = 49152;
+const EPHEMERAL_COUNT: u32 = 16384;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,  // Inclusive
+}
+
+impl PortRange {
+    pub fn new(start: u16, end: u16) -> Self {
+        Self { start: start.min(end), end: start.max(end) }
+    }
+
+    pub fn single(port: u16) -> Self {
+        Self { start: port, end: port }
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+
+    pub fn count(&self) -> u32 {
+        (self.end - self.start) as u32 + 1
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PortHoppingConfig {
+    pub enabled: bool,
+    pub seed: String,       // Base64 secret shared with the server
+    pub interval_secs: u64,
+}
+
+impl Default for PortHoppingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            seed: String::new(),
+            interval_secs: 30,
+        }
+    }
+}
+
+impl PortHoppingConfig {
+    /// Enabled config with a fresh random seed
+    pub fn generate(interval_secs: u64) -> Self {
+        Self {
+            enabled: true,
+            seed: BASE64.encode(rand::random::<[u8; 32]>()),
+            interval_secs,
+        }
+    }
+
+    pub fn validate(&self, protocol: VpnProtocol, use_tcp: bool) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        // IKEv2 pins 500/4500 for NAT traversal and TCP flows cannot move
+        let udp = match protocol {
+            VpnProtocol::WireGuard => true,
+            VpnProtocol::OpenVPN => !use_tcp,
+            _ => false,
+        };
+        if !udp {
+            return Err(VpnError::ConfigError(format!(
+                "Port hopping needs a UDP transport, not {}{}",
+                protocol.name(),
+                if use_tcp { " over TCP" } else { "" }
+            )));
+        }
+
+        if self.interval_secs == 0 {
+            return Err(VpnError::ConfigError("Port hopping interval must be greater than 0".to_string()));
+        }
+
+        match BASE64.decode(&self.seed) {
+            Ok(seed) if seed.len() >= 16 => Ok(()),
+            _ => Err(VpnError::ConfigError(
+                "Port hopping seed must be at least 16 bytes of base64".to_string()
+            )),
+        }
+    }
+}
+
+/// Derives the (source, destination) port pair for each time slot from the
+/// shared seed. Client and server compute the same sequence independently,
+/// so no signalling is needed when the ports move.
+#[derive(Debug, Clone)]
+pub struct PortHopper {
+    seed: Vec<u8>,
+    interval_secs: u64,
+    ranges: Vec<PortRange>,  // Destination ports the server accepts
+}
+
+impl PortHopper {
+    pub fn new(config: &PortHoppingConfig, ranges: Vec<PortRange>) -> Result<Self> {
+        let seed = BASE64.decode(&config.seed)
+            .map_err(|e| VpnError::ConfigError(format!("Invalid port hopping seed: {}", e)))?;
+        if ranges.is_empty() {
+            return Err(VpnError::ConfigError("No destination ports to hop between".to_string()));
+        }
+
+        Ok(Self {
+            seed,
+            interval_secs: config.interval_secs.max(1),
+            ranges,
+        })
+    }
+
+    pub fn epoch(&self, at: DateTime<Utc>) -> u64 {
+        at.timestamp().max(0) as u64 / self.interval_secs
+    }
+
+    pub fn ports_at(&self, epoch: u64) -> (u16, u16) {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.seed).expect("HMAC takes any key length");
+        mac.update(b"port-hop");
+        mac.update(&epoch.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let src_index = u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]);
+        let dst_index = u32::from_be_bytes([digest[4], digest[5], digest[6], digest[7]]);
+        let src = EPHEMERAL_START + (src_index % EPHEMERAL_COUNT) as u16;
+        (src, self.nth_port(dst_index % self.port_count()))
+    }
+
+    pub fn current_ports(&self) -> (u16, u16) {
+        self.ports_at(self.epoch(Utc::now()))
+    }
+
+    /// Server side check, allowing one slot of clock skew either way
+    pub fn accepts(&self, dst_port: u16, at: DateTime<Utc>) -> bool {
+        let epoch = self.epoch(at);
+        (epoch.saturating_sub(1)..=epoch + 1).any(|e| self.ports_at(e).1 == dst_port)
+    }
+
+    fn port_count(&self) -> u32 {
+        self.ranges.iter().map(|r| r.count()).sum()
+    }
+
+    fn nth_port(&self, mut n: u32) -> u16 {
+        for range in &self.ranges {
+            if n < range.count() {
+                return range.start + n as u16;
+            }
+            n -= range.count();
+        }
+        self.ranges[0].start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn hopping() -> PortHoppingConfig {
+        PortHoppingConfig {
+            enabled: true,
+            seed: BASE64.encode([7u8; 32]),
+            interval_secs: 30,
+        }
+    }
+
+    #[test]
+    fn test_both_ends_agree_on_sequence() {
+        let ranges = vec![PortRange::single(51820), PortRange::new(2000, 2999)];
+        let client = PortHopper::new(&hopping(), ranges.clone()).unwrap();
+        let server = PortHopper::new(&hopping(), ranges.clone()).unwrap();
+
+        let mut destinations = std::collections::HashSet::new();
+        for epoch in 0..50 {
+            let (src, dst) = client.ports_at(epoch);
+            assert_eq!((src, dst), server.ports_at(epoch));
+            assert!(src >= EPHEMERAL_START);
+            assert!(ranges.iter().any(|r| r.contains(dst)));
+            destinations.insert(dst);
+        }
+        assert!(destinations.len() > 10);
+
+        let other = PortHopper::new(&PortHoppingConfig::generate(30), ranges).unwrap();
+        assert!((0..10).any(|e| other.ports_at(e) != client.ports_at(e)));
+    }
+
+    #[test]
+    fn test_accepts_with_clock_skew() {
+        let hopper = PortHopper::new(&hopping(), vec![PortRange::new(2000, 60000)]).unwrap();
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        let epoch = hopper.epoch(now);
+
+        assert!(hopper.accepts(hopper.ports_at(epoch).1, now));
+        assert!(hopper.accepts(hopper.ports_at(epoch - 1).1, now));
+        assert!(!hopper.accepts(hopper.ports_at(epoch + 5).1, now));
+    }
+
+    #[test]
+    fn test_hopping_validation() {
+        assert!(hopping().validate(VpnProtocol::WireGuard, false).is_ok());
+        assert!(hopping().validate(VpnProtocol::OpenVPN, true).is_err());
+        assert!(hopping().validate(VpnProtocol::IKEv2, false).is_err());
+
+        let mut short_seed = hopping();
+        short_seed.seed = BASE64.encode([1u8; 4]);
+        assert!(short_seed.validate(VpnProtocol::WireGuard, false).is_err());
+        assert!(PortHoppingConfig::default().validate(VpnProtocol::PPTP, true).is_ok());
+    }
+}
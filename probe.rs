@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use crate::connection::resolve;
+use crate::protocol::ProtocolConfig;
+use crate::{Result, VpnError};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ProbeMethod {
+    Udp,  // Round trip of a datagram the server's probe responder echoes
+    Tcp,  // Time to complete the TCP handshake
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeTarget {
+    pub server_id: String,
+    pub host: String,
+    pub port: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeResult {
+    pub server_id: String,
+    pub rtt: Option<Duration>,  // None = no answer within the timeout
+}
+
+/// Measures round-trip times to many servers at once, never running more
+/// than `concurrency` probes in parallel
+#[derive(Debug, Clone)]
+pub struct LatencyProber {
+    method: ProbeMethod,
+    concurrency: usize,
+    timeout: Duration,
+    attempts: u32,
+}
+
+impl Default for LatencyProber {
+    fn default() -> Self {
+        Self {
+            method: ProbeMethod::Udp,
+            concurrency: 16,
+            timeout: Duration::from_secs(2),
+            attempts: 3,
+        }
+    }
+}
+
+impl LatencyProber {
+    pub fn new(method: ProbeMethod) -> Self {
+        Self {
+            method,
+            ..Default::default()
+        }
+    }
+
+    /// Probes the way the tunnel travels, so the probe reaches something
+    /// listening on the server's tunnel port
+    pub fn for_protocol(config: &ProtocolConfig) -> Self {
+        Self::new(if config.use_tcp { ProbeMethod::Tcp } else { ProbeMethod::Udp })
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Results come back in completion order, one per target
+    pub async fn probe_all(&self, targets: Vec<ProbeTarget>) -> Vec<ProbeResult> {
+        let permits = Arc::new(Semaphore::new(self.concurrency));
+        let mut tasks = JoinSet::new();
+
+        for target in targets {
+            let permits = permits.clone();
+            let prober = self.clone();
+            tasks.spawn(async move {
+                let _permit = permits.acquire_owned().await.expect("semaphore is never closed");
+                let rtt = match prober.probe(&target.host, target.port).await {
+                    Ok(rtt) => Some(rtt),
+                    Err(e) => {
+                        log::debug!("Probe of {} failed: {}", target.server_id, e);
+                        None
+                    }
+                };
+                ProbeResult { server_id: target.server_id, rtt }
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            if let Ok(result) = result {
+                results.push(result);
+            }
+        }
+        results
+    }
+
+    /// Best of `attempts` round trips, so one lost packet or slow
+    /// handshake does not count against the server
+    pub async fn probe(&self, host: &str, port: u16) -> Result<Duration> {
+        let addr = resolve(host, port).await?;
+        let mut best: Option<Duration> = None;
+        let mut last_error = None;
+
+        for _ in 0..self.attempts {
+            let sample = match self.method {
+                ProbeMethod::Udp => self.probe_udp(addr).await,
+                ProbeMethod::Tcp => self.probe_tcp(addr).await,
+            };
+            match sample {
+                Ok(rtt) => best = Some(best.map_or(rtt, |b| b.min(rtt))),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        best.ok_or_else(|| last_error.unwrap_or_else(|| VpnError::NetworkError("No probe attempts".to_string())))
+    }
+
+    async fn probe_udp(&self, addr: std::net::SocketAddr) -> Result<Duration> {
+        let local: std::net::SocketAddr = if addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            (std::net::Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local).await
+            .map_err(|e| VpnError::NetworkError(e.to_string()))?;
+        socket.connect(addr).await
+            .map_err(|e| VpnError::NetworkError(e.to_string()))?;
+
+        let nonce: [u8; 8] = rand::random();
+        let started = Instant::now();
+        socket.send(&nonce).await
+            .map_err(|e| VpnError::NetworkError(e.to_string()))?;
+
+        let mut buf = [0u8; 64];
+        loop {
+            let n = tokio::time::timeout(self.timeout.saturating_sub(started.elapsed()), socket.recv(&mut buf))
+                .await
+                .map_err(|_| VpnError::NetworkError(format!("No reply from {}", addr)))?
+                .map_err(|e| VpnError::NetworkError(e.to_string()))?;
+            // Ignore stray datagrams, e.g. late replies to an earlier attempt
+            if buf[..n] == nonce {
+                return Ok(started.elapsed());
+            }
+        }
+    }
+
+    async fn probe_tcp(&self, addr: std::net::SocketAddr) -> Result<Duration> {
+        let started = Instant::now();
+        tokio::time::timeout(self.timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| VpnError::NetworkError(format!("Handshake with {} timed out", addr)))?
+            .map_err(|e| VpnError::NetworkError(e.to_string()))?;
+        Ok(started.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn udp_echo(delay: Duration) -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+                tokio::time::sleep(delay).await;
+                let _ = socket.send_to(&buf[..n], peer).await;
+            }
+        });
+        port
+    }
+
+    fn target(id: &str, port: u16) -> ProbeTarget {
+        ProbeTarget { server_id: id.to_string(), host: "127.0.0.1".to_string(), port }
+    }
+
+    #[tokio::test]
+    async fn test_udp_probe_ranks_by_rtt() {
+        let fast = udp_echo(Duration::from_millis(5)).await;
+        let slow = udp_echo(Duration::from_millis(80)).await;
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let prober = LatencyProber::new(ProbeMethod::Udp)
+            .with_timeout(Duration::from_millis(300))
+            .with_attempts(2);
+        let results = prober.probe_all(vec![
+            target("fast", fast),
+            target("slow", slow),
+            target("silent", silent.local_addr().unwrap().port()),
+        ]).await;
+
+        let rtt = |id: &str| results.iter().find(|r| r.server_id == id).unwrap().rtt;
+        assert!(rtt("fast").unwrap() < rtt("slow").unwrap());
+        assert!(rtt("slow").unwrap() >= Duration::from_millis(80));
+        assert!(rtt("silent").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+
+        let prober = LatencyProber::new(ProbeMethod::Tcp).with_attempts(2);
+        assert!(prober.probe("127.0.0.1", port).await.unwrap() < Duration::from_secs(1));
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_is_bounded() {
+        let mut targets = Vec::new();
+        for i in 0..6 {
+            targets.push(target(&format!("s{}", i), udp_echo(Duration::from_millis(50)).await));
+        }
+
+        // Six 50ms probes, two at a time, take at least three rounds
+        let prober = LatencyProber::new(ProbeMethod::Udp).with_concurrency(2).with_attempts(1);
+        let started = Instant::now();
+        let results = prober.probe_all(targets).await;
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert!(results.iter().all(|r| r.rtt.is_some()));
+    }
+}
@@ -0,0 +1,328 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use crate::config::{VpnConfig, CONFIG_BACKUPS};
+use crate::migration;
+use crate::scoring::ScoringProfile;
+use crate::server::{Country, ServerManager, VpnServer};
+use crate::storage::{load_newest_backup, write_with_backups};
+use crate::{Result, VpnError};
+
+/// Which server a profile connects to
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub enum ServerSelection {
+    #[default]
+    Fastest,           // Best anywhere under the profile's scoring
+    Country(Country),  // Best in one country
+    Server(String),    // Always this server id
+}
+
+impl ServerSelection {
+    pub fn resolve<'a>(&self, servers: &'a ServerManager, scoring: ScoringProfile) -> Option<&'a VpnServer> {
+        match self {
+            ServerSelection::Fastest => servers.get_fastest_server(scoring),
+            ServerSelection::Country(country) => servers.get_fastest_in_country(country, scoring),
+            ServerSelection::Server(id) => servers.get_all_servers().into_iter().find(|s| &s.id == id),
+        }
+    }
+}
+
+impl fmt::Display for ServerSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerSelection::Fastest => f.write_str("Fastest server"),
+            ServerSelection::Country(country) => write!(f, "Fastest in {}", country.name()),
+            ServerSelection::Server(id) => write!(f, "Server {}", id),
+        }
+    }
+}
+
+/// Starting points for new profiles
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ProfileTemplate {
+    Default,
+    MaximumSecurity,
+    Streaming,
+    Torrenting,
+}
+
+impl ProfileTemplate {
+    pub fn all() -> Vec<ProfileTemplate> {
+        vec![
+            ProfileTemplate::Default,
+            ProfileTemplate::MaximumSecurity,
+            ProfileTemplate::Streaming,
+            ProfileTemplate::Torrenting,
+        ]
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ProfileTemplate::Default => "Default",
+            ProfileTemplate::MaximumSecurity => "Maximum Security",
+            ProfileTemplate::Streaming => "Streaming Optimized",
+            ProfileTemplate::Torrenting => "Torrenting",
+        }
+    }
+
+    pub fn config(&self) -> VpnConfig {
+        match self {
+            ProfileTemplate::Default => VpnConfig::default(),
+            ProfileTemplate::MaximumSecurity => VpnConfig::preset_maximum_security(),
+            ProfileTemplate::Streaming => VpnConfig::preset_streaming(),
+            ProfileTemplate::Torrenting => VpnConfig::preset_torrenting(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub config: VpnConfig,
+    #[serde(default)]
+    pub server: ServerSelection,
+}
+
+/// Named setups such as work, home and travel, stored in profiles.json
+/// next to the config file. Names are unique, ignoring case.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileStore {
+    #[serde(default)]
+    profiles: Vec<Profile>,
+    #[serde(default)]
+    active: Option<String>,  // Name of the profile in use
+}
+
+impl ProfileStore {
+    /// A store holding one "Default" profile with `config`, active
+    pub fn with_config(config: VpnConfig) -> Self {
+        Self {
+            profiles: vec![Profile { name: "Default".to_string(), config, server: ServerSelection::Fastest }],
+            active: Some("Default".to_string()),
+        }
+    }
+
+    /// `None` when no profiles have been saved yet. Each profile's config is
+    /// upgraded like a config file would be. A file that cannot be parsed
+    /// falls back to its newest usable backup; one from a newer version is
+    /// reported as it is.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(VpnError::ConfigError(format!("Failed to read profiles: {}", e))),
+        };
+        match Self::parse(&contents) {
+            Ok(value) => Self::from_value(value).map(Some),
+            Err(e) => load_newest_backup(path, CONFIG_BACKUPS, &e, |_, contents| Self::parse(contents).and_then(Self::from_value))
+                .map(Some)
+                .ok_or(e),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<serde_json::Value> {
+        serde_json::from_str(contents)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to parse profiles: {}", e)))
+    }
+
+    // Upgrades each profile's config, then reads the store
+    fn from_value(mut value: serde_json::Value) -> Result<Self> {
+        if let Some(profiles) = value.get_mut("profiles").and_then(|p| p.as_array_mut()) {
+            for config in profiles.iter_mut().filter_map(|p| p.get_mut("config")) {
+                migration::migrate(config)?;
+            }
+        }
+
+        let mut store: Self = serde_json::from_value(value)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to parse profiles: {}", e)))?;
+        if store.active.as_deref().is_some_and(|name| store.get(name).is_none()) {
+            store.active = store.profiles.first().map(|p| p.name.clone());
+        }
+        Ok(store)
+    }
+
+    /// Saved atomically, keeping the last `CONFIG_BACKUPS` versions
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to serialize profiles: {}", e)))?;
+        write_with_backups(path, json.as_bytes(), CONFIG_BACKUPS)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to write profiles: {}", e)))
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Profile> {
+        self.profiles.iter_mut().find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn active(&self) -> Option<&Profile> {
+        self.active.as_deref().and_then(|name| self.get(name))
+    }
+
+    pub fn active_mut(&mut self) -> Option<&mut Profile> {
+        let name = self.active.clone()?;
+        self.get_mut(&name)
+    }
+
+    /// New profile from a template; does not switch to it
+    pub fn create(&mut self, name: &str, template: ProfileTemplate) -> Result<&Profile> {
+        let name = self.check_new_name(name)?;
+        self.profiles.push(Profile { name, config: template.config(), server: ServerSelection::Fastest });
+        Ok(self.profiles.last().unwrap())
+    }
+
+    /// Copy of an existing profile, settings and server choice included
+    pub fn clone_profile(&mut self, from: &str, name: &str) -> Result<&Profile> {
+        let source = self.get(from).cloned().ok_or_else(|| not_found(from))?;
+        let name = self.check_new_name(name)?;
+        self.profiles.push(Profile { name, ..source });
+        Ok(self.profiles.last().unwrap())
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let to = to.trim();
+        // Changing only the case of a name is fine
+        if !from.eq_ignore_ascii_case(to) {
+            self.check_new_name(to)?;
+        }
+        let was_active = self.active().is_some_and(|p| p.name.eq_ignore_ascii_case(from));
+        let profile = self.get_mut(from).ok_or_else(|| not_found(from))?;
+        profile.name = to.to_string();
+        if was_active {
+            self.active = Some(to.to_string());
+        }
+        Ok(())
+    }
+
+    /// Removes a profile. The last one cannot go; deleting the active one
+    /// switches to the first remaining.
+    pub fn delete(&mut self, name: &str) -> Result<Profile> {
+        let idx = self.profiles.iter()
+            .position(|p| p.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| not_found(name))?;
+        if self.profiles.len() == 1 {
+            return Err(VpnError::ConfigError("Cannot delete the only profile".to_string()));
+        }
+
+        let removed = self.profiles.remove(idx);
+        if self.active.as_deref().is_some_and(|a| a.eq_ignore_ascii_case(&removed.name)) {
+            self.active = self.profiles.first().map(|p| p.name.clone());
+        }
+        Ok(removed)
+    }
+
+    /// Makes `name` the active profile. Its config must pass validation.
+    pub fn switch(&mut self, name: &str) -> Result<&Profile> {
+        let profile = self.get(name).ok_or_else(|| not_found(name))?;
+        profile.config.validate()
+            .map_err(|e| VpnError::ConfigError(format!("Profile {} is invalid: {}", profile.name, e)))?;
+        self.active = Some(profile.name.clone());
+        Ok(self.active().unwrap())
+    }
+
+    fn check_new_name(&self, name: &str) -> Result<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(VpnError::ConfigError("Profile name cannot be empty".to_string()));
+        }
+        if self.get(name).is_some() {
+            return Err(VpnError::ConfigError(format!("A profile named {} already exists", name)));
+        }
+        Ok(name.to_string())
+    }
+}
+
+fn not_found(name: &str) -> VpnError {
+    VpnError::ConfigError(format!("No profile named {}", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::killswitch::KillSwitchMode;
+
+    #[test]
+    fn test_profile_lifecycle() {
+        let mut store = ProfileStore::with_config(VpnConfig::default());
+        store.create("Travel", ProfileTemplate::MaximumSecurity).unwrap();
+        assert!(store.create("travel", ProfileTemplate::Default).is_err());
+        assert!(store.create("  ", ProfileTemplate::Default).is_err());
+
+        // Customizing a profile leaves the template alone
+        store.get_mut("Travel").unwrap().config.block_ads = false;
+        assert!(VpnConfig::preset_maximum_security().block_ads);
+
+        let copy = store.clone_profile("Travel", "Work").unwrap();
+        assert!(!copy.config.block_ads);
+        assert_eq!(copy.config.kill_switch.mode, KillSwitchMode::Always);
+
+        assert_eq!(store.switch("work").unwrap().name, "Work");
+        store.rename("Work", "Office").unwrap();
+        assert_eq!(store.active().unwrap().name, "Office");
+        assert!(store.rename("Office", "travel").is_err());
+        store.rename("Office", "OFFICE").unwrap();
+
+        store.delete("OFFICE").unwrap();
+        assert_eq!(store.active().unwrap().name, "Default");
+        store.delete("Travel").unwrap();
+        assert!(store.delete("Default").is_err());
+        assert!(store.switch("Travel").is_err());
+    }
+
+    #[test]
+    fn test_switch_refuses_invalid_config() {
+        let mut store = ProfileStore::with_config(VpnConfig::default());
+        store.create("Broken", ProfileTemplate::Default).unwrap();
+        store.get_mut("Broken").unwrap().config.mtu = 100;
+        assert!(store.switch("Broken").is_err());
+        assert_eq!(store.active().unwrap().name, "Default");
+    }
+
+    #[test]
+    fn test_selection_and_persistence() {
+        let servers = ServerManager::new();
+        let japan = Country::from_alpha2("JP").unwrap();
+        let mut store = ProfileStore::with_config(VpnConfig::default());
+        store.create("Tokyo", ProfileTemplate::Streaming).unwrap();
+        store.get_mut("Tokyo").unwrap().server = ServerSelection::Country(japan);
+        store.create("Pinned", ProfileTemplate::Default).unwrap();
+        store.get_mut("Pinned").unwrap().server = ServerSelection::Server("CH-1".to_string());
+        store.switch("Tokyo").unwrap();
+
+        let tokyo = store.get("Tokyo").unwrap();
+        let server = tokyo.server.resolve(&servers, tokyo.config.scoring).unwrap();
+        assert_eq!(server.location.country, japan);
+        assert!(server.supports_streaming);
+        let pinned = store.get("Pinned").unwrap();
+        assert_eq!(pinned.server.resolve(&servers, pinned.config.scoring).unwrap().id, "CH-1");
+
+        let path = std::env::temp_dir().join(format!("vpn-profiles-{}.json", rand::random::<u32>()));
+        assert!(ProfileStore::load(&path).unwrap().is_none());
+        store.save(&path).unwrap();
+        let loaded = ProfileStore::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.profiles().len(), 3);
+        assert_eq!(loaded.active().unwrap().name, "Tokyo");
+        assert_eq!(loaded.get("Tokyo").unwrap().server, ServerSelection::Country(japan));
+        assert_eq!(loaded.get("Tokyo").unwrap().config.scoring, ScoringProfile::Streaming);
+
+        // A newer version is reported even with a backup to fall back to
+        store.save(&path).unwrap();
+        let mut newer = store.clone();
+        newer.get_mut("Tokyo").unwrap().config.schema_version = 999;
+        newer.save(&path).unwrap();
+        assert!(ProfileStore::load(&path).is_err());
+        std::fs::write(&path, "{").unwrap();
+        assert_eq!(ProfileStore::load(&path).unwrap().unwrap().profiles().len(), 3);
+        for file in crate::storage::backup_paths(&path, CONFIG_BACKUPS).into_iter().chain([path]) {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
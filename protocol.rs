@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
+use crate::ports::{PortHoppingConfig, PortRange};
+use crate::{Result, VpnError};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum VpnProtocol {
     OpenVPN,
     WireGuard,
@@ -40,6 +42,19 @@ impl VpnProtocol {
         }
     }
 
+    /// Ports a server listens on for this protocol unless it advertises
+    /// its own ranges
+    pub fn default_port_ranges(&self) -> Vec<PortRange> {
+        match self {
+            // 53 and 443 get through most captive and restrictive networks
+            VpnProtocol::OpenVPN => vec![PortRange::single(1194), PortRange::single(443), PortRange::new(1195, 1200)],
+            VpnProtocol::WireGuard => vec![PortRange::single(51820), PortRange::single(53), PortRange::new(2000, 2999)],
+            VpnProtocol::IKEv2 => vec![PortRange::single(500), PortRange::single(4500)],
+            VpnProtocol::L2TP => vec![PortRange::single(1701)],
+            VpnProtocol::PPTP => vec![PortRange::single(1723)],
+        }
+    }
+
     pub fn is_recommended(&self) -> bool {
         matches!(self, VpnProtocol::WireGuard | VpnProtocol::OpenVPN)
     }
@@ -84,10 +99,12 @@ impl Default for VpnProtocol {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProtocolConfig {
     pub protocol: VpnProtocol,
-    pub port: u16,
+    pub port: u16,  // Preferred port, see VpnServer::resolve_port
     pub use_tcp: bool,
     pub obfuscation: bool,
     pub mtu: u16,
+    #[serde(default)]
+    pub port_hopping: PortHoppingConfig,
 }
 
 impl Default for ProtocolConfig {
@@ -98,6 +115,7 @@ impl Default for ProtocolConfig {
             use_tcp: false,
             obfuscation: false,
             mtu: 1420,
+            port_hopping: PortHoppingConfig::default(),
         }
     }
 }
@@ -110,6 +128,7 @@ impl ProtocolConfig {
             use_tcp: false,
             obfuscation: false,
             mtu: 1420,
+            port_hopping: PortHoppingConfig::default(),
         }
     }
 
@@ -127,4 +146,77 @@ impl ProtocolConfig {
         self.mtu = mtu;
         self
     }
+
+    pub fn with_port_hopping(mut self, port_hopping: PortHoppingConfig) -> Self {
+        self.port_hopping = port_hopping;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SecurityPolicy {
+    pub min_security_level: u8,
+    pub allowed_protocols: Vec<VpnProtocol>,  // Opt-in exceptions below the minimum
+    pub banned_protocols: Vec<VpnProtocol>,   // Refused even if opted in
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        Self {
+            min_security_level: 7,
+            allowed_protocols: Vec::new(),
+            banned_protocols: Vec::new(),
+        }
+    }
+}
+
+impl SecurityPolicy {
+    pub fn enterprise() -> Self {
+        Self {
+            min_security_level: 8,
+            allowed_protocols: Vec::new(),
+            banned_protocols: vec![VpnProtocol::PPTP, VpnProtocol::L2TP],
+        }
+    }
+
+    pub fn permits(&self, protocol: VpnProtocol) -> bool {
+        self.check(protocol).is_ok()
+    }
+
+    pub fn check(&self, protocol: VpnProtocol) -> Result<()> {
+        if self.banned_protocols.contains(&protocol) {
+            return Err(VpnError::PolicyViolation(format!(
+                "{} is banned by the security policy",
+                protocol.name()
+            )));
+        }
+
+        if protocol.security_level() < self.min_security_level
+            && !self.allowed_protocols.contains(&protocol)
+        {
+            return Err(VpnError::PolicyViolation(format!(
+                "{} has security level {}/10, below the required minimum of {}/10; \
+                 add it to the allowed protocols to opt in",
+                protocol.name(),
+                protocol.security_level(),
+                self.min_security_level
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn allow(&mut self, protocol: VpnProtocol) {
+        if !self.allowed_protocols.contains(&protocol) {
+            self.allowed_protocols.push(protocol);
+        }
+    }
+
+    pub fn ban(&mut self, protocol: VpnProtocol) {
+        self.allowed_protocols.retain(|p| *p != protocol);
+        if !self.banned_protocols.contains(&protocol) {
+            self.banned_protocols.push(protocol);
+        }
+    }
 }
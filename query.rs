@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::sync::Arc;
+use crate::protocol::VpnProtocol;
+use crate::scoring::{ScoringProfile, ScoringStrategy};
+use crate::server::{Continent, Country, VpnServer};
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ServerSort {
+    #[default]
+    Score,      // Best first under the query's scoring strategy
+    Latency,    // Lowest first
+    Load,       // Lowest first
+    Bandwidth,  // Highest first
+    Distance,   // Nearest first, servers with no known distance last
+    Name,
+}
+
+impl ServerSort {
+    fn compare(&self, a: &VpnServer, b: &VpnServer, scoring: &dyn ScoringStrategy) -> Ordering {
+        match self {
+            ServerSort::Score => scoring.score(b).total_cmp(&scoring.score(a)),
+            ServerSort::Latency => a.latency.cmp(&b.latency),
+            ServerSort::Load => a.load.cmp(&b.load),
+            ServerSort::Bandwidth => b.bandwidth.cmp(&a.bandwidth),
+            ServerSort::Distance => match (a.distance_km, b.distance_km) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            ServerSort::Name => a.name.cmp(&b.name),
+        }
+    }
+}
+
+/// Composable server filter, e.g. the fastest P2P server in Europe under
+/// 50% load:
+///
+/// ```ignore
+/// let query = ServerQuery::new()
+///     .with_p2p(true)
+///     .with_continent(Continent::Europe)
+///     .with_max_load(50);
+/// let server = server_manager.find(&query);
+/// ```
+///
+/// Unset filters match everything. Only available servers match unless
+/// `with_unavailable(true)` is set.
+#[derive(Debug, Clone)]
+pub struct ServerQuery {
+    p2p: Option<bool>,
+    streaming: Option<bool>,
+    premium: Option<bool>,
+    ipv6: Option<bool>,
+    protocol: Option<VpnProtocol>,
+    max_load: Option<u8>,
+    max_latency: Option<u32>,
+    country: Option<Country>,
+    city: Option<String>,
+    continent: Option<Continent>,
+    include_unavailable: bool,
+    scoring: Arc<dyn ScoringStrategy>,
+    sort: ServerSort,
+    page: usize,
+    per_page: Option<usize>,  // None = everything on one page
+}
+
+impl Default for ServerQuery {
+    fn default() -> Self {
+        Self {
+            p2p: None,
+            streaming: None,
+            premium: None,
+            ipv6: None,
+            protocol: None,
+            max_load: None,
+            max_latency: None,
+            country: None,
+            city: None,
+            continent: None,
+            include_unavailable: false,
+            scoring: Arc::new(ScoringProfile::Balanced),
+            sort: ServerSort::Score,
+            page: 0,
+            per_page: None,
+        }
+    }
+}
+
+impl ServerQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_p2p(mut self, p2p: bool) -> Self {
+        self.p2p = Some(p2p);
+        self
+    }
+
+    pub fn with_streaming(mut self, streaming: bool) -> Self {
+        self.streaming = Some(streaming);
+        self
+    }
+
+    pub fn with_premium(mut self, premium: bool) -> Self {
+        self.premium = Some(premium);
+        self
+    }
+
+    pub fn with_ipv6(mut self, ipv6: bool) -> Self {
+        self.ipv6 = Some(ipv6);
+        self
+    }
+
+    pub fn with_protocol(mut self, protocol: VpnProtocol) -> Self {
+        self.protocol = Some(protocol);
+        self
+    }
+
+    pub fn with_max_load(mut self, load: u8) -> Self {
+        self.max_load = Some(load);
+        self
+    }
+
+    pub fn with_max_latency(mut self, latency: u32) -> Self {
+        self.max_latency = Some(latency);
+        self
+    }
+
+    pub fn with_country(mut self, country: Country) -> Self {
+        self.country = Some(country);
+        self
+    }
+
+    /// Case-insensitive match on the city name
+    pub fn with_city(mut self, city: &str) -> Self {
+        self.city = Some(city.to_string());
+        self
+    }
+
+    pub fn with_continent(mut self, continent: Continent) -> Self {
+        self.continent = Some(continent);
+        self
+    }
+
+    pub fn with_unavailable(mut self, include: bool) -> Self {
+        self.include_unavailable = include;
+        self
+    }
+
+    /// How `ServerSort::Score` ranks; the strategy can also exclude servers
+    pub fn with_scoring(mut self, strategy: impl ScoringStrategy + 'static) -> Self {
+        self.scoring = Arc::new(strategy);
+        self
+    }
+
+    pub fn with_sort(mut self, sort: ServerSort) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Zero-based page of `per_page` results
+    pub fn with_page(mut self, page: usize, per_page: usize) -> Self {
+        self.page = page;
+        self.per_page = Some(per_page.max(1));
+        self
+    }
+
+    /// Whether only P2P servers are asked for
+    pub fn wants_p2p(&self) -> bool {
+        self.p2p == Some(true)
+    }
+
+    pub fn scoring(&self) -> &dyn ScoringStrategy {
+        self.scoring.as_ref()
+    }
+
+    pub fn matches(&self, server: &VpnServer) -> bool {
+        (self.include_unavailable || server.is_available())
+            && self.scoring.accepts(server)
+            && self.p2p.is_none_or(|p2p| server.supports_p2p == p2p)
+            && self.streaming.is_none_or(|streaming| server.supports_streaming == streaming)
+            && self.premium.is_none_or(|premium| server.is_premium == premium)
+            && self.ipv6.is_none_or(|ipv6| server.supports_ipv6 == ipv6)
+            && self.protocol.is_none_or(|protocol| server.supports_protocol(protocol))
+            && self.max_load.is_none_or(|load| server.load <= load)
+            && self.max_latency.is_none_or(|latency| server.latency <= latency)
+            && self.country.as_ref().is_none_or(|country| &server.location.country == country)
+            && self.city.as_ref().is_none_or(|city| server.location.city.eq_ignore_ascii_case(city))
+            && self.continent.is_none_or(|continent| server.location.country.continent() == continent)
+    }
+
+    /// Result order. Servers on hold for repeated failures come after all
+    /// others; ties fall back to the id so pages are stable between calls.
+    pub fn compare(&self, a: &VpnServer, b: &VpnServer) -> Ordering {
+        a.is_backing_off().cmp(&b.is_backing_off())
+            .then_with(|| self.sort.compare(a, b, self.scoring.as_ref()))
+            .then_with(|| a.id.cmp(&b.id))
+    }
+
+    /// Filters and sorts `servers`, then cuts out the requested page
+    pub fn run<'a>(&self, servers: impl IntoIterator<Item = &'a VpnServer>) -> ServerPage<'a> {
+        let mut matched: Vec<&VpnServer> = servers.into_iter().filter(|s| self.matches(s)).collect();
+        matched.sort_by(|a, b| self.compare(a, b));
+
+        let total = matched.len();
+        let per_page = self.per_page.unwrap_or(total.max(1));
+        let servers = matched.into_iter().skip(self.page * per_page).take(per_page).collect();
+
+        ServerPage { servers, total, page: self.page, per_page }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerPage<'a> {
+    pub servers: Vec<&'a VpnServer>,
+    pub total: usize,     // Matches across all pages
+    pub page: usize,
+    pub per_page: usize,
+}
+
+impl ServerPage<'_> {
+    pub fn page_count(&self) -> usize {
+        self.total.div_ceil(self.per_page)
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.page + 1 < self.page_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SubscriptionTier;
+    use crate::entitlements::Entitlements;
+    use crate::server::ServerManager;
+
+    #[test]
+    fn test_composed_filters() {
+        let mut manager = ServerManager::new();
+        manager.set_entitlements(Entitlements::for_tier(SubscriptionTier::Premium));
+        let query = ServerQuery::new()
+            .with_p2p(true)
+            .with_continent(Continent::Europe)
+            .with_max_load(50);
+
+        let page = manager.query(&query);
+        assert!(page.total > 0);
+        for server in &page.servers {
+            assert!(server.supports_p2p && server.load <= 50);
+            assert_eq!(server.location.country.continent(), Continent::Europe);
+        }
+        assert_eq!(manager.find(&query).unwrap().id, page.servers[0].id);
+
+        let osaka = manager.query(&ServerQuery::new().with_city("osaka"));
+        assert_eq!(osaka.total, 1);
+        assert_eq!(osaka.servers[0].id, "JP-2");
+
+        assert!(manager.find(&ServerQuery::new().with_max_latency(1)).is_none());
+    }
+
+    #[test]
+    fn test_sorting_and_pagination() {
+        let manager = ServerManager::new();
+        let query = ServerQuery::new().with_sort(ServerSort::Latency).with_unavailable(true);
+        let all = manager.query(&query);
+        assert_eq!(all.total, manager.get_all_servers().len());
+        assert_eq!(all.page_count(), 1);
+        assert!(all.servers.windows(2).all(|w| w[0].latency <= w[1].latency));
+
+        let first = manager.query(&query.clone().with_page(0, 10));
+        let second = manager.query(&query.clone().with_page(1, 10));
+        assert_eq!(first.servers.len(), 10);
+        assert_eq!(first.page_count(), all.total.div_ceil(10));
+        assert!(first.has_next());
+        assert_eq!(second.servers[0].id, all.servers[10].id);
+
+        let last = manager.query(&query.with_page(first.page_count() - 1, 10));
+        assert!(!last.has_next());
+        assert!(!last.servers.is_empty());
+    }
+}
@@ -0,0 +1,357 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+use crate::balance::LoadBalancer;
+use crate::config::VpnConfig;
+use crate::dns::DnsManager;
+use crate::killswitch::KillSwitch;
+use crate::layers::{leaves, pointer, ConfigLoader};
+use crate::policy::ManagedPolicy;
+use crate::server::ServerManager;
+use crate::split_tunnel::SplitTunnel;
+use crate::{Result, VpnError};
+
+// Read when connecting; changing them on a live tunnel would leave it
+// half old, half new
+const RECONNECT_SETTINGS: &[&str] = &[
+    "protocol_config",
+    "security_policy",
+    "ipv6_leak_protection",
+    "mtu",
+    "multi_hop",
+];
+
+/// Settings that differ between two configs, as dotted keys
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    keys: Vec<String>,
+}
+
+impl ConfigDiff {
+    pub fn between(old: &VpnConfig, new: &VpnConfig) -> Self {
+        let (Ok(old), Ok(new)) = (serde_json::to_value(old), serde_json::to_value(new)) else {
+            return Self::default();
+        };
+        let candidates: BTreeSet<String> = leaves("", &old).into_iter().chain(leaves("", &new)).collect();
+        let keys = candidates.into_iter()
+            .filter(|key| old.pointer(&pointer(key)) != new.pointer(&pointer(key)))
+            .collect();
+        Self { keys }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /// Whether `key` or anything inside it changed
+    pub fn touches(&self, key: &str) -> bool {
+        self.keys.iter().any(|k| k == key || k.strip_prefix(key).is_some_and(|rest| rest.starts_with('.')))
+    }
+
+    /// Changed settings that take effect on the next connection
+    pub fn needs_reconnect(&self) -> Vec<&str> {
+        RECONNECT_SETTINGS.iter().copied().filter(|key| self.touches(key)).collect()
+    }
+
+    /// `target` with every changed setting taken from `source`. Settings
+    /// that did not change keep whatever `target` has.
+    pub fn patch(&self, target: &VpnConfig, source: &VpnConfig) -> Result<VpnConfig> {
+        let to_value = |config: &VpnConfig| serde_json::to_value(config)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to serialize config: {}", e)));
+        let (mut patched, source) = (to_value(target)?, to_value(source)?);
+
+        // Sorted keys put a parent before its children
+        for key in &self.keys {
+            let path = pointer(key);
+            if let Some(slot) = patched.pointer_mut(&path) {
+                match source.pointer(&path) {
+                    Some(value) => *slot = value.clone(),
+                    None => remove(&mut patched, key),
+                }
+            } else if let Some(value) = source.pointer(&path) {
+                let (parent, name) = key.rsplit_once('.').unwrap_or(("", key));
+                if let Some(Value::Object(fields)) = patched.pointer_mut(&pointer(parent)) {
+                    fields.insert(name.to_string(), value.clone());
+                }
+            }
+        }
+        serde_json::from_value(patched)
+            .map_err(|e| VpnError::ConfigError(format!("Failed to parse config: {}", e)))
+    }
+
+    pub fn summary(&self) -> String {
+        match self.keys.len() {
+            1 => format!("{} changed", self.keys[0]),
+            n => format!("{} settings changed", n),
+        }
+    }
+}
+
+fn remove(value: &mut Value, key: &str) {
+    let (parent, name) = key.rsplit_once('.').unwrap_or(("", key));
+    if let Some(Value::Object(fields)) = value.pointer_mut(&pointer(parent)) {
+        fields.remove(name);
+    }
+}
+
+/// The running pieces a config change can reach without reconnecting
+pub struct LiveComponents<'a> {
+    pub dns: &'a mut DnsManager,
+    pub kill_switch: &'a mut KillSwitch,
+    pub split_tunnel: &'a mut SplitTunnel,
+    pub servers: &'a mut ServerManager,
+}
+
+impl LiveComponents<'_> {
+    /// Pins the settings `policy` locks, and lifts locks it no longer has
+    pub fn lock(&mut self, policy: &ManagedPolicy) {
+        self.kill_switch.set_locked_mode(policy.locked("kill_switch.mode"));
+        self.dns.set_locked_mode(policy.locked("dns_mode"));
+    }
+
+    /// Pushes the settings in `diff` from `config` to the components that
+    /// use them. DNS, firewall and routing changes are all or nothing: if
+    /// one is refused, none is kept.
+    pub fn apply(&mut self, diff: &ConfigDiff, config: &VpnConfig) -> Result<()> {
+        let previous_dns = self.dns.get_mode().clone();
+        let previous_rules = self.kill_switch.config().clone();
+        if diff.touches("dns_mode") {
+            self.dns.set_mode(config.dns_mode.clone())?;
+        }
+        let mut applied = Ok(());
+        if diff.touches("kill_switch") {
+            applied = self.kill_switch.update_config(config.kill_switch.clone());
+        }
+        if applied.is_ok() && diff.touches("split_tunnel") {
+            applied = self.split_tunnel.update_config(config.split_tunnel.clone());
+        }
+        if let Err(e) = applied {
+            if diff.touches("kill_switch") {
+                self.kill_switch.update_config(previous_rules)?;
+            }
+            self.dns.set_mode(previous_dns)?;
+            return Err(e);
+        }
+
+        if diff.touches("block_ads") {
+            self.dns.enable_ad_blocking(config.block_ads);
+        }
+        if diff.touches("block_malware") {
+            self.dns.enable_malware_blocking(config.block_malware);
+        }
+        if ["dns_mode", "block_ads", "block_malware"].iter().any(|key| diff.touches(key)) {
+            self.dns.apply_dns_configuration()?;
+        }
+
+        if diff.touches("location") {
+            self.servers.set_origin(config.location);
+        }
+        if diff.touches("load_balancing") {
+            self.servers.set_balancer(LoadBalancer::from_config(&config.load_balancing));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ConfigEvent {
+    /// The files changed and still make a valid config. `diff` is against
+    /// the last valid load.
+    Reloaded { diff: ConfigDiff, config: Box<VpnConfig>, policy: ManagedPolicy },
+    /// The files changed but the result was refused; nothing was applied
+    Rejected(String),
+}
+
+/// Watches the files behind a `ConfigLoader` and reloads them on change.
+/// Edits are picked up once the files have been still for the debounce
+/// time, so an editor saving in several steps causes one reload.
+pub struct ConfigWatcher {
+    loader: ConfigLoader,
+    poll_interval: Duration,
+    debounce: Duration,
+}
+
+impl ConfigWatcher {
+    pub fn new(loader: ConfigLoader) -> Self {
+        Self {
+            loader,
+            poll_interval: Duration::from_secs(1),
+            debounce: Duration::from_millis(500),
+        }
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    pub fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Starts watching. Stops once the receiver is dropped.
+    pub fn spawn(self) -> (JoinHandle<()>, mpsc::UnboundedReceiver<ConfigEvent>) {
+        let (events, receiver) = mpsc::unbounded_channel();
+        let handle = tokio::spawn(async move {
+            let files = self.loader.files();
+            let mut last_good = self.loader.load().map(|l| l.into_config()).unwrap_or_default();
+            let mut seen = snapshot(&files);
+            let mut changed_at: Option<Instant> = None;
+
+            let mut ticker = tokio::time::interval(self.poll_interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let current = snapshot(&files);
+                if current != seen {
+                    seen = current;
+                    changed_at = Some(Instant::now());
+                    continue;
+                }
+                match changed_at {
+                    Some(at) if at.elapsed() >= self.debounce => changed_at = None,
+                    _ => continue,
+                }
+
+                let event = match self.loader.load() {
+                    Ok(layered) => {
+                        let policy = layered.policy().clone();
+                        let config = layered.into_config();
+                        let diff = ConfigDiff::between(&last_good, &config);
+                        if diff.is_empty() {
+                            continue;
+                        }
+                        last_good = config.clone();
+                        ConfigEvent::Reloaded { diff, config: Box::new(config), policy }
+                    }
+                    Err(e) => {
+                        log::warn!("Ignoring config change: {}", e);
+                        ConfigEvent::Rejected(e.to_string())
+                    }
+                };
+                if events.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        (handle, receiver)
+    }
+}
+
+// File contents, None for missing files
+fn snapshot(files: &[PathBuf]) -> Vec<Option<Vec<u8>>> {
+    files.iter().map(|path| std::fs::read(path).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::DnsMode;
+    use crate::killswitch::KillSwitchMode;
+    use crate::protocol::{ProtocolConfig, VpnProtocol};
+    use crate::split_tunnel::SplitTunnelMode;
+
+    #[test]
+    fn test_diff_and_patch() {
+        let old = VpnConfig::default();
+        let mut new = old.clone();
+        new.kill_switch.mode = KillSwitchMode::Always;
+        new.protocol_config = ProtocolConfig::new(VpnProtocol::OpenVPN);
+        new.location = Some(crate::geo::GeoPoint::new(47.37, 8.54).unwrap());
+
+        let diff = ConfigDiff::between(&old, &new);
+        assert!(diff.touches("kill_switch") && diff.touches("kill_switch.mode"));
+        assert!(!diff.touches("kill") && !diff.touches("split_tunnel"));
+        assert_eq!(diff.needs_reconnect(), vec!["protocol_config"]);
+        assert!(ConfigDiff::between(&new, &new).is_empty());
+
+        // Only the changed settings move over
+        let mut profile = VpnConfig::default();
+        profile.block_ads = true;
+        let patched = diff.patch(&profile, &new).unwrap();
+        assert!(patched.block_ads);
+        assert_eq!(patched.kill_switch.mode, KillSwitchMode::Always);
+        assert_eq!(patched.location, new.location);
+        assert!(ConfigDiff::between(&new, &old).patch(&patched, &old).unwrap().location.is_none());
+    }
+
+    #[test]
+    fn test_apply_to_live_components() {
+        let config = VpnConfig::default();
+        let mut dns = DnsManager::new();
+        let mut kill_switch = KillSwitch::new(config.kill_switch.clone());
+        let mut split_tunnel = SplitTunnel::new(config.split_tunnel.clone());
+        let mut servers = ServerManager::new();
+        kill_switch.enable().unwrap();
+
+        let mut new = config.clone();
+        new.kill_switch.allow_lan = false;
+        new.dns_mode = DnsMode::System;
+        new.split_tunnel.mode = SplitTunnelMode::ExcludeOnly;
+        new.split_tunnel.ip_ranges.insert("10.0.0.0/8".to_string());
+        let diff = ConfigDiff::between(&config, &new);
+
+        let mut live = LiveComponents {
+            dns: &mut dns,
+            kill_switch: &mut kill_switch,
+            split_tunnel: &mut split_tunnel,
+            servers: &mut servers,
+        };
+        live.apply(&diff, &new).unwrap();
+        assert!(!live.kill_switch.config().allow_lan);
+        assert!(matches!(live.dns.get_mode(), DnsMode::System));
+
+        // A refused route undoes the firewall change with it
+        live.split_tunnel.enable().unwrap();
+        let mut broken = new.clone();
+        broken.kill_switch.allow_lan = true;
+        broken.split_tunnel.ip_ranges.insert("10.0.0.300/8".to_string());
+        assert!(live.apply(&ConfigDiff::between(&new, &broken), &broken).is_err());
+        assert!(!live.kill_switch.config().allow_lan);
+        assert!(!live.split_tunnel.config().ip_ranges.contains("10.0.0.300/8"));
+    }
+
+    async fn next(events: &mut mpsc::UnboundedReceiver<ConfigEvent>) -> ConfigEvent {
+        tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_watcher_reloads_valid_edits_only() {
+        let path = std::env::temp_dir().join(format!("vpn-reload-{}.json", rand::random::<u32>()));
+        std::fs::write(&path, r#"{ "mtu": 1400 }"#).unwrap();
+        let loader = ConfigLoader::new().with_user_file(path.clone());
+        let (_handle, mut events) = ConfigWatcher::new(loader)
+            .with_poll_interval(Duration::from_millis(10))
+            .with_debounce(Duration::from_millis(50))
+            .spawn();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        std::fs::write(&path, r#"{ "mtu": 1380, "block_ads": true }"#).unwrap();
+        match next(&mut events).await {
+            ConfigEvent::Reloaded { diff, config, .. } => {
+                assert_eq!(diff.keys(), ["block_ads", "mtu"]);
+                assert_eq!(config.mtu, 1380);
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+
+        std::fs::write(&path, r#"{ "mtu": 100 }"#).unwrap();
+        assert!(matches!(next(&mut events).await, ConfigEvent::Rejected(e) if e.contains("mtu")));
+
+        // Compared with the last good load, not the rejected one
+        std::fs::write(&path, r#"{ "mtu": 1380 }"#).unwrap();
+        match next(&mut events).await {
+            ConfigEvent::Reloaded { diff, .. } => assert_eq!(diff.keys(), ["block_ads"]),
+            other => panic!("unexpected event {:?}", other),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::server::VpnServer;
+use crate::{Result, VpnError};
+
+/// Decides what "best server" means. Higher scores win.
+pub trait ScoringStrategy: fmt::Debug + Send + Sync {
+    fn score(&self, server: &VpnServer) -> f64;
+
+    /// Servers the strategy never picks, whatever their score
+    fn accepts(&self, _server: &VpnServer) -> bool {
+        true
+    }
+}
+
+/// Relative importance of each metric. Weights need not sum to 1; the
+/// score is normalized to 0-100 either way.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ScoringWeights {
+    pub load: f64,
+    pub latency: f64,
+    pub bandwidth: f64,
+    #[serde(default)]
+    pub reliability: f64,  // Share of past connections that succeeded
+}
+
+impl Default for ScoringWeights {
+    fn default() -> Self {
+        Self { load: 0.35, latency: 0.35, bandwidth: 0.2, reliability: 0.1 }
+    }
+}
+
+impl ScoringWeights {
+    pub fn validate(&self) -> Result<()> {
+        let weights = [self.load, self.latency, self.bandwidth, self.reliability];
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) {
+            return Err(VpnError::ConfigError("Scoring weights cannot be negative".to_string()));
+        }
+        if weights.iter().sum::<f64>() <= 0.0 {
+            return Err(VpnError::ConfigError("At least one scoring weight must be positive".to_string()));
+        }
+        Ok(())
+    }
+
+    /// 0-100 from raw metrics; reliability is 0.0-1.0
+    pub fn combine(&self, load: u8, latency: u32, bandwidth: u64, reliability: f64) -> f64 {
+        let total = self.load + self.latency + self.bandwidth + self.reliability;
+        if total <= 0.0 {
+            return 0.0;
+        }
+
+        let load_score = (100.0 - load.min(100) as f64) / 100.0;
+        let latency_score = 1.0 / (1.0 + (latency as f64 / 100.0));
+        let bandwidth_score = (bandwidth as f64 / 1000.0).min(1.0);
+
+        (load_score * self.load
+            + latency_score * self.latency
+            + bandwidth_score * self.bandwidth
+            + reliability * self.reliability)
+            / total * 100.0
+    }
+}
+
+impl ScoringStrategy for ScoringWeights {
+    /// Metrics plus up to half a point for being close to the user. The
+    /// distance bonus is too small to outweigh any real difference, but
+    /// decides between servers that are otherwise equal.
+    fn score(&self, server: &VpnServer) -> f64 {
+        // No history yet counts as reliable, so new servers are not shunned
+        let reliability = server.reliability.unwrap_or(1.0);
+        let proximity = server.distance_km.map_or(0.0, |km| 0.5 / (1.0 + km / 1000.0));
+        self.combine(server.load, server.latency, server.bandwidth, reliability) + proximity
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum ScoringProfile {
+    #[default]
+    Balanced,
+    LowestLatency,
+    MaxThroughput,
+    Streaming,  // Streaming-capable servers only, bandwidth first
+    P2P,        // P2P-capable servers only, bandwidth and reliability first
+    Custom(ScoringWeights),
+}
+
+impl ScoringProfile {
+    pub fn all() -> Vec<ScoringProfile> {
+        vec![
+            ScoringProfile::Balanced,
+            ScoringProfile::LowestLatency,
+            ScoringProfile::MaxThroughput,
+            ScoringProfile::Streaming,
+            ScoringProfile::P2P,
+        ]
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            ScoringProfile::Balanced => "Balanced",
+            ScoringProfile::LowestLatency => "Lowest Latency",
+            ScoringProfile::MaxThroughput => "Max Throughput",
+            ScoringProfile::Streaming => "Streaming",
+            ScoringProfile::P2P => "P2P",
+            ScoringProfile::Custom(_) => "Custom",
+        }
+    }
+
+    pub fn weights(&self) -> ScoringWeights {
+        let (load, latency, bandwidth, reliability) = match self {
+            ScoringProfile::Balanced => return ScoringWeights::default(),
+            ScoringProfile::LowestLatency => (0.15, 0.7, 0.05, 0.1),
+            ScoringProfile::MaxThroughput => (0.3, 0.1, 0.5, 0.1),
+            ScoringProfile::Streaming => (0.25, 0.25, 0.35, 0.15),
+            ScoringProfile::P2P => (0.35, 0.1, 0.4, 0.15),
+            ScoringProfile::Custom(weights) => return *weights,
+        };
+        ScoringWeights { load, latency, bandwidth, reliability }
+    }
+}
+
+impl ScoringStrategy for ScoringProfile {
+    fn score(&self, server: &VpnServer) -> f64 {
+        self.weights().score(server)
+    }
+
+    fn accepts(&self, server: &VpnServer) -> bool {
+        match self {
+            ScoringProfile::Streaming => server.supports_streaming,
+            ScoringProfile::P2P => server.supports_p2p,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ServerManager;
+
+    #[test]
+    fn test_profiles_pick_different_servers() {
+        let manager = ServerManager::new();
+        let mut server = manager.get_all_servers()[0].clone();
+        server.load = 10;
+        server.bandwidth = 1000;
+
+        let mut fast = server.clone();
+        fast.latency = 5;
+        fast.bandwidth = 100;
+        let mut wide = server.clone();
+        wide.latency = 150;
+
+        let latency = ScoringProfile::LowestLatency;
+        let throughput = ScoringProfile::MaxThroughput;
+        assert!(latency.score(&fast) > latency.score(&wide));
+        assert!(throughput.score(&wide) > throughput.score(&fast));
+
+        wide.supports_p2p = false;
+        assert!(!ScoringProfile::P2P.accepts(&wide));
+        assert!(ScoringProfile::Balanced.accepts(&wide));
+    }
+
+    #[test]
+    fn test_reliability_and_custom_weights() {
+        let manager = ServerManager::new();
+        let mut server = manager.get_all_servers()[0].clone();
+        let balanced = ScoringProfile::Balanced;
+        let unknown = balanced.score(&server);
+
+        server.reliability = Some(0.5);
+        assert!(balanced.score(&server) < unknown);
+
+        let only_reliability = ScoringWeights { load: 0.0, latency: 0.0, bandwidth: 0.0, reliability: 1.0 };
+        assert!((ScoringProfile::Custom(only_reliability).score(&server) - 50.0).abs() < 1e-9);
+
+        assert!(only_reliability.validate().is_ok());
+        assert!(ScoringWeights { load: -1.0, ..only_reliability }.validate().is_err());
+        assert!(ScoringWeights { reliability: 0.0, ..only_reliability }.validate().is_err());
+    }
+}
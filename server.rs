@@ -1,158 +1,26 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+pub use crate::country::{Continent, Country};
+use crate::balance::LoadBalancer;
+use crate::entitlements::Entitlements;
+use crate::favorites::{Favorite, SavedServers};
+use crate::geo::GeoPoint;
+use crate::health::{HealthEventKind, ServerHealth};
+use crate::manifest::{ManifestLoader, ServerManifest};
+use crate::multihop::ServerChain;
+use crate::probe::{ProbeResult, ProbeTarget};
+use crate::query::{ServerPage, ServerQuery};
+use crate::scoring::{ScoringProfile, ScoringStrategy};
+use crate::ports::PortRange;
+use crate::protocol::{ProtocolConfig, VpnProtocol};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
-pub enum Country {
-    UnitedStates,
-    UnitedKingdom,
-    Canada,
-    Germany,
-    France,
-    Netherlands,
-    Switzerland,
-    Sweden,
-    Japan,
-    Singapore,
-    Australia,
-    Brazil,
-    India,
-    SouthKorea,
-    Spain,
-    Italy,
-    Norway,
-    Denmark,
-    Finland,
-    Poland,
-    Turkey,
-    UnitedArabEmirates,
-    SouthAfrica,
-    Mexico,
-    Argentina,
-}
-
-impl Country {
-    pub fn code(&self) -> &str {
-        match self {
-            Country::UnitedStates => "US",
-            Country::UnitedKingdom => "GB",
-            Country::Canada => "CA",
-            Country::Germany => "DE",
-            Country::France => "FR",
-            Country::Netherlands => "NL",
-            Country::Switzerland => "CH",
-            Country::Sweden => "SE",
-            Country::Japan => "JP",
-            Country::Singapore => "SG",
-            Country::Australia => "AU",
-            Country::Brazil => "BR",
-            Country::India => "IN",
-            Country::SouthKorea => "KR",
-            Country::Spain => "ES",
-            Country::Italy => "IT",
-            Country::Norway => "NO",
-            Country::Denmark => "DK",
-            Country::Finland => "FI",
-            Country::Poland => "PL",
-            Country::Turkey => "TR",
-            Country::UnitedArabEmirates => "AE",
-            Country::SouthAfrica => "ZA",
-            Country::Mexico => "MX",
-            Country::Argentina => "AR",
-        }
-    }
-
-    pub fn name(&self) -> &str {
-        match self {
-            Country::UnitedStates => "United States",
-            Country::UnitedKingdom => "United Kingdom",
-            Country::Canada => "Canada",
-            Country::Germany => "Germany",
-            Country::France => "France",
-            Country::Netherlands => "Netherlands",
-            Country::Switzerland => "Switzerland",
-            Country::Sweden => "Sweden",
-            Country::Japan => "Japan",
-            Country::Singapore => "Singapore",
-            Country::Australia => "Australia",
-            Country::Brazil => "Brazil",
-            Country::India => "India",
-            Country::SouthKorea => "South Korea",
-            Country::Spain => "Spain",
-            Country::Italy => "Italy",
-            Country::Norway => "Norway",
-            Country::Denmark => "Denmark",
-            Country::Finland => "Finland",
-            Country::Poland => "Poland",
-            Country::Turkey => "Turkey",
-            Country::UnitedArabEmirates => "United Arab Emirates",
-            Country::SouthAfrica => "South Africa",
-            Country::Mexico => "Mexico",
-            Country::Argentina => "Argentina",
-        }
-    }
-
-    pub fn flag_emoji(&self) -> &str {
-        match self {
-            Country::UnitedStates => "🇺🇸",
-            Country::UnitedKingdom => "🇬🇧",
-            Country::Canada => "🇨🇦",
-            Country::Germany => "🇩🇪",
-            Country::France => "🇫🇷",
-            Country::Netherlands => "🇳🇱",
-            Country::Switzerland => "🇨🇭",
-            Country::Sweden => "🇸🇪",
-            Country::Japan => "🇯🇵",
-            Country::Singapore => "🇸🇬",
-            Country::Australia => "🇦🇺",
-            Country::Brazil => "🇧🇷",
-            Country::India => "🇮🇳",
-            Country::SouthKorea => "🇰🇷",
-            Country::Spain => "🇪🇸",
-            Country::Italy => "🇮🇹",
-            Country::Norway => "🇳🇴",
-            Country::Denmark => "🇩🇰",
-            Country::Finland => "🇫🇮",
-            Country::Poland => "🇵🇱",
-            Country::Turkey => "🇹🇷",
-            Country::UnitedArabEmirates => "🇦🇪",
-            Country::SouthAfrica => "🇿🇦",
-            Country::Mexico => "🇲🇽",
-            Country::Argentina => "🇦🇷",
-        }
-    }
-
-    pub fn all() -> Vec<Country> {
-        vec![
-            Country::UnitedStates,
-            Country::UnitedKingdom,
-            Country::Canada,
-            Country::Germany,
-            Country::France,
-            Country::Netherlands,
-            Country::Switzerland,
-            Country::Sweden,
-            Country::Japan,
-            Country::Singapore,
-            Country::Australia,
-            Country::Brazil,
-            Country::India,
-            Country::SouthKorea,
-            Country::Spain,
-            Country::Italy,
-            Country::Norway,
-            Country::Denmark,
-            Country::Finland,
-            Country::Poland,
-            Country::Turkey,
-            Country::UnitedArabEmirates,
-            Country::SouthAfrica,
-            Country::Mexico,
-            Country::Argentina,
-        ]
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ServerLocation {
     pub city: String,
     pub country: Country,
@@ -160,20 +28,40 @@ pub struct ServerLocation {
     pub longitude: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl ServerLocation {
+    pub fn point(&self) -> GeoPoint {
+        GeoPoint { latitude: self.latitude, longitude: self.longitude }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct VpnServer {
     pub id: String,
     pub name: String,
     pub location: ServerLocation,
     pub host: String,
-    pub port: u16,
+    pub port: u16,             // Legacy, superseded by port_ranges
+    #[serde(default)]
+    pub port_ranges: HashMap<VpnProtocol, Vec<PortRange>>,
+    #[serde(default)]
+    pub public_keys: HashMap<VpnProtocol, String>,  // Base64, per protocol
     pub load: u8,              // 0-100 percentage
     pub latency: u32,          // milliseconds
     pub bandwidth: u64,        // Mbps
     pub is_premium: bool,
     pub supports_p2p: bool,
     pub supports_streaming: bool,
+    #[serde(default)]
+    pub supports_ipv6: bool,
     pub online: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub former_ids: Vec<String>,   // Ids this server had in earlier catalogs
+    #[serde(skip)]
+    pub distance_km: Option<f64>,  // From the user's location, set by ServerManager
+    #[serde(skip)]
+    pub reliability: Option<f64>,  // Connection success rate, set by ServerManager
+    #[serde(skip)]
+    pub backoff_until: Option<DateTime<Utc>>,  // Skipped after repeated failures
 }
 
 impl VpnServer {
@@ -181,94 +69,335 @@ impl VpnServer {
         self.online && self.load < 95
     }
 
+    /// Still usable, but ranked after every server that is not failing
+    pub fn is_backing_off(&self) -> bool {
+        self.backoff_until.is_some_and(|until| until > Utc::now())
+    }
+
+    /// Score under the balanced profile; see `ScoringProfile` for others
     pub fn score(&self) -> f64 {
-        // Calculate server score based on load, latency, and bandwidth
-        let load_score = (100.0 - self.load as f64) / 100.0;
-        let latency_score = 1.0 / (1.0 + (self.latency as f64 / 100.0));
-        let bandwidth_score = (self.bandwidth as f64 / 1000.0).min(1.0);
-        
-        (load_score * 0.4 + latency_score * 0.4 + bandwidth_score * 0.2) * 100.0
+        ScoringProfile::Balanced.score(self)
     }
+
+    /// Servers without port ranges predate the manifest and are assumed
+    /// to speak every protocol
+    pub fn supports_protocol(&self, protocol: VpnProtocol) -> bool {
+        self.port_ranges.is_empty() || self.port_ranges.contains_key(&protocol)
+    }
+
+    /// A server that advertises no ranges for a protocol predates
+    /// port_ranges and is assumed to accept any port.
+    pub fn accepts_port(&self, protocol: VpnProtocol, port: u16) -> bool {
+        match self.port_ranges.get(&protocol) {
+            Some(ranges) if !ranges.is_empty() => ranges.iter().any(|r| r.contains(port)),
+            _ => true,
+        }
+    }
+
+    /// The port to dial, highest precedence first:
+    /// 1. `ProtocolConfig.port`, if the server accepts it for the protocol
+    /// 2. The protocol's default port, if the server accepts it
+    /// 3. The first port the server advertises for the protocol
+    pub fn resolve_port(&self, config: &ProtocolConfig) -> u16 {
+        let protocol = config.protocol;
+        if self.accepts_port(protocol, config.port) {
+            config.port
+        } else if self.accepts_port(protocol, protocol.default_port()) {
+            protocol.default_port()
+        } else {
+            self.port_ranges[&protocol][0].start
+        }
+    }
+
+    /// Destination ports to hop between; legacy servers only get the
+    /// resolved port, which leaves the source port to move
+    pub fn hop_ranges(&self, config: &ProtocolConfig) -> Vec<PortRange> {
+        match self.port_ranges.get(&config.protocol) {
+            Some(ranges) if !ranges.is_empty() => ranges.clone(),
+            _ => vec![PortRange::single(self.resolve_port(config))],
+        }
+    }
+}
+
+/// What a catalog refresh changed, keyed by server id
+#[derive(Debug, Clone, Default)]
+pub struct ServerDiff {
+    pub added: Vec<VpnServer>,
+    pub removed: Vec<VpnServer>,
+    pub changed: Vec<VpnServer>,  // New version of each changed server
+}
+
+impl ServerDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    pub fn summary(&self) -> String {
+        format!("{} added, {} removed, {} changed",
+            self.added.len(), self.removed.len(), self.changed.len())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    CatalogUpdated(ServerDiff),
+    RefreshFailed(String),
 }
 
+/// A favorite or recent entry. Servers that left the catalog stay listed,
+/// with their last known details, until the user removes them.
+#[derive(Debug, Clone, Copy)]
+pub struct SavedServer<'a> {
+    pub server: &'a VpnServer,
+    pub label: Option<&'a str>,  // Set if the server is a labelled favorite
+    pub removed: bool,
+}
+
+// How many recent servers to remember
+const MAX_RECENT: usize = 10;
+
 pub struct ServerManager {
     servers: HashMap<Country, Vec<VpnServer>>,
-    favorites: Vec<String>,
+    favorites: Vec<Favorite>,
     recent: Vec<String>,
+    removed: HashMap<String, VpnServer>,  // Saved servers no longer in the catalog
+    measured: HashMap<String, f64>,        // Smoothed probe RTT in ms, by server id
+    origin: Option<GeoPoint>,              // Where the user is, if known
+    health: HashMap<String, ServerHealth>,  // Recent connection outcomes by server id
+    saved_path: Option<PathBuf>,           // Where favorites and recents persist
+    balancer: LoadBalancer,                // Off unless configured
+    entitlements: Entitlements,            // Servers outside the plan are never picked
+    events: broadcast::Sender<ServerEvent>,
 }
 
+// Weight of a new probe sample in the moving average
+const LATENCY_SMOOTHING: f64 = 0.3;
+
 impl ServerManager {
+    /// Catalog from the manifest bundled with the client
     pub fn new() -> Self {
+        Self::from_manifest(ServerManifest::bundled())
+    }
+
+    /// Builds the catalog from a verified manifest
+    pub fn from_manifest(manifest: ServerManifest) -> Self {
         let mut manager = Self {
             servers: HashMap::new(),
             favorites: Vec::new(),
             recent: Vec::new(),
+            removed: HashMap::new(),
+            measured: HashMap::new(),
+            origin: None,
+            health: HashMap::new(),
+            saved_path: None,
+            balancer: LoadBalancer::default(),
+            entitlements: Entitlements::default(),
+            events: broadcast::channel(16).0,
         };
-        manager.initialize_servers();
+        manager.set_servers(manifest.servers);
         manager
     }
 
-    fn initialize_servers(&mut self) {
-        // Add servers for each country
-        for country in Country::all() {
-            let servers = self.generate_servers_for_country(&country);
-            self.servers.insert(country, servers);
+    /// Loads the catalog from the loader's source, falling back to its
+    /// last-known-good cache
+    pub async fn load(loader: &mut ManifestLoader) -> crate::Result<Self> {
+        Ok(Self::from_manifest(loader.load().await?))
+    }
+
+    fn set_servers(&mut self, servers: Vec<VpnServer>) {
+        self.servers.clear();
+        for mut server in servers {
+            server.distance_km = self.origin.map(|o| o.distance_km(&server.location.point()));
+            if let Some(health) = self.health.get(&server.id) {
+                server.reliability = Some(health.reliability(Utc::now()));
+                server.backoff_until = health.backoff_until();
+            }
+            self.servers
+                .entry(server.location.country)
+                .or_default()
+                .push(server);
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// Replaces the catalog and publishes what changed
+    pub fn apply_manifest(&mut self, manifest: ServerManifest) -> ServerDiff {
+        let mut old: HashMap<String, VpnServer> = self.servers.drain()
+            .flat_map(|(_, servers)| servers)
+            .map(|s| (s.id.clone(), s))
+            .collect();
+
+        let mut diff = ServerDiff::default();
+        for server in &manifest.servers {
+            match old.remove(&server.id) {
+                None => diff.added.push(server.clone()),
+                Some(mut previous) => {
+                    // Our own measurements are not a catalog change
+                    if self.measured.contains_key(&server.id) {
+                        previous.latency = server.latency;
+                    }
+                    previous.distance_km = server.distance_km;
+                    previous.reliability = server.reliability;
+                    previous.backoff_until = server.backoff_until;
+                    if previous != *server {
+                        diff.changed.push(server.clone());
+                    }
+                }
+            }
+        }
+        diff.removed = old.into_values().collect();
+        diff.removed.sort_by(|a, b| a.id.cmp(&b.id));
+
+        self.set_servers(manifest.servers);
+        self.measured.retain(|id, _| !diff.removed.iter().any(|s| &s.id == id));
+        let measured: Vec<(String, f64)> = self.measured.iter().map(|(id, ms)| (id.clone(), *ms)).collect();
+        for (id, ms) in measured {
+            if let Some(server) = self.find_server_mut(&id) {
+                server.latency = ms.round() as u32;
+            }
+        }
+        for server in &diff.added {
+            self.removed.remove(&server.id);
+        }
+        self.migrate_saved_ids();
+        for server in &diff.removed {
+            if self.is_saved(&server.id) {
+                log::warn!("Saved server {} was removed from the catalog", server.name);
+                self.removed.insert(server.id.clone(), server.clone());
+            }
+        }
+        if !diff.removed.is_empty() {
+            self.save();
+        }
+
+        if !diff.is_empty() {
+            log::info!("Server catalog updated: {}", diff.summary());
+            // No subscribers is fine
+            let _ = self.events.send(ServerEvent::CatalogUpdated(diff.clone()));
         }
+        diff
     }
 
-    fn generate_servers_for_country(&self, country: &Country) -> Vec<VpnServer> {
-        let cities = self.get_cities_for_country(country);
-        cities.iter().enumerate().map(|(idx, city)| {
-            VpnServer {
-                id: format!("{}-{}", country.code(), idx + 1),
-                name: format!("{} #{}", city, idx + 1),
-                location: ServerLocation {
-                    city: city.to_string(),
-                    country: country.clone(),
-                    latitude: 0.0,  // Would be actual coordinates
-                    longitude: 0.0,
-                },
-                host: format!("{}.vpn.server.com", city.to_lowercase().replace(" ", "-")),
-                port: 443,
-                load: (idx * 7) as u8 % 90,
-                latency: (idx * 13 + 20) as u32 % 150,
-                bandwidth: 1000 + (idx as u64 * 100),
-                is_premium: idx % 3 == 0,
-                supports_p2p: idx % 2 == 0,
-                supports_streaming: true,
-                online: true,
+    /// Refreshes the shared catalog every `interval`, skipping unchanged
+    /// downloads through the loader's ETag/Last-Modified validators
+    pub fn spawn_refresher(
+        manager: Arc<RwLock<ServerManager>>,
+        mut loader: ManifestLoader,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            // The first tick fires at once; the catalog was just loaded
+            ticker.tick().await;
+
+            loop {
+                ticker.tick().await;
+                match loader.refresh().await {
+                    Ok(Some(manifest)) => {
+                        manager.write().await.apply_manifest(manifest);
+                    }
+                    Ok(None) => log::debug!("Server catalog unchanged"),
+                    Err(e) => {
+                        log::warn!("Server catalog refresh failed: {}", e);
+                        let _ = manager.read().await.events.send(ServerEvent::RefreshFailed(e.to_string()));
+                    }
+                }
             }
-        }).collect()
-    }
-
-    fn get_cities_for_country(&self, country: &Country) -> Vec<String> {
-        match country {
-            Country::UnitedStates => vec!["New York", "Los Angeles", "Chicago", "Miami", "Seattle"],
-            Country::UnitedKingdom => vec!["London", "Manchester", "Edinburgh"],
-            Country::Canada => vec!["Toronto", "Montreal", "Vancouver"],
-            Country::Germany => vec!["Frankfurt", "Berlin", "Munich"],
-            Country::France => vec!["Paris", "Marseille", "Lyon"],
-            Country::Netherlands => vec!["Amsterdam", "Rotterdam"],
-            Country::Switzerland => vec!["Zurich", "Geneva"],
-            Country::Sweden => vec!["Stockholm", "Gothenburg"],
-            Country::Japan => vec!["Tokyo", "Osaka", "Kyoto"],
-            Country::Singapore => vec!["Singapore"],
-            Country::Australia => vec!["Sydney", "Melbourne", "Brisbane"],
-            Country::Brazil => vec!["Sao Paulo", "Rio de Janeiro"],
-            Country::India => vec!["Mumbai", "Delhi", "Bangalore"],
-            Country::SouthKorea => vec!["Seoul", "Busan"],
-            Country::Spain => vec!["Madrid", "Barcelona"],
-            Country::Italy => vec!["Rome", "Milan"],
-            Country::Norway => vec!["Oslo"],
-            Country::Denmark => vec!["Copenhagen"],
-            Country::Finland => vec!["Helsinki"],
-            Country::Poland => vec!["Warsaw", "Krakow"],
-            Country::Turkey => vec!["Istanbul", "Ankara"],
-            Country::UnitedArabEmirates => vec!["Dubai", "Abu Dhabi"],
-            Country::SouthAfrica => vec!["Johannesburg", "Cape Town"],
-            Country::Mexico => vec!["Mexico City", "Guadalajara"],
-            Country::Argentina => vec!["Buenos Aires", "Cordoba"],
-        }.iter().map(|s| s.to_string()).collect()
+        })
+    }
+
+    /// Ranks servers by distance from `origin` from now on. None forgets
+    /// the location and ranking falls back to the server metrics alone.
+    pub fn set_origin(&mut self, origin: Option<GeoPoint>) {
+        self.origin = origin;
+        for server in self.servers.values_mut().flatten() {
+            server.distance_km = origin.map(|o| o.distance_km(&server.location.point()));
+        }
+    }
+
+    pub fn origin(&self) -> Option<GeoPoint> {
+        self.origin
+    }
+
+    /// Available servers, closest to `origin` first
+    pub fn get_nearest_servers(&self, origin: &GeoPoint) -> Vec<&VpnServer> {
+        let mut servers: Vec<(f64, &VpnServer)> = self.get_all_servers()
+            .into_iter()
+            .filter(|s| s.is_available())
+            .map(|s| (origin.distance_km(&s.location.point()), s))
+            .collect();
+        servers.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        servers.into_iter().map(|(_, s)| s).collect()
+    }
+
+    pub fn get_nearest_server(&self, origin: &GeoPoint) -> Option<&VpnServer> {
+        self.get_nearest_servers(origin).into_iter().next()
+    }
+
+    /// Adds to the server's health history, which feeds the reliability
+    /// factor in scores and puts servers that keep failing on hold
+    pub fn record_health(&mut self, server_id: &str, event: HealthEventKind) {
+        let now = Utc::now();
+        let health = self.health.entry(server_id.to_string()).or_default();
+        health.record(event, now);
+        let (reliability, backoff_until) = (health.reliability(now), health.backoff_until());
+
+        if let Some(until) = backoff_until {
+            log::warn!("Server {} keeps failing, skipping it until {}", server_id, until);
+        }
+        if let Some(server) = self.find_server_mut(server_id) {
+            server.reliability = Some(reliability);
+            server.backoff_until = backoff_until;
+        }
+    }
+
+    pub fn get_health(&self, server_id: &str) -> Option<&ServerHealth> {
+        self.health.get(server_id)
+    }
+
+    /// Where to probe each online server for the given protocol
+    pub fn probe_targets(&self, config: &ProtocolConfig) -> Vec<ProbeTarget> {
+        self.get_all_servers()
+            .into_iter()
+            .filter(|s| s.online && s.supports_protocol(config.protocol))
+            .map(|s| ProbeTarget {
+                server_id: s.id.clone(),
+                host: s.host.clone(),
+                port: s.resolve_port(config),
+            })
+            .collect()
+    }
+
+    /// Folds probe results into `latency`, which `score()` ranks on. The
+    /// first sample replaces the catalog's figure; later ones are smoothed.
+    pub fn record_latencies(&mut self, results: &[ProbeResult]) -> usize {
+        let mut updated = 0;
+        for result in results {
+            let Some(rtt) = result.rtt else { continue };
+            let sample = rtt.as_secs_f64() * 1000.0;
+            let smoothed = match self.measured.get(&result.server_id) {
+                Some(previous) => LATENCY_SMOOTHING * sample + (1.0 - LATENCY_SMOOTHING) * previous,
+                None => sample,
+            };
+
+            if let Some(server) = self.find_server_mut(&result.server_id) {
+                server.latency = smoothed.round() as u32;
+                self.measured.insert(result.server_id.clone(), smoothed);
+                updated += 1;
+            }
+        }
+        updated
+    }
+
+    /// Countries with at least one server, by name
+    pub fn get_countries(&self) -> Vec<Country> {
+        let mut countries: Vec<Country> = self.servers.keys().copied().collect();
+        countries.sort_by_key(|c| c.name());
+        countries
     }
 
     pub fn get_servers_by_country(&self, country: &Country) -> Option<&Vec<VpnServer>> {
@@ -279,55 +408,458 @@ impl ServerManager {
         self.servers.values().flatten().collect()
     }
 
-    pub fn get_fastest_server(&self) -> Option<&VpnServer> {
-        self.get_all_servers()
-            .into_iter()
-            .filter(|s| s.is_available())
-            .max_by(|a, b| a.score().partial_cmp(&b.score()).unwrap())
+    pub fn query(&self, query: &ServerQuery) -> ServerPage<'_> {
+        query.run(self.servers.values().flatten())
     }
 
-    pub fn get_fastest_in_country(&self, country: &Country) -> Option<&VpnServer> {
-        self.servers.get(country)
-            .and_then(|servers| {
-                servers.iter()
-                    .filter(|s| s.is_available())
-                    .max_by(|a, b| a.score().partial_cmp(&b.score()).unwrap())
-            })
+    /// First match of `query` in its sort order, ignoring pagination and
+    /// servers the subscription does not cover
+    pub fn find(&self, query: &ServerQuery) -> Option<&VpnServer> {
+        self.servers.values()
+            .flatten()
+            .filter(|s| query.matches(s) && self.entitlements.permits_server(s))
+            .min_by(|a, b| query.compare(a, b))
+    }
+
+    /// Like `find`, but spread over near-equal servers by the load balancer
+    pub fn pick(&self, query: &ServerQuery) -> Option<&VpnServer> {
+        let mut ranked: Vec<&VpnServer> = self.servers.values()
+            .flatten()
+            .filter(|s| query.matches(s) && self.entitlements.permits_server(s))
+            .collect();
+        ranked.sort_by(|a, b| query.compare(a, b));
+        self.balancer.choose(&ranked, query.scoring())
+    }
+
+    pub fn set_balancer(&mut self, balancer: LoadBalancer) {
+        self.balancer = balancer;
+    }
+
+    /// Listings still show every server; only selection is restricted
+    pub fn set_entitlements(&mut self, entitlements: Entitlements) {
+        self.entitlements = entitlements;
+    }
+
+    pub fn get_fastest_server(&self, strategy: impl ScoringStrategy + 'static) -> Option<&VpnServer> {
+        self.pick(&ServerQuery::new().with_scoring(strategy))
+    }
+
+    pub fn get_fastest_in_country(
+        &self,
+        country: &Country,
+        strategy: impl ScoringStrategy + 'static,
+    ) -> Option<&VpnServer> {
+        self.pick(&ServerQuery::new().with_country(*country).with_scoring(strategy))
+    }
+
+    /// Best entry/exit pair by combined score. Hops must be in different
+    /// countries so no single jurisdiction sees both ends of the chain.
+    pub fn get_best_chain(&self, entry: Option<&Country>, exit: Option<&Country>) -> Option<ServerChain> {
+        let candidates = |country: Option<&Country>| -> Vec<&VpnServer> {
+            self.get_all_servers()
+                .into_iter()
+                .filter(|s| s.is_available() && self.entitlements.permits_server(s))
+                .filter(|s| country.is_none_or(|c| &s.location.country == c))
+                .collect()
+        };
+        let entries = candidates(entry);
+        let exits = candidates(exit);
+
+        entries.iter()
+            .flat_map(|e| exits.iter().map(move |x| (*e, *x)))
+            .filter(|(e, x)| e.location.country != x.location.country)
+            .filter_map(|(e, x)| ServerChain::new(vec![e.clone(), x.clone()]).ok())
+            .max_by(|a, b| a.combined_score().partial_cmp(&b.combined_score()).unwrap())
+    }
+
+    /// Keeps favorites and recents in `path` from now on, starting from
+    /// what is already saved there
+    pub fn persist_to(&mut self, path: PathBuf) -> crate::Result<()> {
+        if let Some(saved) = SavedServers::load(&path)? {
+            self.favorites = saved.favorites;
+            self.recent = saved.recent;
+            self.removed = saved.removed.into_iter().map(|s| (s.id.clone(), s)).collect();
+        }
+        self.saved_path = Some(path);
+        self.migrate_saved_ids();
+        self.forget_removed();
+        Ok(())
     }
 
     pub fn add_favorite(&mut self, server_id: String) {
-        if !self.favorites.contains(&server_id) {
-            self.favorites.push(server_id);
+        if !self.favorites.iter().any(|f| f.server_id == server_id) {
+            self.favorites.push(Favorite::new(server_id));
+            self.save();
         }
     }
 
     pub fn remove_favorite(&mut self, server_id: &str) {
-        self.favorites.retain(|id| id != server_id);
+        self.favorites.retain(|f| f.server_id != server_id);
+        self.forget_removed();
+        self.save();
+    }
+
+    /// Moves a favorite to `index` in the list, clamped to the end
+    pub fn move_favorite(&mut self, server_id: &str, index: usize) -> bool {
+        let Some(from) = self.favorites.iter().position(|f| f.server_id == server_id) else {
+            return false;
+        };
+        let favorite = self.favorites.remove(from);
+        self.favorites.insert(index.min(self.favorites.len()), favorite);
+        self.save();
+        true
+    }
+
+    /// A blank label clears it
+    pub fn set_favorite_label(&mut self, server_id: &str, label: Option<String>) -> bool {
+        let Some(favorite) = self.favorites.iter_mut().find(|f| f.server_id == server_id) else {
+            return false;
+        };
+        favorite.label = label.map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+        self.save();
+        true
     }
 
     pub fn add_recent(&mut self, server_id: String) {
         self.recent.retain(|id| id != &server_id);
         self.recent.insert(0, server_id);
-        if self.recent.len() > 10 {
-            self.recent.truncate(10);
-        }
+        self.recent.truncate(MAX_RECENT);
+        self.forget_removed();
+        self.save();
     }
 
-    pub fn get_favorites(&self) -> Vec<&VpnServer> {
+    pub fn get_favorites(&self) -> Vec<SavedServer<'_>> {
         self.favorites.iter()
-            .filter_map(|id| self.find_server_by_id(id))
+            .filter_map(|f| self.find_saved(&f.server_id))
             .collect()
     }
 
-    pub fn get_recent(&self) -> Vec<&VpnServer> {
+    pub fn get_recent(&self) -> Vec<SavedServer<'_>> {
         self.recent.iter()
-            .filter_map(|id| self.find_server_by_id(id))
+            .filter_map(|id| self.find_saved(id))
             .collect()
     }
 
+    /// The most recent server that can still be connected to
+    pub fn get_last_server(&self) -> Option<&VpnServer> {
+        self.get_recent()
+            .into_iter()
+            .find(|saved| !saved.removed && saved.server.is_available())
+            .map(|saved| saved.server)
+    }
+
+    fn find_saved(&self, id: &str) -> Option<SavedServer<'_>> {
+        let label = self.favorites.iter()
+            .find(|f| f.server_id == id)
+            .and_then(|f| f.label.as_deref());
+        match self.find_server_by_id(id) {
+            Some(server) => Some(SavedServer { server, label, removed: false }),
+            None => self.removed.get(id).map(|server| SavedServer { server, label, removed: true }),
+        }
+    }
+
+    fn is_saved(&self, id: &str) -> bool {
+        self.favorites.iter().any(|f| f.server_id == id) || self.recent.iter().any(|r| r == id)
+    }
+
+    // Drop details of removed servers nothing refers to any more
+    fn forget_removed(&mut self) {
+        let saved: Vec<String> = self.removed.keys().filter(|id| self.is_saved(id)).cloned().collect();
+        self.removed.retain(|id, _| saved.contains(id));
+    }
+
+    // Follow servers whose id changed between catalogs
+    fn migrate_saved_ids(&mut self) {
+        let renames: HashMap<String, String> = self.get_all_servers()
+            .into_iter()
+            .flat_map(|s| s.former_ids.iter().map(move |old| (old.clone(), s.id.clone())))
+            .filter(|(old, _)| self.find_server_by_id(old).is_none())
+            .collect();
+        if renames.is_empty() {
+            return;
+        }
+
+        let mut saved = self.saved_servers();
+        if saved.migrate(&renames) {
+            self.favorites = saved.favorites;
+            self.recent = saved.recent;
+            self.forget_removed();
+            self.save();
+        }
+    }
+
+    fn saved_servers(&self) -> SavedServers {
+        let mut removed: Vec<VpnServer> = self.removed.values().cloned().collect();
+        removed.sort_by(|a, b| a.id.cmp(&b.id));
+        SavedServers {
+            favorites: self.favorites.clone(),
+            recent: self.recent.clone(),
+            removed,
+        }
+    }
+
+    // Failing to persist should not stop the user connecting
+    fn save(&self) {
+        let Some(path) = &self.saved_path else { return };
+        if let Err(e) = self.saved_servers().save(path) {
+            log::warn!("{}", e);
+        }
+    }
+
     fn find_server_by_id(&self, id: &str) -> Option<&VpnServer> {
         self.servers.values()
             .flatten()
             .find(|s| s.id == id)
     }
+
+    fn find_server_mut(&mut self, id: &str) -> Option<&mut VpnServer> {
+        self.servers.values_mut()
+            .flatten()
+            .find(|s| s.id == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SubscriptionTier;
+    use crate::manifest::ManifestSource;
+    use crate::scoring::ScoringWeights;
+    use ed25519_dalek::SigningKey;
+
+    #[test]
+    fn test_apply_manifest_diff() {
+        let mut manager = ServerManager::new();
+        manager.add_favorite("CH-1".to_string());
+        manager.add_favorite("SE-1".to_string());
+        manager.add_recent("CH-1".to_string());
+        let mut events = manager.subscribe();
+
+        let mut manifest = ServerManifest::bundled();
+        manifest.servers.retain(|s| s.id != "CH-1");
+        manifest.servers.iter_mut().find(|s| s.id == "SE-1").unwrap().load = 80;
+        let mut added = manifest.servers[0].clone();
+        added.id = "SE-9".to_string();
+        manifest.servers.push(added);
+
+        let diff = manager.apply_manifest(manifest);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.changed[0].id, "SE-1");
+        assert!(matches!(events.try_recv(), Ok(ServerEvent::CatalogUpdated(d)) if d.removed[0].id == "CH-1"));
+
+        // The removed favorite is still listed, flagged
+        let favorites = manager.get_favorites();
+        assert_eq!(favorites.len(), 2);
+        assert!(favorites[0].removed && favorites[0].server.id == "CH-1");
+        assert!(!favorites[1].removed && favorites[1].server.load == 80);
+        assert!(manager.get_recent()[0].removed);
+
+        manager.remove_favorite("CH-1");
+        assert_eq!(manager.get_favorites().len(), 1);
+        assert!(manager.get_recent()[0].removed);
+
+        // Coming back clears the flag
+        manager.apply_manifest(ServerManifest::bundled());
+        assert!(!manager.get_recent()[0].removed);
+    }
+
+    #[test]
+    fn test_saved_servers_persist_and_follow_renames() {
+        let path = std::env::temp_dir().join(format!("vpn-saved-servers-{}.json", rand::random::<u32>()));
+        let mut manager = ServerManager::new();
+        manager.persist_to(path.clone()).unwrap();
+        manager.add_favorite("CH-1".to_string());
+        manager.add_favorite("SE-1".to_string());
+        manager.add_favorite("JP-1".to_string());
+        assert!(manager.move_favorite("JP-1", 0));
+        assert!(manager.set_favorite_label("SE-1", Some("Work".to_string())));
+        assert!(!manager.set_favorite_label("XX-1", None));
+        manager.add_recent("SE-1".to_string());
+        manager.add_recent("CH-1".to_string());
+
+        // A new session sees the same lists
+        let mut restored = ServerManager::new();
+        restored.persist_to(path.clone()).unwrap();
+        let ids: Vec<&str> = restored.get_favorites().iter().map(|f| f.server.id.as_str()).collect();
+        assert_eq!(ids, ["JP-1", "CH-1", "SE-1"]);
+        assert_eq!(restored.get_favorites()[2].label, Some("Work"));
+        assert_eq!(restored.get_last_server().unwrap().id, "CH-1");
+
+        // CH-1 is renumbered in the next catalog
+        let mut manifest = ServerManifest::bundled();
+        let zurich = manifest.servers.iter_mut().find(|s| s.id == "CH-1").unwrap();
+        zurich.id = "CH-ZRH-1".to_string();
+        zurich.former_ids = vec!["CH-1".to_string()];
+        restored.apply_manifest(manifest);
+
+        assert_eq!(restored.get_favorites()[1].server.id, "CH-ZRH-1");
+        assert!(!restored.get_favorites()[1].removed);
+        assert_eq!(restored.get_last_server().unwrap().id, "CH-ZRH-1");
+        let saved = SavedServers::load(&path).unwrap().unwrap();
+        assert_eq!(saved.recent, vec!["CH-ZRH-1".to_string(), "SE-1".to_string()]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_measured_latency_drives_ranking() {
+        let mut manager = ServerManager::new();
+        let targets = manager.probe_targets(&ProtocolConfig::default());
+        assert_eq!(targets.len(), manager.get_all_servers().len());
+        assert!(targets.iter().all(|t| t.port == 51820));
+
+        let probe = |id: &str, ms: u64| ProbeResult {
+            server_id: id.to_string(),
+            rtt: Some(Duration::from_millis(ms)),
+        };
+        assert_eq!(manager.record_latencies(&[probe("JP-3", 1), probe("XX-1", 1)]), 1);
+        assert_eq!(manager.get_fastest_server(ScoringProfile::Balanced).unwrap().id, "JP-3");
+
+        // Smoothing: one bad sample moves the estimate, but not all the way
+        manager.record_latencies(&[probe("JP-3", 201)]);
+        let latency = manager.find_server_by_id("JP-3").unwrap().latency;
+        assert_eq!(latency, 61);
+
+        // A catalog refresh keeps measurements and does not report them as changes
+        let diff = manager.apply_manifest(ServerManifest::bundled());
+        assert!(diff.is_empty());
+        assert_eq!(manager.find_server_by_id("JP-3").unwrap().latency, 61);
+    }
+
+    #[tokio::test]
+    async fn test_probe_targets_answer_the_tunnel_transport() {
+        use crate::probe::LatencyProber;
+        use tokio::net::{TcpListener, UdpSocket};
+
+        let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let udp_port = echo.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((n, peer)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(&buf[..n], peer).await;
+            }
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { while listener.accept().await.is_ok() {} });
+
+        let mut manifest = ServerManifest::bundled();
+        manifest.servers.truncate(1);
+        manifest.servers[0].host = "127.0.0.1".to_string();
+        manifest.servers[0].port_ranges.clear();
+        let mut manager = ServerManager::from_manifest(manifest);
+
+        // The same prober main uses, over UDP and then over TCP
+        for (use_tcp, port) in [(false, udp_port), (true, tcp_port)] {
+            let config = ProtocolConfig { port, use_tcp, ..ProtocolConfig::default() };
+            let targets = manager.probe_targets(&config);
+            let results = LatencyProber::for_protocol(&config)
+                .with_timeout(Duration::from_millis(500))
+                .with_attempts(1)
+                .probe_all(targets)
+                .await;
+            assert_eq!(manager.record_latencies(&results), 1, "use_tcp = {}", use_tcp);
+        }
+    }
+
+    #[test]
+    fn test_distance_breaks_ties() {
+        let mut manager = ServerManager::new();
+        let zurich = GeoPoint::new(47.3769, 8.5417).unwrap();
+        let tokyo = GeoPoint::new(35.6762, 139.6503).unwrap();
+
+        // Free plans are never handed a premium server
+        manager.set_origin(Some(zurich));
+        assert!(!manager.get_fastest_server(ScoringProfile::Balanced).unwrap().is_premium);
+        assert!(manager.get_best_chain(None, None).unwrap().hops().iter().all(|h| !h.is_premium));
+        manager.set_entitlements(Entitlements::for_tier(SubscriptionTier::Premium));
+
+        assert_eq!(manager.get_nearest_server(&zurich).unwrap().id, "CH-1");
+        let nearest = manager.get_nearest_servers(&tokyo);
+        assert_eq!(nearest[0].id, "JP-1");
+        assert!(nearest[1].location.country == Country::from_alpha2("JP").unwrap());
+
+        // Top-tier servers all score the same until distance is known
+        manager.set_origin(Some(tokyo));
+        assert_eq!(manager.get_fastest_server(ScoringProfile::Balanced).unwrap().id, "JP-1");
+        manager.set_origin(Some(zurich));
+        assert_eq!(manager.get_fastest_server(ScoringProfile::Balanced).unwrap().id, "CH-1");
+
+        // But distance never beats a measurably better server
+        let probe = ProbeResult { server_id: "AU-3".to_string(), rtt: Some(Duration::from_millis(1)) };
+        manager.record_latencies(&[probe]);
+        assert_eq!(manager.get_fastest_server(ScoringProfile::Balanced).unwrap().id, "AU-3");
+
+        // Refreshing the catalog keeps distances and reports no change
+        assert!(manager.apply_manifest(ServerManifest::bundled()).is_empty());
+        assert!(manager.find_server_by_id("CH-1").unwrap().distance_km.unwrap() < 1.0);
+    }
+
+    #[test]
+    fn test_failures_lower_reliability() {
+        let mut manager = ServerManager::new();
+        let best = manager.get_fastest_server(ScoringProfile::Balanced).unwrap().id.clone();
+
+        manager.record_health(&best, HealthEventKind::ConnectFailed);
+        manager.record_health(&best, HealthEventKind::Dropped { after: Duration::from_secs(30) });
+        let reliability = manager.find_server_by_id(&best).unwrap().reliability.unwrap();
+        assert!((reliability - 1.0 / 3.0).abs() < 0.01);
+        assert_ne!(manager.get_fastest_server(ScoringProfile::Balanced).unwrap().id, best);
+
+        // Profiles that ignore reliability still pick it
+        let metrics_only = ScoringWeights { reliability: 0.0, ..ScoringWeights::default() };
+        assert_eq!(manager.get_fastest_server(ScoringProfile::Custom(metrics_only)).unwrap().id, best);
+
+        // Until it fails once more and is put on hold
+        manager.record_health(&best, HealthEventKind::ConnectFailed);
+        assert!(manager.find_server_by_id(&best).unwrap().is_backing_off());
+        assert_ne!(manager.get_fastest_server(ScoringProfile::Custom(metrics_only)).unwrap().id, best);
+        assert_eq!(manager.get_health(&best).unwrap().consecutive_failures(), 3);
+
+        // Streaming never lands on a server without streaming support
+        let streaming = manager.get_fastest_server(ScoringProfile::Streaming).unwrap();
+        assert!(streaming.supports_streaming);
+
+        // History survives a catalog refresh
+        manager.apply_manifest(ServerManifest::bundled());
+        assert!(manager.find_server_by_id(&best).unwrap().is_backing_off());
+
+        // A successful connect lifts the hold
+        let handshake = Duration::from_millis(150);
+        manager.record_health(&best, HealthEventKind::Connected { handshake });
+        assert!(!manager.find_server_by_id(&best).unwrap().is_backing_off());
+        assert_eq!(manager.get_health(&best).unwrap().average_handshake(), Some(handshake));
+    }
+
+    #[tokio::test]
+    async fn test_refresher_publishes_diff() {
+        let dir = std::env::temp_dir().join(format!("vpn-refresh-{}", rand::random::<u32>()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("servers.json");
+        let key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+        let mut manifest = ServerManifest::bundled();
+        std::fs::write(&path, manifest.sign(&key).unwrap().to_json().unwrap()).unwrap();
+
+        let mut loader = ManifestLoader::new(ManifestSource::File(path.clone()))
+            .with_pinned_key(key.verifying_key());
+        let manager = Arc::new(RwLock::new(ServerManager::load(&mut loader).await.unwrap()));
+        let mut events = manager.read().await.subscribe();
+        let refresher = ServerManager::spawn_refresher(manager.clone(), loader, Duration::from_millis(50));
+
+        manifest.servers.retain(|s| s.location.country != Country::from_alpha2("JP").unwrap());
+        std::fs::write(&path, manifest.sign(&key).unwrap().to_json().unwrap()).unwrap();
+
+        let event = tokio::time::timeout(Duration::from_secs(5), events.recv()).await.unwrap().unwrap();
+        match event {
+            ServerEvent::CatalogUpdated(diff) => {
+                assert_eq!(diff.removed.len(), 3);
+                assert!(diff.added.is_empty() && diff.changed.is_empty());
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+        assert!(manager.read().await.get_servers_by_country(&Country::from_alpha2("JP").unwrap()).is_none());
+
+        refresher.abort();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::net::IpAddr;
+use crate::net::IpNetwork;
 use crate::{Result, VpnError};
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
@@ -10,6 +12,7 @@ pub enum SplitTunnelMode {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SplitTunnelConfig {
     pub mode: SplitTunnelMode,
     pub apps: HashSet<String>,
@@ -50,8 +53,12 @@ impl SplitTunnel {
 
         log::info!("Enabling split tunneling (mode: {:?})", self.config.mode);
         
-        self.apply_routing_rules()?;
+        // Active first, the rules are decided by the live routing checks
         self.active = true;
+        if let Err(e) = self.apply_routing_rules() {
+            self.active = false;
+            return Err(e);
+        }
         
         log::info!("Split tunneling enabled successfully");
         Ok(())
@@ -71,6 +78,30 @@ impl SplitTunnel {
         self.active
     }
 
+    pub fn config(&self) -> &SplitTunnelConfig {
+        &self.config
+    }
+
+    /// Swaps in new routes. While active they are re-applied at once, and
+    /// a mode of Disabled turns split tunneling off. If the new routes
+    /// cannot be applied the old ones stay.
+    pub fn update_config(&mut self, config: SplitTunnelConfig) -> Result<()> {
+        let previous = std::mem::replace(&mut self.config, config);
+        if !self.active {
+            return Ok(());
+        }
+        if self.config.mode == SplitTunnelMode::Disabled {
+            return self.disable();
+        }
+
+        if let Err(e) = self.remove_routing_rules().and_then(|_| self.apply_routing_rules()) {
+            self.config = previous;
+            self.apply_routing_rules()?;
+            return Err(e);
+        }
+        Ok(())
+    }
+
     pub fn set_mode(&mut self, mode: SplitTunnelMode) {
         self.config.mode = mode;
     }
@@ -144,9 +175,16 @@ impl SplitTunnel {
             log::info!("Routing domains: {:?}", self.config.domains);
         }
         
-        // Route IP ranges
-        if !self.config.ip_ranges.is_empty() {
-            log::info!("Routing IP ranges: {:?}", self.config.ip_ranges);
+        // Route IP ranges, IPv4 and IPv6 get separate routing tables
+        let ranges = self.config.ip_ranges.iter()
+            .map(|r| r.parse::<IpNetwork>())
+            .collect::<Result<Vec<_>>>()?;
+        let (v6, v4): (Vec<&IpNetwork>, Vec<&IpNetwork>) = ranges.iter().partition(|n| n.is_ipv6());
+        for (family, networks) in [("IPv4", v4), ("IPv6", v6)] {
+            for network in networks {
+                let route = if self.should_route_ip_through_vpn(network.addr()) { "VPN" } else { "direct" };
+                log::info!("Routing {} range {} via {}", family, network, route);
+            }
         }
         
         Ok(())
@@ -175,6 +213,21 @@ impl SplitTunnel {
         }
     }
 
+    pub fn should_route_ip_through_vpn(&self, ip: IpAddr) -> bool {
+        if !self.active || self.config.mode == SplitTunnelMode::Disabled {
+            return true;
+        }
+
+        let matched = self.config.ip_ranges.iter()
+            .filter_map(|r| r.parse::<IpNetwork>().ok())
+            .any(|n| n.contains(ip));
+        match self.config.mode {
+            SplitTunnelMode::IncludeOnly => matched,
+            SplitTunnelMode::ExcludeOnly => !matched,
+            SplitTunnelMode::Disabled => true,
+        }
+    }
+
     pub fn get_preset_configs() -> Vec<PresetConfig> {
         vec![
             PresetConfig {
@@ -269,6 +322,24 @@ mod tests {
         assert!(split_tunnel.should_route_through_vpn("Chrome"));
     }
 
+    #[test]
+    fn test_split_tunnel_ip_ranges() {
+        let mut config = SplitTunnelConfig::default();
+        config.mode = SplitTunnelMode::ExcludeOnly;
+        
+        let mut split_tunnel = SplitTunnel::new(config);
+        split_tunnel.add_ip_range("203.0.113.0/24".to_string());
+        split_tunnel.add_ip_range("2001:db8:abcd::/48".to_string());
+        split_tunnel.enable().unwrap();
+        
+        assert!(!split_tunnel.should_route_ip_through_vpn("203.0.113.9".parse().unwrap()));
+        assert!(!split_tunnel.should_route_ip_through_vpn("2001:db8:abcd::1".parse().unwrap()));
+        assert!(split_tunnel.should_route_ip_through_vpn("2001:db8:ffff::1".parse().unwrap()));
+        
+        split_tunnel.add_ip_range("2001:db8::/129".to_string());
+        assert!(split_tunnel.enable().is_err());
+    }
+
     #[test]
     fn test_preset_configs() {
         let presets = SplitTunnel::get_preset_configs();
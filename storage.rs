@@ -0,0 +1,139 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Replaces `path` with `contents` so readers see the old file or the new
+/// one, never a partial write. The data goes to a temporary file in the same
+/// directory, is flushed to disk, then renamed over the target. On Unix the
+/// file is readable by its owner only (0600).
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    let tmp = dir.join(format!(".{}.{}.tmp", name, rand::random::<u32>()));
+    let result = create_private(&tmp)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    sync_dir(dir)
+}
+
+/// Like `write_atomic`, but first keeps the current file as `<name>.bak.1`,
+/// moving older copies along and dropping the one past `keep`. Writing the
+/// same contents again does not use up a backup.
+pub(crate) fn write_with_backups(path: &Path, contents: &[u8], keep: usize) -> std::io::Result<()> {
+    match fs::read(path) {
+        Ok(current) if current != contents && keep > 0 => {
+            let backups = backup_paths(path, keep);
+            for idx in (1..backups.len()).rev() {
+                if backups[idx - 1].exists() {
+                    fs::rename(&backups[idx - 1], &backups[idx])?;
+                }
+            }
+            write_atomic(&backups[0], &current)?;
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    write_atomic(path, contents)
+}
+
+/// Backups made by `write_with_backups`, newest first, whether or not they
+/// exist
+pub(crate) fn backup_paths(path: &Path, keep: usize) -> Vec<PathBuf> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    (1..=keep).map(|idx| path.with_file_name(format!("{}.bak.{}", name, idx))).collect()
+}
+
+/// For when `path` itself cannot be read or parsed: the newest of its
+/// backups that `load` accepts, given the backup's path and contents.
+/// `error` is what was wrong with `path`, for the log.
+pub(crate) fn load_newest_backup<T, E>(
+    path: &Path,
+    keep: usize,
+    error: &dyn std::fmt::Display,
+    load: impl Fn(&Path, &str) -> Result<T, E>,
+) -> Option<T> {
+    backup_paths(path, keep).iter().find_map(|backup| {
+        let loaded = load(backup, &fs::read_to_string(backup).ok()?).ok()?;
+        log::warn!("{} is unusable ({}); using backup {}", path.display(), error, backup.display());
+        Some(loaded)
+    })
+}
+
+#[cfg(unix)]
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+// Makes the rename itself survive a crash
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let dir = std::env::temp_dir().join(format!("vpn-storage-{}", rand::random::<u32>()));
+        let path = dir.join("nested").join("data.json");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+
+        // No temporary files are left behind
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_backups_rotate() {
+        let dir = std::env::temp_dir().join(format!("vpn-storage-{}", rand::random::<u32>()));
+        let path = dir.join("config.json");
+        for contents in ["1", "2", "3", "4", "4"] {
+            write_with_backups(&path, contents.as_bytes(), 2).unwrap();
+        }
+
+        let backups = backup_paths(&path, 3);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "4");
+        assert_eq!(fs::read_to_string(&backups[0]).unwrap(), "3");
+        assert_eq!(fs::read_to_string(&backups[1]).unwrap(), "2");
+        assert!(!backups[2].exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            for file in [&path, &backups[0]] {
+                assert_eq!(fs::metadata(file).unwrap().permissions().mode() & 0o777, 0o600);
+            }
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
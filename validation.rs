@@ -0,0 +1,122 @@
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,  // Works, but probably not what the user wants
+    Error,    // The config is refused
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub path: String,        // JSON path into the config, e.g. "kill_switch.allowed_ips[2]"
+    pub severity: Severity,
+    pub code: &'static str,  // Stable identifier for tooling, e.g. "invalid_cidr"
+    pub message: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "{}[{}] {}: {}", severity, self.code, self.path, self.message)
+    }
+}
+
+/// Every problem found in a config, rather than just the first
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct ValidationReport {
+    issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn error(&mut self, path: impl Into<String>, code: &'static str, message: impl Into<String>) {
+        self.push(path.into(), Severity::Error, code, message.into());
+    }
+
+    pub fn warning(&mut self, path: impl Into<String>, code: &'static str, message: impl Into<String>) {
+        self.push(path.into(), Severity::Warning, code, message.into());
+    }
+
+    /// Records a failed check from one of the per-module validators
+    pub fn check(&mut self, path: impl Into<String>, code: &'static str, result: crate::Result<()>) {
+        if let Err(e) = result {
+            self.error(path, code, e.to_string());
+        }
+    }
+
+    fn push(&mut self, path: String, severity: Severity, code: &'static str, message: String) {
+        self.issues.push(ValidationIssue { path, severity, code, message });
+    }
+
+    pub fn issues(&self) -> &[ValidationIssue] {
+        &self.issues
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|i| i.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|i| i.severity == Severity::Warning)
+    }
+
+    /// No errors; warnings are allowed
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn find(&self, path: &str) -> Option<&ValidationIssue> {
+        self.issues.iter().find(|i| i.path == path)
+    }
+}
+
+/// One issue per line, errors first
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut issues: Vec<&ValidationIssue> = self.issues.iter().collect();
+        issues.sort_by_key(|issue| std::cmp::Reverse(issue.severity));
+        for (idx, issue) in issues.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VpnError;
+
+    #[test]
+    fn test_report_collects_everything() {
+        let mut report = ValidationReport::new();
+        assert!(report.is_valid());
+
+        report.warning("dns_mode", "dns_leak_risk", "System DNS can leak");
+        assert!(report.is_valid());
+
+        report.error("kill_switch.allowed_ips[2]", "invalid_cidr", "Not a CIDR: 10.0.0.300/8");
+        report.check("mtu", "out_of_range", Err(VpnError::ConfigError("too small".to_string())));
+        report.check("port", "invalid_port", Ok(()));
+
+        assert!(!report.is_valid());
+        assert_eq!(report.errors().count(), 2);
+        assert_eq!(report.warnings().count(), 1);
+        assert_eq!(report.find("kill_switch.allowed_ips[2]").unwrap().code, "invalid_cidr");
+        assert_eq!(
+            report.to_string().lines().next().unwrap(),
+            "error[invalid_cidr] kill_switch.allowed_ips[2]: Not a CIDR: 10.0.0.300/8"
+        );
+        assert!(report.to_string().lines().last().unwrap().starts_with("warning[dns_leak_risk]"));
+    }
+}